@@ -0,0 +1,8 @@
+-- Follows that we have seen come through EventSub (or the local stand-in).
+-- Only the latest follow is kept, re-following moves followed_at forward.
+CREATE TABLE twitch_follows (
+  twitch_user_id  INTEGER PRIMARY KEY NOT NULL,
+  followed_at     DATETIME NOT NULL,
+
+  FOREIGN KEY(twitch_user_id) REFERENCES twitch_users(id)
+);
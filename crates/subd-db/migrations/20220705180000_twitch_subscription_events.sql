@@ -0,0 +1,32 @@
+-- Nothing wrote to twitch_gifted_subscriptions before this, so it is safe to recreate.
+-- Mystery gifts are announced before we know who receives them, so the recipient
+-- has to be nullable until the matching gifted sub shows up.
+DROP TABLE twitch_gifted_subscriptions;
+
+CREATE TABLE twitch_gifted_subscriptions (
+  id integer PRIMARY KEY AUTOINCREMENT,
+  broadcaster_id  INTEGER NOT NULL,
+  -- NULL until we know who received the gift
+  user_id         INTEGER,
+  -- Anonymous gifts use twitch's AnAnonymousGifter account (274598607)
+  gifter_id       INTEGER NOT NULL,
+  tier            INTEGER NOT NULL CHECK(tier >= 0 AND tier <= 3),
+  gifted_at       DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+
+  FOREIGN KEY(broadcaster_id) REFERENCES twitch_users(id),
+  FOREIGN KEY(user_id)        REFERENCES twitch_users(id),
+  FOREIGN KEY(gifter_id)      REFERENCES twitch_users(id)
+);
+
+CREATE INDEX twitch_gifted_subscriptions__gifter_id on twitch_gifted_subscriptions (gifter_id);
+
+-- "sub", "resub" or "gift"
+ALTER TABLE twitch_subscriptions ADD COLUMN kind TEXT NOT NULL DEFAULT 'sub';
+ALTER TABLE twitch_subscriptions ADD COLUMN cumulative_months INTEGER;
+
+INSERT OR IGNORE INTO twitch_users (id, login, display_name, broadcaster_type, account_type)
+  VALUES (274598607, 'ananonymousgifter', 'AnAnonymousGifter', '', '');
+
+-- Gifted subs reference the channel they were gifted in
+INSERT OR IGNORE INTO twitch_users (id, login, display_name, broadcaster_type, account_type)
+  VALUES (114257969, 'teej_dv', 'teej_dv', 'partner', '');
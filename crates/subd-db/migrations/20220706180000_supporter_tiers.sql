@@ -0,0 +1,5 @@
+-- NULL when the user isn't subscribed / sponsoring
+ALTER TABLE USER_ROLES ADD COLUMN twitch_sub_tier INTEGER CHECK(twitch_sub_tier >= 0 AND twitch_sub_tier <= 3);
+ALTER TABLE USER_ROLES ADD COLUMN twitch_sub_months INTEGER;
+ALTER TABLE USER_ROLES ADD COLUMN github_sponsor_tier TEXT;
+ALTER TABLE USER_ROLES ADD COLUMN github_sponsor_dollars INTEGER;
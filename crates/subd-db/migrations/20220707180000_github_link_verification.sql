@@ -0,0 +1,13 @@
+-- Links made before verification existed are treated as unverified
+ALTER TABLE users ADD COLUMN github_verified boolean NOT NULL DEFAULT false;
+
+-- Pending `!set github` requests, waiting for the code to show up on GitHub
+CREATE TABLE github_link_requests (
+  user_id       INTEGER PRIMARY KEY NOT NULL,
+  github_id     TEXT NOT NULL,
+  code          TEXT NOT NULL,
+  requested_at  DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+
+  FOREIGN KEY(user_id)   REFERENCES users(id),
+  FOREIGN KEY(github_id) REFERENCES github_users(id)
+);
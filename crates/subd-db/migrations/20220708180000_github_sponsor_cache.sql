@@ -0,0 +1,8 @@
+-- Last answer GitHub gave for each login, so we don't ask on every chat message
+CREATE TABLE github_sponsor_cache (
+  github_login     TEXT PRIMARY KEY NOT NULL,
+  is_sponsoring    boolean NOT NULL,
+  tier_name        TEXT,
+  monthly_dollars  INTEGER,
+  checked_at       DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Events we only hear about once (cheers, raids, sponsorships, ...), kept so we can
+-- look back at what happened during a stream
+CREATE TABLE event_journal (
+  id           INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind         TEXT NOT NULL,
+  -- The serde_json encoding of the subd_types::Event
+  payload      TEXT NOT NULL,
+  recorded_at  DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX event_journal__kind_recorded_at on event_journal (kind, recorded_at);
+CREATE INDEX twitch_chat_history__timestamp on TWITCH_CHAT_HISTORY (timestamp);
@@ -0,0 +1,11 @@
+-- `!suggest` issues that couldn't be filed yet, retried until GitHub takes them
+CREATE TABLE github_pending_suggestions (
+  id            INTEGER PRIMARY KEY AUTOINCREMENT,
+  repo          TEXT NOT NULL,
+  title         TEXT NOT NULL,
+  body          TEXT NOT NULL,
+  suggested_by  TEXT NOT NULL,
+  attempts      INTEGER DEFAULT 0 NOT NULL,
+  last_error    TEXT,
+  created_at    DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
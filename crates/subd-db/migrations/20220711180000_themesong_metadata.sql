@@ -0,0 +1,12 @@
+-- Remember where each themesong came from, so it can be shown, fixed or re-downloaded.
+-- Songs from before this have NULLs until update_old_themesongs backfills them from chat.
+ALTER TABLE USER_THEME_SONGS ADD COLUMN url TEXT;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN start_seconds REAL;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN end_seconds REAL;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN requested_at DATETIME;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN requested_by INTEGER REFERENCES USERS(id);
+ALTER TABLE USER_THEME_SONGS ADD COLUMN file_format TEXT;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN byte_size INTEGER;
+
+-- Every themesong so far was downloaded as an mp3
+UPDATE USER_THEME_SONGS SET file_format = 'mp3', byte_size = length(song);
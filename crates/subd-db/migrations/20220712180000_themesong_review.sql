@@ -0,0 +1,8 @@
+-- New themesongs wait for a mod before they can play on stream
+ALTER TABLE USER_THEME_SONGS ADD COLUMN status TEXT DEFAULT 'pending' NOT NULL;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN reviewed_by INTEGER REFERENCES USERS(id);
+ALTER TABLE USER_THEME_SONGS ADD COLUMN reviewed_at DATETIME;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN reject_reason TEXT;
+
+-- Everything that was already playing was fine
+UPDATE USER_THEME_SONGS SET status = 'approved';
@@ -0,0 +1,3 @@
+-- What the clip measured before it was normalized (EBU R128)
+ALTER TABLE USER_THEME_SONGS ADD COLUMN loudness_lufs REAL;
+ALTER TABLE USER_THEME_SONGS ADD COLUMN peak_dbfs REAL;
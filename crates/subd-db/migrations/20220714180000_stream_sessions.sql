@@ -0,0 +1,10 @@
+-- One stream, from going live to going offline (or a mod's `!session start` and `!session end`).
+-- Themesongs play once per session.
+CREATE TABLE stream_sessions (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  started_at  DATETIME NOT NULL,
+  -- NULL while the stream is still going
+  ended_at    DATETIME
+);
+
+CREATE INDEX user_theme_song_history__user_id_played_at on USER_THEME_SONG_HISTORY (user_id, played_at);
@@ -0,0 +1,28 @@
+-- `!themesong` downloads waiting for (or getting) their turn, so they survive a restart
+CREATE TABLE themesong_download_jobs (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  -- Whose themesong it will be
+  user_id         INTEGER NOT NULL,
+  display_name    TEXT NOT NULL,
+  -- Who asked, usually the same user unless a mod is fixing someone's clip
+  requested_by    INTEGER NOT NULL,
+  requester_name  TEXT NOT NULL,
+  url             TEXT NOT NULL,
+  start_seconds   REAL NOT NULL,
+  end_seconds     REAL NOT NULL,
+  max_seconds     REAL NOT NULL,
+  -- Mods' clips don't need review
+  approve         BOOLEAN DEFAULT FALSE NOT NULL,
+  -- queued, running, done, failed or cancelled
+  status          TEXT DEFAULT 'queued' NOT NULL,
+  attempts        INTEGER DEFAULT 0 NOT NULL,
+  last_error      TEXT,
+  -- Retries wait a bit before going again
+  run_after       DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  created_at      DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+
+  FOREIGN KEY(user_id) REFERENCES USERS(id),
+  FOREIGN KEY(requested_by) REFERENCES USERS(id)
+);
+
+CREATE INDEX themesong_download_jobs__status on themesong_download_jobs (status, run_after);
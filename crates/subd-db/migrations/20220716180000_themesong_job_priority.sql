@@ -0,0 +1,2 @@
+-- Supporters' downloads go first, see `perks::Perks::queue_priority`
+ALTER TABLE themesong_download_jobs ADD COLUMN priority INTEGER DEFAULT 0 NOT NULL;
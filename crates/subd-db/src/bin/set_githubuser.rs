@@ -1,12 +1,12 @@
 use anyhow::Result;
-use subd_db::set_github_info_for_user;
-
+use subd_db::request_github_link;
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut conn = subd_db::get_handle().await;
     let user = 138;
     let github_login = "conni2461";
-    set_github_info_for_user(&mut conn, &user, github_login).await?;
-
+    // The link only counts once they run `!set github verify` with this code on GitHub
+    let code = request_github_link(&mut conn, &user, github_login).await?;
+    println!("Put {} in the bio of github {}", code, github_login);
     Ok(())
 }
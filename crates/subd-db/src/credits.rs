@@ -0,0 +1,122 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+
+use crate::subscriptions::{GifterCount, ANONYMOUS_GIFTER_ID};
+
+/// Gifters of subs since `since`, most generous first. Anonymous gifts are left out.
+pub async fn get_gifters_since(
+    conn: &mut SqliteConnection,
+    since: DateTime<Utc>,
+) -> Result<Vec<GifterCount>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT twitch_users.display_name, count(*) as "gifted!: i64"
+            FROM twitch_gifted_subscriptions
+                JOIN twitch_users ON twitch_users.id = twitch_gifted_subscriptions.gifter_id
+            WHERE twitch_gifted_subscriptions.gifter_id != ?1
+                AND twitch_gifted_subscriptions.gifted_at >= datetime(?2)
+            GROUP BY twitch_gifted_subscriptions.gifter_id
+            ORDER BY 2 DESC
+        "#,
+        ANONYMOUS_GIFTER_ID,
+        since
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| GifterCount {
+            display_name: record.display_name,
+            gifted: record.gifted,
+        })
+        .collect())
+}
+
+/// Display names of everyone whose themesong played since `since`, in play order
+pub async fn get_themesong_players_since(
+    conn: &mut SqliteConnection,
+    since: DateTime<Utc>,
+) -> Result<Vec<String>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT twitch_users.display_name
+            FROM USER_THEME_SONG_HISTORY
+                JOIN users ON users.id = USER_THEME_SONG_HISTORY.user_id
+                JOIN twitch_users ON twitch_users.id = users.twitch_id
+            WHERE USER_THEME_SONG_HISTORY.played_at >= datetime(?1)
+            GROUP BY USER_THEME_SONG_HISTORY.user_id
+            ORDER BY min(USER_THEME_SONG_HISTORY.played_at)
+        "#,
+        since
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| record.display_name)
+        .collect())
+}
+
+/// The chattiest people since `since`, with how many messages they sent
+pub async fn get_top_chatters_since(
+    conn: &mut SqliteConnection,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<(String, i64)>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT twitch_users.display_name, count(*) as "messages!: i64"
+            FROM TWITCH_CHAT_HISTORY
+                JOIN users ON users.id = TWITCH_CHAT_HISTORY.user_id
+                JOIN twitch_users ON twitch_users.id = users.twitch_id
+            WHERE TWITCH_CHAT_HISTORY.timestamp >= datetime(?1)
+            GROUP BY TWITCH_CHAT_HISTORY.user_id
+            ORDER BY 2 DESC
+            LIMIT ?2
+        "#,
+        since,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| (record.display_name, record.messages))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::save_twitch_message;
+    use crate::test_utils::{chatter, test_conn};
+
+    #[tokio::test]
+    async fn test_top_chatters_since() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        chatter(&mut conn, "1", "nyxkrage").await;
+        chatter(&mut conn, "2", "theprimeagen").await;
+        save_twitch_message(&mut conn, "1", "hello").await?;
+        save_twitch_message(&mut conn, "2", "hello").await?;
+        save_twitch_message(&mut conn, "2", "vim btw").await?;
+
+        let since = Utc::now() - chrono::Duration::minutes(5);
+        let chatters = get_top_chatters_since(&mut conn, since, 10).await?;
+        assert_eq!(
+            chatters,
+            vec![("theprimeagen".to_string(), 2), ("nyxkrage".to_string(), 1)]
+        );
+
+        let later = Utc::now() + chrono::Duration::minutes(5);
+        assert!(get_top_chatters_since(&mut conn, later, 10)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+use subd_types::Event;
+
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub id: i64,
+    pub event: Event,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// The journal kind for events worth keeping. Everything else is left out.
+pub fn journal_kind(event: &Event) -> Option<&'static str> {
+    Some(match event {
+        Event::TwitchSubscription(_) => "twitch_subscription",
+        Event::TwitchCheer(_) => "twitch_cheer",
+        Event::TwitchRaid(_) => "twitch_raid",
+        Event::TwitchFollow(_) => "twitch_follow",
+        Event::TwitchChannelPointsRedemption(_) => "twitch_redemption",
+        Event::TwitchStreamOnline(_) => "twitch_stream_online",
+        Event::TwitchStreamOffline => "twitch_stream_offline",
+        Event::GithubSponsorshipEvent(_) => "github_sponsorship",
+        _ => return None,
+    })
+}
+
+/// Save `event` to the journal if it's a kind we keep. Returns whether it was saved.
+pub async fn record_event(conn: &mut SqliteConnection, event: &Event) -> Result<bool> {
+    let kind = match journal_kind(event) {
+        Some(kind) => kind,
+        None => return Ok(false),
+    };
+    let payload = serde_json::to_string(event)?;
+
+    sqlx::query!(
+        "INSERT INTO event_journal (kind, payload) VALUES (?1, ?2)",
+        kind,
+        payload
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(true)
+}
+
+/// Everything journaled since `since`, oldest first
+pub async fn get_journal_since(
+    conn: &mut SqliteConnection,
+    since: DateTime<Utc>,
+) -> Result<Vec<JournalEntry>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT id as "id!: i64", payload, recorded_at as "recorded_at: DateTime<Utc>"
+            FROM event_journal
+            WHERE recorded_at >= datetime(?1)
+            ORDER BY id
+        "#,
+        since
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    records
+        .into_iter()
+        .map(|record| {
+            Ok(JournalEntry {
+                id: record.id,
+                event: serde_json::from_str(&record.payload)?,
+                recorded_at: record.recorded_at,
+            })
+        })
+        .collect()
+}
+
+/// When the most recent stream went live, if we saw it happen
+pub async fn get_last_stream_start(conn: &mut SqliteConnection) -> Result<Option<DateTime<Utc>>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT payload FROM event_journal
+            WHERE kind = 'twitch_stream_online'
+            ORDER BY id DESC
+            LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(match record {
+        Some(record) => match serde_json::from_str(&record.payload)? {
+            Event::TwitchStreamOnline(started_at) => Some(started_at),
+            _ => None,
+        },
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_conn;
+
+    #[tokio::test]
+    async fn test_event_journal() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        let started_at = Utc::now() - chrono::Duration::hours(1);
+        let raid = Event::TwitchRaid(subd_types::TwitchRaidEvent {
+            from_broadcaster_id: "1".to_string(),
+            from_broadcaster_login: "theprimeagen".to_string(),
+            from_broadcaster_name: "ThePrimeagen".to_string(),
+            viewers: 420,
+        });
+
+        assert!(record_event(&mut conn, &Event::TwitchStreamOnline(started_at)).await?);
+        assert!(record_event(&mut conn, &raid).await?);
+        assert!(!record_event(&mut conn, &Event::RequestTwitchSubCount).await?);
+
+        assert_eq!(get_last_stream_start(&mut conn).await?, Some(started_at));
+
+        let entries = get_journal_since(&mut conn, started_at).await?;
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(
+            &entries[1].event,
+            Event::TwitchRaid(raid) if raid.viewers == 420
+        ));
+
+        Ok(())
+    }
+}
@@ -2,7 +2,28 @@
 
 use anyhow::Result;
 use sqlx::{Connection, SqliteConnection};
-use subd_types::{GithubUser, UserID, UserRoles};
+use chrono::{DateTime, Utc};
+use subd_gh::GithubApi;
+use subd_types::{GithubSponsorship, GithubUser, SubTier, TwitchFollowEvent, UserID, UserRoles};
+
+mod credits;
+mod journal;
+mod roles;
+mod sessions;
+mod sponsors;
+mod subscriptions;
+mod suggestions;
+#[cfg(test)]
+mod test_utils;
+mod themesong_jobs;
+pub use credits::*;
+pub use journal::*;
+pub use roles::*;
+pub use sessions::*;
+pub use sponsors::*;
+pub use subscriptions::*;
+pub use suggestions::*;
+pub use themesong_jobs::*;
 
 pub struct User {
     pub id: UserID,
@@ -30,17 +51,30 @@ pub async fn get_handle() -> SqliteConnection {
         .expect("To connect to the database")
 }
 
-pub async fn set_github_info_for_user(
+/// How long a `!set github` code stays valid
+const GITHUB_LINK_EXPIRY: &str = "-30 minutes";
+
+#[derive(Debug, Clone)]
+pub struct GithubLink {
+    pub user: GithubUser,
+    /// Only verified links count for sponsor perks
+    pub verified: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum GithubLinkResult {
+    NoRequest,
+    Expired,
+    CodeNotFound { login: String, code: String },
+    Verified(GithubUser),
+}
+
+async fn save_github_user(
     conn: &mut SqliteConnection,
-    user: &UserID,
+    github: &dyn GithubApi,
     github_login: &str,
-) -> Result<()> {
-    let github_user = subd_gh::get_github_user(github_login)
-        .await?
-        .ok_or(anyhow::anyhow!(
-            "Could not find github user: {:?}",
-            github_login
-        ))?;
+) -> Result<GithubUser> {
+    let github_user = github.get_user(github_login).await?;
 
     sqlx::query!(
         "
@@ -54,43 +88,168 @@ pub async fn set_github_info_for_user(
     .execute(&mut *conn)
     .await?;
 
-    // let result = sqlx::query!("SELECT id from github_users WHERE id = ?1", github_user.id)
-    //     .fetch_optional(&mut *conn)
-    //     .await?;
-    // if result.is_some() {
-    //     sqlx::query!(
-    //         "UPDATE github_users set (id, login, name) = (?1, ?2, ?3) WHERE id = ?1",
-    //         github_user.id,
-    //         github_user.login,
-    //         github_user.name
-    //     )
-    //     .execute(&mut *conn)
-    //     .await?;
-    // } else {
-    //     // Add github_user info to github_users
-    //     // TODO: Probably should delete any existing github user? sqlx::query!("DELETE
-    //     sqlx::query!(
-    //         "INSERT INTO github_users (id, login, name) VALUES (?1, ?2, ?3)",
-    //         github_user.id,
-    //         github_user.login,
-    //         github_user.name
-    //     )
-    //     .execute(&mut *conn)
-    //     .await?;
-    // }
-
-    // Update user to have github_user name
+    Ok(github_user)
+}
+
+fn new_verification_code() -> String {
+    format!("subd-{}", &uuid::Uuid::new_v4().simple().to_string()[..8])
+}
+
+/// Start linking `github_login` to `user`. Returns the code they need to put on GitHub.
+/// Nothing is linked until `verify_github_link` finds the code.
+pub async fn request_github_link(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+    user: &UserID,
+    github_login: &str,
+) -> Result<String> {
+    let github_user = save_github_user(conn, github, github_login).await?;
+    let code = new_verification_code();
+
     sqlx::query!(
-        "UPDATE users SET github_id = ?2 WHERE id = ?1",
+        "INSERT OR REPLACE INTO github_link_requests (user_id, github_id, code) VALUES (?1, ?2, ?3)",
         user,
-        github_user.id
+        github_user.id,
+        code
     )
     .execute(&mut *conn)
     .await?;
 
+    Ok(code)
+}
+
+/// Check GitHub for the code from `request_github_link`, and link the accounts if it's there
+pub async fn verify_github_link(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+    user: &UserID,
+) -> Result<GithubLinkResult> {
+    let request = sqlx::query!(
+        r#"
+        SELECT
+            github_link_requests.code,
+            github_link_requests.requested_at >= datetime('now', ?2) as "is_fresh!: bool",
+            github_users.id,
+            github_users.login,
+            github_users.name
+        FROM github_link_requests
+            JOIN github_users ON github_users.id = github_link_requests.github_id
+        WHERE github_link_requests.user_id = ?1
+        "#,
+        user,
+        GITHUB_LINK_EXPIRY
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let request = match request {
+        Some(request) => request,
+        None => return Ok(GithubLinkResult::NoRequest),
+    };
+
+    if !request.is_fresh {
+        sqlx::query!("DELETE FROM github_link_requests WHERE user_id = ?1", user)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(GithubLinkResult::Expired);
+    }
+
+    if !github
+        .has_verification_code(&request.login, &request.code)
+        .await?
+    {
+        return Ok(GithubLinkResult::CodeNotFound {
+            login: request.login,
+            code: request.code,
+        });
+    }
+
+    link_github_user(conn, user, &request.id).await?;
+
+    Ok(GithubLinkResult::Verified(GithubUser {
+        id: request.id,
+        login: request.login,
+        name: request.name,
+    }))
+}
+
+/// Link a github account that `user` has proven they own
+async fn link_github_user(
+    conn: &mut SqliteConnection,
+    user: &UserID,
+    github_id: &str,
+) -> Result<()> {
+    // Whoever had this account linked before never proved it was theirs
+    sqlx::query!(
+        "UPDATE users SET github_id = NULL, github_verified = false WHERE github_id = ?1 AND id != ?2",
+        github_id,
+        user
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE users SET github_id = ?2, github_verified = true WHERE id = ?1",
+        user,
+        github_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!("DELETE FROM github_link_requests WHERE user_id = ?1", user)
+        .execute(&mut *conn)
+        .await?;
+
     Ok(())
 }
 
+pub async fn get_github_link_for_user(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+) -> Result<Option<GithubLink>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT github_users.id, github_users.login, github_users.name, users.github_verified
+            FROM users
+                JOIN github_users ON github_users.id = users.github_id
+            WHERE users.id = ?1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(record.map(|record| GithubLink {
+        user: GithubUser {
+            id: record.id,
+            login: record.login,
+            name: record.name,
+        },
+        verified: record.github_verified,
+    }))
+}
+
+/// The user (and their twitch login) that has verified they own `github_login`
+pub async fn get_user_from_github_login(
+    conn: &mut SqliteConnection,
+    github_login: &str,
+) -> Result<Option<(UserID, String)>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT users.id, twitch_users.login
+            FROM users
+                JOIN github_users ON github_users.id = users.github_id
+                JOIN twitch_users ON twitch_users.id = users.twitch_id
+            WHERE lower(github_users.login) = lower(?1) AND users.github_verified
+        "#,
+        github_login
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(record.map(|record| (record.id, record.login)))
+}
+
 pub async fn get_github_info_for_user(
     conn: &mut SqliteConnection,
     user_id: &UserID,
@@ -268,6 +427,61 @@ pub async fn save_twitch_message(
     Ok(())
 }
 
+pub async fn save_twitch_follow(
+    conn: &mut SqliteConnection,
+    follow: &TwitchFollowEvent,
+) -> Result<()> {
+    // People can follow before they ever chat, so make sure we know about them
+    sqlx::query!(
+        "INSERT OR IGNORE INTO twitch_users (id, login, display_name, broadcaster_type, account_type)
+            VALUES ( ?1, ?2, ?3, '', '' )",
+        follow.user_id,
+        follow.user_login,
+        follow.user_name
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO twitch_follows (twitch_user_id, followed_at) VALUES (?1, ?2)
+            ON CONFLICT(twitch_user_id) DO UPDATE SET followed_at = ?2",
+        follow.user_id,
+        follow.followed_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_twitch_followed_at(
+    conn: &mut SqliteConnection,
+    login: &str,
+) -> Result<Option<DateTime<Utc>>> {
+    let login = login.replace("@", "").to_lowercase();
+
+    let record = sqlx::query!(
+        r#"
+        SELECT twitch_follows.followed_at as "followed_at: DateTime<Utc>"
+            FROM twitch_follows
+                JOIN twitch_users ON twitch_users.id = twitch_follows.twitch_user_id
+            WHERE twitch_users.login = ?1
+        "#,
+        login
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(record.map(|record| record.followed_at))
+}
+
+pub async fn get_twitch_follower_count(conn: &mut SqliteConnection) -> Result<i32> {
+    Ok(sqlx::query!("SELECT count(*) as c FROM twitch_follows")
+        .fetch_one(&mut *conn)
+        .await?
+        .c)
+}
+
 pub type TwitchUserID = i64;
 pub struct TwitchUser {
     id: TwitchUserID,
@@ -307,6 +521,12 @@ pub async fn set_user_roles(
     user_id: &UserID,
     roles: UserRoles,
 ) -> Result<()> {
+    let twitch_sub_tier = roles.twitch_sub_tier.map(|tier| tier.as_number());
+    let (github_sponsor_tier, github_sponsor_dollars) = match roles.github_sponsorship {
+        Some(sponsorship) => (Some(sponsorship.tier_name), Some(sponsorship.monthly_dollars)),
+        None => (None, None),
+    };
+
     sqlx::query!(
         "INSERT INTO user_roles (
             user_id, 
@@ -314,14 +534,22 @@ pub async fn set_user_roles(
             is_twitch_mod,
             is_twitch_vip,
             is_twitch_founder,
-            is_twitch_sub
+            is_twitch_sub,
+            twitch_sub_tier,
+            twitch_sub_months,
+            github_sponsor_tier,
+            github_sponsor_dollars
         ) VALUES (
             ?1,
             ?2,
             ?3,
             ?4,
             ?5,
-            ?6
+            ?6,
+            ?7,
+            ?8,
+            ?9,
+            ?10
         )",
         user_id,
         roles.is_github_sponsor,
@@ -329,6 +557,10 @@ pub async fn set_user_roles(
         roles.is_twitch_vip,
         roles.is_twitch_founder,
         roles.is_twitch_sub,
+        twitch_sub_tier,
+        roles.twitch_sub_months,
+        github_sponsor_tier,
+        github_sponsor_dollars,
     )
     .execute(&mut *conn)
     .await?;
@@ -337,15 +569,18 @@ pub async fn set_user_roles(
 }
 
 pub async fn get_user_roles(conn: &mut SqliteConnection, user_id: &UserID) -> Result<UserRoles> {
-    Ok(sqlx::query_as!(
-        UserRoles,
+    let record = sqlx::query!(
         "
 SELECT 
     is_github_sponsor,
     is_twitch_mod,
     is_twitch_vip,
     is_twitch_founder,
-    is_twitch_sub
+    is_twitch_sub,
+    twitch_sub_tier,
+    twitch_sub_months,
+    github_sponsor_tier,
+    github_sponsor_dollars
 FROM user_roles WHERE user_id = ?1
 ORDER BY verified_date DESC
 LIMIT 1
@@ -353,8 +588,31 @@ LIMIT 1
         user_id
     )
     .fetch_optional(&mut *conn)
-    .await?
-    .unwrap_or_default())
+    .await?;
+
+    let record = match record {
+        Some(record) => record,
+        None => return Ok(UserRoles::default()),
+    };
+
+    let github_sponsorship = match (record.github_sponsor_tier, record.github_sponsor_dollars) {
+        (Some(tier_name), Some(monthly_dollars)) => Some(GithubSponsorship {
+            tier_name,
+            monthly_dollars,
+        }),
+        _ => None,
+    };
+
+    Ok(UserRoles {
+        is_github_sponsor: record.is_github_sponsor,
+        is_twitch_mod: record.is_twitch_mod,
+        is_twitch_vip: record.is_twitch_vip,
+        is_twitch_founder: record.is_twitch_founder,
+        is_twitch_sub: record.is_twitch_sub,
+        twitch_sub_tier: record.twitch_sub_tier.map(SubTier::from_number),
+        twitch_sub_months: record.twitch_sub_months,
+        github_sponsorship,
+    })
 }
 
 #[cfg(test)]
@@ -402,4 +660,114 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_save_twitch_follow() -> anyhow::Result<()> {
+        let pool = get_test_database().await?;
+        let mut conn = pool.acquire().await?;
+
+        let followed_at = "2022-07-04T18:00:00Z".parse::<DateTime<Utc>>()?;
+        let follow = TwitchFollowEvent {
+            user_id: "1234".to_string(),
+            user_login: "nyxkrage".to_string(),
+            user_name: "NyxKrage".to_string(),
+            followed_at,
+        };
+
+        save_twitch_follow(&mut conn, &follow).await?;
+        // Following twice should not count twice
+        save_twitch_follow(&mut conn, &follow).await?;
+
+        assert_eq!(get_twitch_follower_count(&mut conn).await?, 1);
+        assert_eq!(
+            get_twitch_followed_at(&mut conn, "@NyxKrage").await?,
+            Some(followed_at)
+        );
+        assert_eq!(get_twitch_followed_at(&mut conn, "teej_dv").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verified_github_link_replaces_claims() -> anyhow::Result<()> {
+        let pool = get_test_database().await?;
+        let mut conn = pool.acquire().await?;
+
+        sqlx::query!(
+            "INSERT INTO github_users (id, login, name) VALUES ('gh-1', 'tjdevries', 'TJ')"
+        )
+        .execute(&mut conn)
+        .await?;
+
+        create_twitch_user_chat(&mut conn, "1", "claimer").await?;
+        create_twitch_user_chat(&mut conn, "2", "tjdevries").await?;
+        let claimer = get_user_from_twitch_user(&mut conn, "1").await?;
+        let owner = get_user_from_twitch_user(&mut conn, "2").await?;
+        sqlx::query!("UPDATE users SET github_id = 'gh-1' WHERE id = ?1", claimer)
+            .execute(&mut conn)
+            .await?;
+
+        // Links from before verification existed don't count
+        let link = get_github_link_for_user(&mut conn, &claimer)
+            .await?
+            .unwrap();
+        assert!(!link.verified);
+
+        link_github_user(&mut conn, &owner, "gh-1").await?;
+
+        assert!(get_github_link_for_user(&mut conn, &claimer)
+            .await?
+            .is_none());
+        let link = get_github_link_for_user(&mut conn, &owner).await?.unwrap();
+        assert!(link.verified);
+        assert_eq!(link.user.login, "tjdevries");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verification_codes_are_unique() {
+        let code = new_verification_code();
+        assert!(code.starts_with("subd-"));
+        assert_eq!(code.len(), "subd-".len() + 8);
+        assert_ne!(code, new_verification_code());
+    }
+
+    #[tokio::test]
+    async fn test_github_link_needs_code_on_github() -> anyhow::Result<()> {
+        let pool = get_test_database().await?;
+        let mut conn = pool.acquire().await?;
+
+        create_twitch_user_chat(&mut conn, "1", "jesseleite").await?;
+        let user = get_user_from_twitch_user(&mut conn, "1").await?;
+
+        let github = subd_gh::FakeGithub::new().with_user("jesseleite");
+        let err = request_github_link(&mut conn, &github, &user, "nope")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<subd_gh::GithubError>(),
+            Some(subd_gh::GithubError::NotFound(_))
+        ));
+
+        let code = request_github_link(&mut conn, &github, &user, "jesseleite").await?;
+        assert!(matches!(
+            verify_github_link(&mut conn, &github, &user).await?,
+            GithubLinkResult::CodeNotFound { .. }
+        ));
+
+        let github = github.with_verification_source("jesseleite", &code);
+        assert!(matches!(
+            verify_github_link(&mut conn, &github, &user).await?,
+            GithubLinkResult::Verified(_)
+        ));
+        assert!(
+            get_github_link_for_user(&mut conn, &user)
+                .await?
+                .unwrap()
+                .verified
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_types::{SubscriptionUser, UserID};
+
+use crate::subscriptions::ensure_twitch_user;
+
+/// Someone whose most recent roles include a twitch role
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwitchRoleHolder {
+    pub user_id: UserID,
+    pub twitch_id: String,
+    pub login: String,
+}
+
+/// Replace the moderators we know about for `broadcaster_id` with `moderators`
+pub async fn set_twitch_moderators(
+    conn: &mut SqliteConnection,
+    broadcaster_id: &str,
+    moderators: &[SubscriptionUser],
+) -> Result<()> {
+    for moderator in moderators {
+        ensure_twitch_user(conn, moderator).await?;
+    }
+
+    sqlx::query!(
+        "DELETE FROM twitch_moderators WHERE broadcaster_id = ?1",
+        broadcaster_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    for moderator in moderators {
+        sqlx::query!(
+            "INSERT INTO twitch_moderators (broadcaster_id, user_id) VALUES (?1, ?2)",
+            broadcaster_id,
+            moderator.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Mark a subscription reported by Helix as still active.
+///
+/// Helix doesn't tell us when a sub started, so rather than adding a row every sync
+/// we bump noted_date on the latest row for the same user and tier.
+pub async fn note_twitch_subscription(
+    conn: &mut SqliteConnection,
+    user: &SubscriptionUser,
+    tier: i64,
+    is_gift: bool,
+) -> Result<()> {
+    ensure_twitch_user(conn, user).await?;
+
+    let updated = sqlx::query!(
+        "UPDATE twitch_subscriptions SET noted_date = CURRENT_TIMESTAMP
+            WHERE rowid = (
+                SELECT rowid FROM twitch_subscriptions
+                    WHERE user_id = ?1 AND tier = ?2
+                    ORDER BY noted_date DESC
+                    LIMIT 1
+            )",
+        user.id,
+        tier
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        let kind = if is_gift { "gift" } else { "sub" };
+        sqlx::query!(
+            "INSERT INTO twitch_subscriptions (user_id, tier, kind) VALUES (?1, ?2, ?3)",
+            user.id,
+            tier,
+            kind
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Everyone whose latest USER_ROLES row says they are a mod, vip or sub
+pub async fn get_twitch_role_holders(conn: &mut SqliteConnection) -> Result<Vec<TwitchRoleHolder>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT users.id as "user_id!: i64", twitch_users.id as "twitch_id!: i64", twitch_users.login
+            FROM users
+                JOIN twitch_users ON twitch_users.id = users.twitch_id
+                JOIN USER_ROLES ON USER_ROLES.user_id = users.id
+            WHERE USER_ROLES.verified_date = (
+                    SELECT max(verified_date) FROM USER_ROLES WHERE user_id = users.id
+                )
+                AND (USER_ROLES.is_twitch_mod OR USER_ROLES.is_twitch_vip OR USER_ROLES.is_twitch_sub)
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| TwitchRoleHolder {
+            user_id: record.user_id,
+            twitch_id: record.twitch_id.to_string(),
+            login: record.login,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{sub_user, test_conn};
+    use crate::{get_subscription_history, get_user_from_twitch_user, set_user_roles};
+    use subd_types::UserRoles;
+
+    #[tokio::test]
+    async fn test_twitch_role_sync_helpers() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        let nyx = sub_user("2", "nyxkrage", "NyxKrage");
+        let prime = sub_user("1", "theprimeagen", "ThePrimeagen");
+
+        // Syncing the same sub twice only notes it again
+        note_twitch_subscription(&mut conn, &nyx, 1, false).await?;
+        note_twitch_subscription(&mut conn, &nyx, 1, false).await?;
+        assert_eq!(
+            get_subscription_history(&mut conn, "nyxkrage").await?.len(),
+            1
+        );
+
+        set_twitch_moderators(&mut conn, "114257969", &[nyx.clone(), prime]).await?;
+        set_twitch_moderators(&mut conn, "114257969", &[nyx]).await?;
+        let moderators = sqlx::query!("SELECT count(*) as c FROM twitch_moderators")
+            .fetch_one(&mut conn)
+            .await?
+            .c;
+        assert_eq!(moderators, 1);
+
+        let user_id = get_user_from_twitch_user(&mut conn, "2").await?;
+        set_user_roles(
+            &mut conn,
+            &user_id,
+            UserRoles {
+                is_twitch_mod: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let holders = get_twitch_role_holders(&mut conn).await?;
+        assert_eq!(holders.len(), 1);
+        assert_eq!(holders[0].login, "nyxkrage");
+        assert_eq!(holders[0].twitch_id, "2");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+
+/// One stream, from going live until going offline
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamSession {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    /// `None` while the stream is still going
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+/// The session that hasn't ended yet, if we're live
+pub async fn get_current_stream_session(
+    conn: &mut SqliteConnection,
+) -> Result<Option<StreamSession>> {
+    let session = sqlx::query_as!(
+        StreamSession,
+        r#"
+        SELECT id as "id!: i64",
+               started_at as "started_at: DateTime<Utc>",
+               ended_at as "ended_at: DateTime<Utc>"
+            FROM stream_sessions
+            WHERE ended_at IS NULL
+            ORDER BY id DESC
+            LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(session)
+}
+
+/// Start a session at `started_at`. If one is already going (say a mod ran
+/// `!session start` before twitch told us we're live), that one keeps going.
+pub async fn start_stream_session(
+    conn: &mut SqliteConnection,
+    started_at: DateTime<Utc>,
+) -> Result<StreamSession> {
+    if let Some(session) = get_current_stream_session(conn).await? {
+        return Ok(session);
+    }
+
+    sqlx::query!(
+        "INSERT INTO stream_sessions (started_at) VALUES (datetime(?1))",
+        started_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    get_current_stream_session(conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("stream session wasn't saved"))
+}
+
+/// End the current session, returning it if there was one
+pub async fn end_stream_session(
+    conn: &mut SqliteConnection,
+    ended_at: DateTime<Utc>,
+) -> Result<Option<StreamSession>> {
+    let mut session = match get_current_stream_session(conn).await? {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    sqlx::query!(
+        "UPDATE stream_sessions SET ended_at = datetime(?1) WHERE id = ?2",
+        ended_at,
+        session.id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    session.ended_at = Some(ended_at);
+    Ok(Some(session))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_conn;
+
+    #[tokio::test]
+    async fn test_stream_sessions() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        assert_eq!(get_current_stream_session(&mut conn).await?, None);
+        assert_eq!(end_stream_session(&mut conn, Utc::now()).await?, None);
+
+        let started_at = "2022-07-14T18:00:00Z".parse::<DateTime<Utc>>()?;
+        let session = start_stream_session(&mut conn, started_at).await?;
+        assert_eq!(session.started_at, started_at);
+
+        // Going live again while a session is running keeps the first one
+        let again = start_stream_session(&mut conn, Utc::now()).await?;
+        assert_eq!(again, session);
+
+        let ended = end_stream_session(&mut conn, Utc::now()).await?.unwrap();
+        assert_eq!(ended.id, session.id);
+        assert_eq!(get_current_stream_session(&mut conn).await?, None);
+
+        let next = start_stream_session(&mut conn, Utc::now()).await?;
+        assert_ne!(next.id, session.id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::SqliteConnection;
+use subd_types::{GithubSponsorStatus, GithubSponsorship, UserID};
+
+/// How long a cached sponsor status is trusted before asking GitHub again
+pub fn sponsor_cache_ttl() -> Duration {
+    Duration::hours(1)
+}
+
+/// Someone who has proven they own a GitHub account
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedGithubLink {
+    pub user_id: UserID,
+    pub github_login: String,
+    pub twitch_login: Option<String>,
+}
+
+/// The cached status for `github_login`, unless it is older than `ttl`
+pub async fn get_cached_sponsor_status(
+    conn: &mut SqliteConnection,
+    github_login: &str,
+    ttl: Duration,
+) -> Result<Option<GithubSponsorStatus>> {
+    let checked_after = Utc::now() - ttl;
+    let record = sqlx::query!(
+        r#"
+        SELECT is_sponsoring, tier_name, monthly_dollars,
+               checked_at as "checked_at: DateTime<Utc>"
+            FROM github_sponsor_cache
+            WHERE github_login = lower(?1)
+        "#,
+        github_login
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(record
+        .filter(|record| record.checked_at > checked_after)
+        .map(|record| GithubSponsorStatus {
+            is_sponsoring: record.is_sponsoring,
+            sponsorship: match (record.tier_name, record.monthly_dollars) {
+                (Some(tier_name), Some(monthly_dollars)) => Some(GithubSponsorship {
+                    tier_name,
+                    monthly_dollars,
+                }),
+                _ => None,
+            },
+        }))
+}
+
+pub async fn set_cached_sponsor_status(
+    conn: &mut SqliteConnection,
+    github_login: &str,
+    status: &GithubSponsorStatus,
+) -> Result<()> {
+    let tier_name = status.sponsorship.as_ref().map(|s| s.tier_name.clone());
+    let monthly_dollars = status.sponsorship.as_ref().map(|s| s.monthly_dollars);
+
+    sqlx::query!(
+        "INSERT OR REPLACE INTO github_sponsor_cache
+            (github_login, is_sponsoring, tier_name, monthly_dollars, checked_at)
+            VALUES (lower(?1), ?2, ?3, ?4, CURRENT_TIMESTAMP)",
+        github_login,
+        status.is_sponsoring,
+        tier_name,
+        monthly_dollars
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_verified_github_links(
+    conn: &mut SqliteConnection,
+) -> Result<Vec<VerifiedGithubLink>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT users.id as "user_id!: i64", github_users.login as github_login,
+               twitch_users.login as "twitch_login?"
+            FROM users
+                JOIN github_users ON github_users.id = users.github_id
+                LEFT JOIN twitch_users ON twitch_users.id = users.twitch_id
+            WHERE users.github_verified
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| VerifiedGithubLink {
+            user_id: record.user_id,
+            github_login: record.github_login,
+            twitch_login: record.twitch_login,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_conn;
+
+    #[tokio::test]
+    async fn test_sponsor_cache_expires() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        let status = GithubSponsorStatus {
+            is_sponsoring: true,
+            sponsorship: Some(GithubSponsorship {
+                tier_name: "$10 a month".to_string(),
+                monthly_dollars: 10,
+            }),
+        };
+        set_cached_sponsor_status(&mut conn, "JesseLeite", &status).await?;
+
+        let cached =
+            get_cached_sponsor_status(&mut conn, "jesseleite", sponsor_cache_ttl()).await?;
+        assert_eq!(cached, Some(status));
+
+        sqlx::query!("UPDATE github_sponsor_cache SET checked_at = datetime('now', '-2 hours')")
+            .execute(&mut conn)
+            .await?;
+        let cached =
+            get_cached_sponsor_status(&mut conn, "jesseleite", sponsor_cache_ttl()).await?;
+        assert_eq!(cached, None);
+
+        Ok(())
+    }
+}
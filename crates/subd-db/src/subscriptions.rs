@@ -0,0 +1,352 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+use subd_types::{SubTier, SubscriptionKind, SubscriptionUser, TwitchSubscriptionEvent};
+
+/// Twitch's own account for gifts where the gifter wants to stay anonymous
+pub const ANONYMOUS_GIFTER_ID: &str = "274598607";
+
+/// How long after a mystery gift we will still match gifted subs to it
+const MYSTERY_GIFT_WINDOW: &str = "-10 minutes";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GifterCount {
+    pub display_name: String,
+    pub gifted: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionHistoryEntry {
+    pub tier: SubTier,
+    /// "sub", "resub" or "gift"
+    pub kind: String,
+    pub cumulative_months: Option<i64>,
+    pub gifter: Option<String>,
+    pub noted_date: DateTime<Utc>,
+}
+
+pub(crate) async fn ensure_twitch_user(conn: &mut SqliteConnection, user: &SubscriptionUser) -> Result<()> {
+    sqlx::query!(
+        "INSERT OR IGNORE INTO twitch_users (id, login, display_name, broadcaster_type, account_type)
+            VALUES ( ?1, ?2, ?3, '', '' )",
+        user.id,
+        user.login,
+        user.display_name
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Record any subscription event in twitch_subscriptions / twitch_gifted_subscriptions
+pub async fn save_twitch_subscription(
+    conn: &mut SqliteConnection,
+    broadcaster_id: &str,
+    sub: &TwitchSubscriptionEvent,
+) -> Result<()> {
+    if let Some(user) = &sub.user {
+        ensure_twitch_user(conn, user).await?;
+    }
+    if let Some(recipient) = &sub.recipient {
+        ensure_twitch_user(conn, recipient).await?;
+    }
+
+    let tier = sub.tier.as_number();
+    let gifter_id = match (&sub.user, sub.is_anonymous) {
+        (Some(user), false) => Some(user.id.clone()),
+        (_, true) => Some(ANONYMOUS_GIFTER_ID.to_string()),
+        (None, false) => None,
+    };
+
+    match &sub.kind {
+        SubscriptionKind::MysteryGift { count } => {
+            let gifter_id = gifter_id.unwrap_or_else(|| ANONYMOUS_GIFTER_ID.to_string());
+
+            // The recipients show up as their own gifted subs right after this
+            for _ in 0..*count {
+                sqlx::query!(
+                    "INSERT INTO twitch_gifted_subscriptions (broadcaster_id, user_id, gifter_id, tier)
+                        VALUES (?1, NULL, ?2, ?3)",
+                    broadcaster_id,
+                    gifter_id,
+                    tier
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        SubscriptionKind::Gift => {
+            let recipient = match &sub.recipient {
+                Some(recipient) => recipient,
+                None => return Err(anyhow::anyhow!("gifted sub without a recipient")),
+            };
+
+            let gift_id = match gifter_id {
+                Some(gifter_id) => Some(
+                    sqlx::query!(
+                        "INSERT INTO twitch_gifted_subscriptions (broadcaster_id, user_id, gifter_id, tier)
+                            VALUES (?1, ?2, ?3, ?4)",
+                        broadcaster_id,
+                        recipient.id,
+                        gifter_id,
+                        tier
+                    )
+                    .execute(&mut *conn)
+                    .await?
+                    .last_insert_rowid(),
+                ),
+                // EventSub doesn't say who gave this one, so claim it from a recent mystery gift
+                None => claim_mystery_gift(conn, &recipient.id, tier).await?,
+            };
+
+            sqlx::query!(
+                "INSERT OR IGNORE INTO twitch_subscriptions (user_id, tier, gift_id, start_date, kind)
+                    VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP, 'gift')",
+                recipient.id,
+                tier,
+                gift_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        SubscriptionKind::Sub | SubscriptionKind::Resub => {
+            let user = match &sub.user {
+                Some(user) => user,
+                None => return Err(anyhow::anyhow!("subscription without a user")),
+            };
+
+            // We only know when a sub started if we saw it start
+            let (kind, start_date) = match sub.kind {
+                SubscriptionKind::Sub => ("sub", Some(Utc::now())),
+                _ => ("resub", None),
+            };
+
+            sqlx::query!(
+                "INSERT OR IGNORE INTO twitch_subscriptions (user_id, tier, start_date, kind, cumulative_months)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                user.id,
+                tier,
+                start_date,
+                kind,
+                sub.cumulative_months
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn claim_mystery_gift(
+    conn: &mut SqliteConnection,
+    recipient_id: &str,
+    tier: i64,
+) -> Result<Option<i64>> {
+    let pending = sqlx::query!(
+        "SELECT id FROM twitch_gifted_subscriptions
+            WHERE user_id IS NULL AND tier = ?1 AND gifted_at >= datetime('now', ?2)
+            ORDER BY id
+            LIMIT 1",
+        tier,
+        MYSTERY_GIFT_WINDOW
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let id = match pending {
+        Some(pending) => pending.id,
+        None => {
+            println!(
+                "  No mystery gift found for gifted sub to: {}",
+                recipient_id
+            );
+            return Ok(None);
+        }
+    };
+
+    sqlx::query!(
+        "UPDATE twitch_gifted_subscriptions SET user_id = ?2 WHERE id = ?1",
+        id,
+        recipient_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(Some(id))
+}
+
+/// Gifters with the most gifted subs, anonymous gifts not included
+pub async fn get_top_gifters(conn: &mut SqliteConnection, limit: i64) -> Result<Vec<GifterCount>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT twitch_users.display_name, count(*) as "gifted!: i64"
+            FROM twitch_gifted_subscriptions
+                JOIN twitch_users ON twitch_users.id = twitch_gifted_subscriptions.gifter_id
+            WHERE twitch_gifted_subscriptions.gifter_id != ?1
+            GROUP BY twitch_gifted_subscriptions.gifter_id
+            ORDER BY 2 DESC
+            LIMIT ?2
+        "#,
+        ANONYMOUS_GIFTER_ID,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| GifterCount {
+            display_name: record.display_name,
+            gifted: record.gifted,
+        })
+        .collect())
+}
+
+/// Every subscription we have recorded for a twitch login, newest first
+pub async fn get_subscription_history(
+    conn: &mut SqliteConnection,
+    login: &str,
+) -> Result<Vec<SubscriptionHistoryEntry>> {
+    let login = login.replace("@", "").to_lowercase();
+
+    let records = sqlx::query!(
+        r#"
+        SELECT
+            twitch_subscriptions.tier,
+            twitch_subscriptions.kind,
+            twitch_subscriptions.cumulative_months,
+            gifter.display_name as "gifter?",
+            twitch_subscriptions.noted_date as "noted_date: DateTime<Utc>"
+        FROM twitch_subscriptions
+            JOIN twitch_users ON twitch_users.id = twitch_subscriptions.user_id
+            LEFT JOIN twitch_gifted_subscriptions ON twitch_gifted_subscriptions.id = twitch_subscriptions.gift_id
+            LEFT JOIN twitch_users gifter ON gifter.id = twitch_gifted_subscriptions.gifter_id
+        WHERE twitch_users.login = ?1
+        ORDER BY twitch_subscriptions.noted_date DESC
+        "#,
+        login
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| SubscriptionHistoryEntry {
+            tier: SubTier::from_number(record.tier),
+            kind: record.kind,
+            cumulative_months: record.cumulative_months,
+            gifter: record.gifter,
+            noted_date: record.noted_date,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{sub_user, test_conn};
+
+    fn sub_event(
+        kind: SubscriptionKind,
+        user: Option<SubscriptionUser>,
+        recipient: Option<SubscriptionUser>,
+    ) -> TwitchSubscriptionEvent {
+        TwitchSubscriptionEvent {
+            kind,
+            tier: SubTier::Tier1,
+            user,
+            recipient,
+            is_anonymous: false,
+            cumulative_months: None,
+            streak_months: None,
+            message: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_twitch_subscription_gifts() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        let broadcaster = "114257969";
+        let prime = sub_user("1", "theprimeagen", "ThePrimeagen");
+        let nyx = sub_user("2", "nyxkrage", "NyxKrage");
+        let bash = sub_user("3", "bashbunni", "bashbunni");
+
+        // PubSub tells us both sides of a gift at once
+        save_twitch_subscription(
+            &mut conn,
+            broadcaster,
+            &sub_event(
+                SubscriptionKind::Gift,
+                Some(prime.clone()),
+                Some(nyx.clone()),
+            ),
+        )
+        .await?;
+
+        // EventSub announces the mystery gift, then each recipient without a gifter
+        save_twitch_subscription(
+            &mut conn,
+            broadcaster,
+            &sub_event(
+                SubscriptionKind::MysteryGift { count: 2 },
+                Some(prime),
+                None,
+            ),
+        )
+        .await?;
+        save_twitch_subscription(
+            &mut conn,
+            broadcaster,
+            &sub_event(SubscriptionKind::Gift, None, Some(bash)),
+        )
+        .await?;
+
+        let mut anonymous = sub_event(SubscriptionKind::MysteryGift { count: 5 }, None, None);
+        anonymous.is_anonymous = true;
+        save_twitch_subscription(&mut conn, broadcaster, &anonymous).await?;
+
+        assert_eq!(
+            get_top_gifters(&mut conn, 5).await?,
+            vec![GifterCount {
+                display_name: "ThePrimeagen".to_string(),
+                gifted: 3
+            }]
+        );
+
+        let history = get_subscription_history(&mut conn, "@BashBunni").await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].kind, "gift");
+        assert_eq!(history[0].gifter.as_deref(), Some("ThePrimeagen"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_save_twitch_subscription_resubs() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        let nyx = sub_user("2", "nyxkrage", "NyxKrage");
+        save_twitch_subscription(
+            &mut conn,
+            "114257969",
+            &sub_event(SubscriptionKind::Sub, Some(nyx.clone()), None),
+        )
+        .await?;
+
+        let mut resub = sub_event(SubscriptionKind::Resub, Some(nyx), None);
+        resub.cumulative_months = Some(2);
+        save_twitch_subscription(&mut conn, "114257969", &resub).await?;
+
+        let history = get_subscription_history(&mut conn, "nyxkrage").await?;
+        assert_eq!(history.len(), 2);
+        assert!(history
+            .iter()
+            .any(|entry| entry.cumulative_months == Some(2)));
+        assert!(history.iter().all(|entry| entry.gifter.is_none()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+
+/// A `!suggest` that is waiting to be filed as a GitHub issue
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSuggestion {
+    pub id: i64,
+    pub repo: String,
+    pub title: String,
+    pub body: String,
+    pub suggested_by: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn add_pending_suggestion(
+    conn: &mut SqliteConnection,
+    repo: &str,
+    title: &str,
+    body: &str,
+    suggested_by: &str,
+) -> Result<i64> {
+    let id = sqlx::query!(
+        "INSERT INTO github_pending_suggestions (repo, title, body, suggested_by)
+            VALUES (?1, ?2, ?3, ?4)",
+        repo,
+        title,
+        body,
+        suggested_by
+    )
+    .execute(&mut *conn)
+    .await?
+    .last_insert_rowid();
+
+    Ok(id)
+}
+
+/// Oldest first, so suggestions get filed in the order they were made
+pub async fn get_pending_suggestions(
+    conn: &mut SqliteConnection,
+) -> Result<Vec<PendingSuggestion>> {
+    let suggestions = sqlx::query_as!(
+        PendingSuggestion,
+        r#"
+        SELECT id as "id!: i64", repo, title, body, suggested_by,
+               attempts as "attempts!: i64", last_error,
+               created_at as "created_at: DateTime<Utc>"
+            FROM github_pending_suggestions
+            ORDER BY id
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(suggestions)
+}
+
+pub async fn record_suggestion_failure(
+    conn: &mut SqliteConnection,
+    id: i64,
+    error: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE github_pending_suggestions
+            SET attempts = attempts + 1, last_error = ?2
+            WHERE id = ?1",
+        id,
+        error
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_pending_suggestion(conn: &mut SqliteConnection, id: i64) -> Result<()> {
+    sqlx::query!("DELETE FROM github_pending_suggestions WHERE id = ?1", id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use sqlx::{Connection, SqliteConnection};
+use subd_types::{SubscriptionUser, UserID};
+
+/// A fresh in-memory database with every migration applied
+pub async fn test_conn() -> anyhow::Result<SqliteConnection> {
+    let mut conn = SqliteConnection::connect(":memory:").await?;
+    sqlx::migrate!().run(&mut conn).await?;
+
+    Ok(conn)
+}
+
+/// A user that has chatted as `login`
+pub async fn chatter(conn: &mut SqliteConnection, twitch_id: &str, login: &str) -> UserID {
+    crate::create_twitch_user_chat(conn, twitch_id, login)
+        .await
+        .unwrap();
+    crate::get_user_from_twitch_user(conn, twitch_id)
+        .await
+        .unwrap()
+}
+
+pub fn sub_user(id: &str, login: &str, display_name: &str) -> SubscriptionUser {
+    SubscriptionUser {
+        id: id.to_string(),
+        login: login.to_string(),
+        display_name: display_name.to_string(),
+    }
+}
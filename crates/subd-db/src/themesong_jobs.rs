@@ -0,0 +1,363 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+use subd_types::UserID;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemesongJobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    /// Replaced by a newer request from the same user
+    Cancelled,
+}
+
+impl ThemesongJobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThemesongJobStatus::Queued => "queued",
+            ThemesongJobStatus::Running => "running",
+            ThemesongJobStatus::Done => "done",
+            ThemesongJobStatus::Failed => "failed",
+            ThemesongJobStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for ThemesongJobStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(status: &str) -> Result<Self> {
+        match status {
+            "queued" => Ok(ThemesongJobStatus::Queued),
+            "running" => Ok(ThemesongJobStatus::Running),
+            "done" => Ok(ThemesongJobStatus::Done),
+            "failed" => Ok(ThemesongJobStatus::Failed),
+            "cancelled" => Ok(ThemesongJobStatus::Cancelled),
+            _ => Err(anyhow!("unknown themesong job status: {:?}", status)),
+        }
+    }
+}
+
+/// A `!themesong` that hasn't been queued yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewThemesongJob {
+    pub user_id: UserID,
+    pub display_name: String,
+    pub requested_by: UserID,
+    pub requester_name: String,
+    pub url: String,
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub max_seconds: f64,
+    pub approve: bool,
+    /// Higher goes first
+    pub priority: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThemesongJob {
+    pub id: i64,
+    pub user_id: UserID,
+    pub display_name: String,
+    pub requested_by: UserID,
+    pub requester_name: String,
+    pub url: String,
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub max_seconds: f64,
+    pub approve: bool,
+    pub priority: i64,
+    pub status: String,
+    /// Including the one that's running
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub run_after: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ThemesongJob {
+    pub fn status(&self) -> Result<ThemesongJobStatus> {
+        self.status.parse()
+    }
+}
+
+pub async fn add_themesong_job(conn: &mut SqliteConnection, job: &NewThemesongJob) -> Result<i64> {
+    let id = sqlx::query!(
+        "INSERT INTO themesong_download_jobs
+            (user_id, display_name, requested_by, requester_name, url,
+             start_seconds, end_seconds, max_seconds, approve, priority)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        job.user_id,
+        job.display_name,
+        job.requested_by,
+        job.requester_name,
+        job.url,
+        job.start_seconds,
+        job.end_seconds,
+        job.max_seconds,
+        job.approve,
+        job.priority
+    )
+    .execute(&mut *conn)
+    .await?
+    .last_insert_rowid();
+
+    Ok(id)
+}
+
+pub async fn get_themesong_job(
+    conn: &mut SqliteConnection,
+    id: i64,
+) -> Result<Option<ThemesongJob>> {
+    let job = sqlx::query_as!(
+        ThemesongJob,
+        r#"
+        SELECT id as "id!: i64", user_id, display_name, requested_by, requester_name, url,
+               start_seconds, end_seconds, max_seconds, approve as "approve: bool",
+               priority as "priority!: i64", status, attempts as "attempts!: i64", last_error,
+               run_after as "run_after: DateTime<Utc>",
+               created_at as "created_at: DateTime<Utc>"
+            FROM themesong_download_jobs
+            WHERE id = ?1
+        "#,
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(job)
+}
+
+/// Cancel every job `user_id` has queued or running, returning their ids
+pub async fn cancel_themesong_jobs_for(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+) -> Result<Vec<i64>> {
+    let cancelled = sqlx::query!(
+        r#"
+        SELECT id as "id!: i64"
+            FROM themesong_download_jobs
+            WHERE user_id = ?1 AND status IN ('queued', 'running')
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+
+    sqlx::query!(
+        "UPDATE themesong_download_jobs SET status = 'cancelled'
+            WHERE user_id = ?1 AND status IN ('queued', 'running')",
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(cancelled)
+}
+
+/// How many queued jobs will start before job `id`
+pub async fn queued_themesong_jobs_ahead(conn: &mut SqliteConnection, id: i64) -> Result<i64> {
+    let record = sqlx::query!(
+        r#"
+        SELECT count(*) as "count!: i64"
+            FROM themesong_download_jobs AS queued, themesong_download_jobs AS job
+            WHERE job.id = ?1 AND queued.status = 'queued'
+              AND (queued.priority > job.priority
+                   OR (queued.priority = job.priority AND queued.id < job.id))
+        "#,
+        id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(record.count)
+}
+
+/// Mark the next job that's allowed to run as running, and count the attempt.
+/// Higher priority jobs go first, then the oldest.
+pub async fn claim_next_themesong_job(
+    conn: &mut SqliteConnection,
+    now: DateTime<Utc>,
+) -> Result<Option<ThemesongJob>> {
+    let next = sqlx::query!(
+        r#"
+        SELECT id as "id!: i64"
+            FROM themesong_download_jobs
+            WHERE status = 'queued' AND run_after <= datetime(?1)
+            ORDER BY priority DESC, id
+            LIMIT 1
+        "#,
+        now
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let id = match next {
+        Some(next) => next.id,
+        None => return Ok(None),
+    };
+
+    sqlx::query!(
+        "UPDATE themesong_download_jobs SET status = 'running', attempts = attempts + 1
+            WHERE id = ?1",
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    get_themesong_job(conn, id).await
+}
+
+/// Put a running job back in the queue until `run_after`
+pub async fn retry_themesong_job(
+    conn: &mut SqliteConnection,
+    id: i64,
+    error: &str,
+    run_after: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE themesong_download_jobs
+            SET status = 'queued', last_error = ?2, run_after = datetime(?3)
+            WHERE id = ?1 AND status = 'running'",
+        id,
+        error,
+        run_after
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Finish a running job. Jobs that were cancelled in the meantime stay cancelled.
+pub async fn finish_themesong_job(
+    conn: &mut SqliteConnection,
+    id: i64,
+    status: ThemesongJobStatus,
+    error: Option<&str>,
+) -> Result<()> {
+    let status = status.as_str();
+    sqlx::query!(
+        "UPDATE themesong_download_jobs SET status = ?2, last_error = ?3
+            WHERE id = ?1 AND status = 'running'",
+        id,
+        status,
+        error
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Jobs that were running when we shut down start over
+pub async fn requeue_running_themesong_jobs(conn: &mut SqliteConnection) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE themesong_download_jobs SET status = 'queued' WHERE status = 'running'"
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+
+    #[tokio::test]
+    async fn test_themesong_jobs() -> anyhow::Result<()> {
+        let mut conn = test_conn().await?;
+
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let prime = chatter(&mut conn, "2", "theprimeagen").await;
+
+        let job = NewThemesongJob {
+            user_id: nyx,
+            display_name: "nyxkrage".to_string(),
+            requested_by: nyx,
+            requester_name: "nyxkrage".to_string(),
+            url: "https://youtu.be/SkypZuY6ZvA".to_string(),
+            start_seconds: 65.,
+            end_seconds: 72.,
+            max_seconds: 10.,
+            approve: false,
+            priority: 0,
+        };
+        let first = add_themesong_job(&mut conn, &job).await?;
+        let other = add_themesong_job(
+            &mut conn,
+            &NewThemesongJob {
+                user_id: prime,
+                display_name: "theprimeagen".to_string(),
+                ..job.clone()
+            },
+        )
+        .await?;
+        assert_eq!(queued_themesong_jobs_ahead(&mut conn, other).await?, 1);
+
+        // A new request from the same user replaces the old one
+        assert_eq!(
+            cancel_themesong_jobs_for(&mut conn, &nyx).await?,
+            vec![first]
+        );
+        let second = add_themesong_job(&mut conn, &job).await?;
+        assert_eq!(queued_themesong_jobs_ahead(&mut conn, second).await?, 1);
+
+        let now = Utc::now();
+        let claimed = claim_next_themesong_job(&mut conn, now).await?.unwrap();
+        assert_eq!(claimed.id, other);
+        assert_eq!(claimed.status()?, ThemesongJobStatus::Running);
+        assert_eq!(claimed.attempts, 1);
+
+        // Retries wait their turn
+        let retry_at = now + chrono::Duration::minutes(1);
+        retry_themesong_job(&mut conn, other, "connection reset", retry_at).await?;
+        let claimed = claim_next_themesong_job(&mut conn, now).await?.unwrap();
+        assert_eq!(claimed.id, second);
+        assert_eq!(claim_next_themesong_job(&mut conn, now).await?, None);
+
+        // A supporter's job skips ahead of the retry
+        let supporter = add_themesong_job(
+            &mut conn,
+            &NewThemesongJob {
+                priority: 2,
+                ..job.clone()
+            },
+        )
+        .await?;
+        assert_eq!(queued_themesong_jobs_ahead(&mut conn, supporter).await?, 0);
+        assert_eq!(queued_themesong_jobs_ahead(&mut conn, other).await?, 1);
+
+        let later = now + chrono::Duration::minutes(2);
+        let claimed = claim_next_themesong_job(&mut conn, later).await?.unwrap();
+        assert_eq!(claimed.id, supporter);
+        let retried = claim_next_themesong_job(&mut conn, later).await?.unwrap();
+        assert_eq!(retried.id, other);
+        assert_eq!(retried.attempts, 2);
+        assert_eq!(retried.last_error.as_deref(), Some("connection reset"));
+
+        // Cancelled while running, so finishing doesn't bring it back
+        cancel_themesong_jobs_for(&mut conn, &nyx).await?;
+        finish_themesong_job(&mut conn, second, ThemesongJobStatus::Done, None).await?;
+        let second = get_themesong_job(&mut conn, second).await?.unwrap();
+        assert_eq!(second.status()?, ThemesongJobStatus::Cancelled);
+
+        assert_eq!(requeue_running_themesong_jobs(&mut conn).await?, 1);
+        let other = get_themesong_job(&mut conn, other).await?.unwrap();
+        assert_eq!(other.status()?, ThemesongJobStatus::Queued);
+
+        // Anything else in the table is a mistake, not a job to run again
+        assert!("paused".parse::<ThemesongJobStatus>().is_err());
+
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
-use subd_gh::is_user_sponsoring;
+use subd_gh::{GithubApi, GithubClient};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     println!("Hello world");
 
-    is_user_sponsoring("jesseleite").await?;
+    let github = GithubClient::from_env();
+    println!("{:?}", github.is_sponsoring("jesseleite").await?);
 
     Ok(())
 }
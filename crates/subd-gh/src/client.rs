@@ -0,0 +1,129 @@
+//! Shared GitHub GraphQL transport that keeps track of our rate limit.
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, TimeZone, Utc};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use once_cell::sync::Lazy;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{GithubError, GithubResult, GraphqlResponse};
+
+const GITHUB_GRAPHQL_URL: &str = "https://api.github.com/graphql";
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+static RATE_LIMIT: Lazy<Mutex<Option<RateLimit>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub remaining: u64,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<i64>().ok())
+        };
+
+        Some(Self {
+            remaining: header("x-ratelimit-remaining")? as u64,
+            reset_at: Utc.timestamp(header("x-ratelimit-reset")?, 0),
+        })
+    }
+
+    /// Whether we have to wait for the reset before making another request
+    pub fn is_exhausted(&self, now: DateTime<Utc>) -> bool {
+        self.remaining == 0 && now < self.reset_at
+    }
+}
+
+/// The rate limit GitHub reported on our last request
+pub fn rate_limit() -> Option<RateLimit> {
+    *RATE_LIMIT.lock().unwrap()
+}
+
+/// The account whose sponsors get perks. Defaults to the stream's GitHub account.
+pub fn sponsorable_login() -> String {
+    std::env::var("GITHUB_SPONSORABLE").unwrap_or_else(|_| "tjdevries".to_string())
+}
+
+/// Send a GraphQL request with the shared client.
+/// Fails without sending anything if we already know we're out of requests.
+pub(crate) async fn post_graphql<B: Serialize, R: DeserializeOwned>(
+    token: Option<&str>,
+    body: &B,
+) -> GithubResult<R> {
+    if let Some(limit) = rate_limit() {
+        if limit.is_exhausted(Utc::now()) {
+            return Err(GithubError::RateLimited {
+                reset_at: Some(limit.reset_at),
+            });
+        }
+    }
+
+    let token = token.ok_or(GithubError::Unauthorized)?;
+    let authorization = HeaderValue::from_str(&format!("token {}", token))
+        .map_err(|_| GithubError::Unauthorized)?;
+
+    let res = CLIENT
+        .post(GITHUB_GRAPHQL_URL)
+        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+        .header(USER_AGENT, HeaderValue::from_static("subd"))
+        .header(AUTHORIZATION, authorization)
+        .json(body)
+        .send()
+        .await?;
+
+    let limit = RateLimit::from_headers(res.headers());
+    if let Some(limit) = limit {
+        *RATE_LIMIT.lock().unwrap() = Some(limit);
+    }
+
+    match res.status() {
+        StatusCode::UNAUTHORIZED => return Err(GithubError::Unauthorized),
+        StatusCode::TOO_MANY_REQUESTS => {
+            return Err(GithubError::RateLimited {
+                reset_at: limit.map(|limit| limit.reset_at),
+            })
+        }
+        // GitHub also uses 403 for running out of requests
+        StatusCode::FORBIDDEN if limit.map_or(false, |limit| limit.remaining == 0) => {
+            return Err(GithubError::RateLimited {
+                reset_at: limit.map(|limit| limit.reset_at),
+            })
+        }
+        StatusCode::FORBIDDEN => return Err(GithubError::Unauthorized),
+        status if !status.is_success() => {
+            return Err(GithubError::Network(format!("GitHub returned {}", status)))
+        }
+        _ => {}
+    }
+
+    res.json::<GraphqlResponse<R>>().await?.into_result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1657300000"));
+
+        let limit = RateLimit::from_headers(&headers).unwrap();
+        assert_eq!(limit.remaining, 0);
+        assert!(limit.is_exhausted(Utc.timestamp(1657299999, 0)));
+        assert!(!limit.is_exhausted(Utc.timestamp(1657300000, 0)));
+
+        assert!(RateLimit::from_headers(&HeaderMap::new()).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use thiserror::Error;
+
+pub type GithubResult<T> = std::result::Result<T, GithubError>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum GithubError {
+    #[error("github user not found: {0}")]
+    NotFound(String),
+
+    /// Missing, expired or under-scoped `GITHUB_ACCESS` token
+    #[error("not authorized to use the GitHub API")]
+    Unauthorized,
+
+    #[error("GitHub rate limit exhausted until {reset_at:?}")]
+    RateLimited { reset_at: Option<DateTime<Utc>> },
+
+    #[error("could not reach GitHub: {0}")]
+    Network(String),
+
+    /// GitHub answered, but not with anything we understand
+    #[error("unexpected response from GitHub: {0}")]
+    Response(String),
+}
+
+impl From<reqwest::Error> for GithubError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            GithubError::Response(err.to_string())
+        } else {
+            GithubError::Network(err.to_string())
+        }
+    }
+}
+
+/// An entry of the `errors` list. GitHub adds a `type` that graphql_client drops.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct GraphqlError {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GraphqlResponse<T> {
+    pub data: Option<T>,
+    #[serde(default)]
+    pub errors: Vec<GraphqlError>,
+}
+
+impl<T> GraphqlResponse<T> {
+    /// The data, or the most specific error GitHub gave us.
+    /// Partial data is still returned, since a missing user just comes back as `null`.
+    pub fn into_result(self) -> GithubResult<T> {
+        let has_kind = |kind: &str| self.errors.iter().any(|e| e.kind.as_deref() == Some(kind));
+
+        if has_kind("RATE_LIMITED") {
+            return Err(GithubError::RateLimited { reset_at: None });
+        }
+
+        match self.data {
+            Some(data) => Ok(data),
+            None if has_kind("NOT_FOUND") => {
+                Err(GithubError::NotFound(self.errors[0].message.clone()))
+            }
+            None => Err(GithubError::Response(
+                self.errors
+                    .iter()
+                    .map(|e| e.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(json: &str) -> GraphqlResponse<serde_json::Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn maps_graphql_errors() {
+        let rate_limited = response(
+            r#"{ "data": null, "errors": [{ "type": "RATE_LIMITED", "message": "API rate limit exceeded" }] }"#,
+        );
+        assert_eq!(
+            rate_limited.into_result(),
+            Err(GithubError::RateLimited { reset_at: None })
+        );
+
+        let not_found = response(
+            r#"{ "errors": [{ "type": "NOT_FOUND", "message": "Could not resolve to a User with the login of 'nope'." }] }"#,
+        );
+        assert!(matches!(
+            not_found.into_result(),
+            Err(GithubError::NotFound(_))
+        ));
+
+        let partial = response(
+            r#"{ "data": { "user": null }, "errors": [{ "type": "NOT_FOUND", "message": "..." }] }"#,
+        );
+        assert!(partial.into_result().unwrap()["user"].is_null());
+    }
+}
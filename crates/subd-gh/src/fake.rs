@@ -0,0 +1,252 @@
+//! In-memory `GithubApi` for tests, so nothing has to reach api.github.com.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use subd_types::{
+    GithubIssue, GithubIssueState, GithubSponsor, GithubSponsorStatus, GithubSponsorship,
+    GithubUser,
+};
+
+use crate::{GithubApi, GithubError, GithubResult};
+
+#[derive(Debug, Clone, Default)]
+struct FakeUser {
+    user: GithubUser,
+    status: GithubSponsorStatus,
+    verification_sources: Vec<String>,
+}
+
+impl FakeUser {
+    fn new(login: &str) -> Self {
+        Self {
+            user: GithubUser {
+                id: format!("gh-{}", login.to_lowercase()),
+                login: login.to_string(),
+                name: login.to_string(),
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FakeGithub {
+    /// Keyed by lowercase login, since GitHub logins are case insensitive
+    users: HashMap<String, FakeUser>,
+    issues: Mutex<Vec<GithubIssue>>,
+    failure: Mutex<Option<GithubError>>,
+    requests: AtomicUsize,
+}
+
+impl FakeGithub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a user that exists on GitHub but doesn't sponsor
+    pub fn with_user(mut self, login: &str) -> Self {
+        self.users
+            .insert(login.to_lowercase(), FakeUser::new(login));
+        self
+    }
+
+    /// Make `login` a sponsor. `None` is a custom amount without a tier.
+    pub fn with_sponsor(mut self, login: &str, sponsorship: Option<GithubSponsorship>) -> Self {
+        self.user_mut(login).status = GithubSponsorStatus {
+            is_sponsoring: true,
+            sponsorship,
+        };
+        self
+    }
+
+    /// Put some text in `login`'s bio or gists
+    pub fn with_verification_source(mut self, login: &str, text: &str) -> Self {
+        self.user_mut(login)
+            .verification_sources
+            .push(text.to_string());
+        self
+    }
+
+    pub fn with_issue(self, issue: GithubIssue) -> Self {
+        self.issues.lock().unwrap().push(issue);
+        self
+    }
+
+    /// Every issue, including the ones filed through `create_issue`
+    pub fn issues(&self) -> Vec<GithubIssue> {
+        self.issues.lock().unwrap().clone()
+    }
+
+    /// Fail every request with `error` until `recover` is called
+    pub fn fail_with(&self, error: GithubError) {
+        *self.failure.lock().unwrap() = Some(error);
+    }
+
+    pub fn recover(&self) {
+        *self.failure.lock().unwrap() = None;
+    }
+
+    /// How many requests have been made, failed ones included
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    fn user_mut(&mut self, login: &str) -> &mut FakeUser {
+        self.users
+            .entry(login.to_lowercase())
+            .or_insert_with(|| FakeUser::new(login))
+    }
+
+    fn request(&self) -> GithubResult<()> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        match self.failure.lock().unwrap().clone() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn find(&self, login: &str) -> GithubResult<&FakeUser> {
+        self.users
+            .get(&login.to_lowercase())
+            .ok_or_else(|| GithubError::NotFound(login.to_string()))
+    }
+}
+
+#[async_trait]
+impl GithubApi for FakeGithub {
+    async fn get_user(&self, login: &str) -> GithubResult<GithubUser> {
+        self.request()?;
+        Ok(self.find(login)?.user.clone())
+    }
+
+    async fn is_sponsoring(&self, login: &str) -> GithubResult<bool> {
+        self.request()?;
+        Ok(self.find(login)?.status.is_sponsoring)
+    }
+
+    async fn get_sponsor_statuses(
+        &self,
+        logins: &[String],
+    ) -> GithubResult<HashMap<String, GithubSponsorStatus>> {
+        self.request()?;
+        Ok(logins
+            .iter()
+            .map(|login| {
+                let status = self
+                    .find(login)
+                    .map(|user| user.status.clone())
+                    .unwrap_or_default();
+                (login.clone(), status)
+            })
+            .collect())
+    }
+
+    async fn get_sponsors(&self) -> GithubResult<Vec<GithubSponsor>> {
+        self.request()?;
+
+        let mut sponsors = self
+            .users
+            .values()
+            .filter(|user| user.status.is_sponsoring)
+            .map(|user| GithubSponsor {
+                login: user.user.login.clone(),
+                name: Some(user.user.name.clone()),
+                tier: user.status.sponsorship.clone(),
+                is_one_time: false,
+            })
+            .collect::<Vec<_>>();
+        sponsors.sort_by(|a, b| a.login.cmp(&b.login));
+
+        Ok(sponsors)
+    }
+
+    async fn get_verification_sources(&self, login: &str) -> GithubResult<Vec<String>> {
+        self.request()?;
+        Ok(self.find(login)?.verification_sources.clone())
+    }
+
+    async fn get_issue(&self, repo: &str, number: i64) -> GithubResult<GithubIssue> {
+        self.request()?;
+        self.issues
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|issue| issue.repo.eq_ignore_ascii_case(repo) && issue.number == number)
+            .cloned()
+            .ok_or_else(|| GithubError::NotFound(format!("{}#{}", repo, number)))
+    }
+
+    async fn create_issue(
+        &self,
+        repo: &str,
+        title: &str,
+        _body: &str,
+    ) -> GithubResult<GithubIssue> {
+        self.request()?;
+
+        let mut issues = self.issues.lock().unwrap();
+        let number = issues
+            .iter()
+            .filter(|issue| issue.repo.eq_ignore_ascii_case(repo))
+            .map(|issue| issue.number)
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let issue = GithubIssue {
+            repo: repo.to_string(),
+            number,
+            title: title.to_string(),
+            state: GithubIssueState::Open,
+            url: format!("https://github.com/{}/issues/{}", repo, number),
+            is_pull_request: false,
+        };
+        issues.push(issue.clone());
+
+        Ok(issue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_from_memory() {
+        let github = FakeGithub::new()
+            .with_user("nyxkrage")
+            .with_sponsor("JesseLeite", None)
+            .with_verification_source("jesseleite", "subd-1a2b3c4d");
+
+        assert_eq!(
+            github.get_user("jesseleite").await.unwrap().login,
+            "JesseLeite"
+        );
+        assert!(github.is_sponsoring("jesseleite").await.unwrap());
+        assert!(!github.is_sponsoring("nyxkrage").await.unwrap());
+        assert!(github
+            .has_verification_code("jesseleite", "subd-1a2b3c4d")
+            .await
+            .unwrap());
+        assert_eq!(
+            github.get_user("nope").await,
+            Err(GithubError::NotFound("nope".to_string()))
+        );
+
+        github.fail_with(GithubError::RateLimited { reset_at: None });
+        assert!(github.get_sponsor_status("jesseleite").await.is_err());
+        github.recover();
+        assert!(
+            github
+                .get_sponsor_status("jesseleite")
+                .await
+                .unwrap()
+                .is_sponsoring
+        );
+        assert_eq!(github.get_sponsors().await.unwrap()[0].login, "JesseLeite");
+        assert_eq!(github.requests(), 8);
+    }
+}
@@ -1,7 +1,21 @@
-use anyhow::Result;
-use graphql_client::{GraphQLQuery, Response};
-use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
-use subd_types::GithubUser;
+pub mod client;
+pub mod error;
+pub mod fake;
+pub mod sponsors;
+pub mod webhook;
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use graphql_client::GraphQLQuery;
+use subd_types::{
+    GithubIssue, GithubIssueState, GithubSponsor, GithubSponsorStatus, GithubSponsorship,
+    GithubUser,
+};
+
+pub use client::{rate_limit, sponsorable_login, RateLimit};
+pub use error::{GithubError, GithubResult};
+pub use fake::FakeGithub;
 
 // The paths are relative to the directory where your `Cargo.toml` is located.
 // Both json and the GraphQL schema language are supported as sources for the schema
@@ -21,77 +35,394 @@ pub struct IsSponsoring;
 )]
 pub struct GetUser;
 
-pub async fn get_github_user(login: &str) -> Result<Option<GithubUser>> {
-    let gh_token = String::from("token ")
-        + &std::env::var("GITHUB_ACCESS").expect("Should have GITHUB_ACCESS token");
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "gql/gh.schema.graphql",
+    query_path = "gql/get_verification_sources.query.graphql",
+    response_derives = "Debug"
+)]
+pub struct GetVerificationSources;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "gql/gh.schema.graphql",
+    query_path = "gql/get_sponsors.query.graphql",
+    response_derives = "Debug"
+)]
+pub struct GetSponsors;
 
-    let request_body = GetUser::build_query(get_user::Variables {
-        login: login.to_string(),
-    });
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "gql/gh.schema.graphql",
+    query_path = "gql/get_issue.query.graphql",
+    response_derives = "Debug"
+)]
+pub struct GetIssue;
 
-    let client = reqwest::Client::new();
-    let res = client
-        .post("https://api.github.com/graphql")
-        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-        .header(USER_AGENT, HeaderValue::from_static("subd"))
-        .header(AUTHORIZATION, HeaderValue::from_str(&gh_token).unwrap())
-        .json(&request_body)
-        .send()
-        .await?;
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "gql/gh.schema.graphql",
+    query_path = "gql/get_repository_id.query.graphql",
+    response_derives = "Debug"
+)]
+pub struct GetRepositoryId;
 
-    let response_body: Response<get_user::ResponseData> = res.json().await?;
-    if let None = response_body.data {
-        return Ok(None);
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "gql/gh.schema.graphql",
+    query_path = "gql/create_issue.mutation.graphql",
+    response_derives = "Debug"
+)]
+pub struct CreateIssue;
+
+/// GitHub's `URI` scalar, used by the issue queries. graphql_client needs the name as is.
+#[allow(clippy::upper_case_acronyms)]
+type URI = String;
+
+/// Everything we ask GitHub about. `GithubClient` talks to api.github.com,
+/// `FakeGithub` answers from memory for tests.
+#[async_trait]
+pub trait GithubApi: Send + Sync {
+    async fn get_user(&self, login: &str) -> GithubResult<GithubUser>;
+
+    /// Whether `login` sponsors the sponsorable account
+    async fn is_sponsoring(&self, login: &str) -> GithubResult<bool>;
+
+    /// Sponsor status for every login, keyed by the login as given
+    async fn get_sponsor_statuses(
+        &self,
+        logins: &[String],
+    ) -> GithubResult<HashMap<String, GithubSponsorStatus>>;
+
+    /// Everyone publicly sponsoring the sponsorable account
+    async fn get_sponsors(&self) -> GithubResult<Vec<GithubSponsor>>;
+
+    /// Places a user can put a verification code: their bio, and their public gists
+    async fn get_verification_sources(&self, login: &str) -> GithubResult<Vec<String>>;
+
+    /// Issue or pull request `number` of `repo` (`owner/name`)
+    async fn get_issue(&self, repo: &str, number: i64) -> GithubResult<GithubIssue>;
+
+    /// File a new issue on `repo` (`owner/name`)
+    async fn create_issue(&self, repo: &str, title: &str, body: &str) -> GithubResult<GithubIssue>;
+
+    async fn get_sponsor_status(&self, login: &str) -> GithubResult<GithubSponsorStatus> {
+        let login = login.to_string();
+        let mut statuses = self
+            .get_sponsor_statuses(std::slice::from_ref(&login))
+            .await?;
+
+        Ok(statuses.remove(&login).unwrap_or_default())
     }
 
-    let result = match response_body.data.unwrap().user {
-        Some(result) => result,
-        None => return Ok(None),
-    };
+    /// Whether `login` has put `code` in their bio or one of their recent public gists
+    async fn has_verification_code(&self, login: &str, code: &str) -> GithubResult<bool> {
+        Ok(contains_code(
+            &self.get_verification_sources(login).await?,
+            code,
+        ))
+    }
+}
 
-    Ok(Some(GithubUser {
-        id: result.id,
-        login: result.login,
-        name: match result.name {
-            Some(name) => name,
-            None => "UNKNOWN NAME".to_string(),
-        },
-    }))
+/// The real GraphQL client
+#[derive(Debug, Clone)]
+pub struct GithubClient {
+    token: Option<String>,
+    pub sponsorable: String,
 }
 
-pub async fn is_user_sponsoring(github_user: &str) -> Result<bool> {
-    // TODO: Could probably make this static
-    let gh_token = String::from("token ")
-        + &std::env::var("GITHUB_ACCESS").expect("Should have GITHUB_ACCESS token");
+impl GithubClient {
+    pub fn new(token: Option<String>, sponsorable: impl Into<String>) -> Self {
+        Self {
+            token,
+            sponsorable: sponsorable.into(),
+        }
+    }
+
+    /// Uses `GITHUB_ACCESS` and `GITHUB_SPONSORABLE`.
+    /// A missing token shows up as `GithubError::Unauthorized` on the first request.
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("GITHUB_ACCESS").ok(), sponsorable_login())
+    }
+
+    fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
 
-    let request_body = IsSponsoring::build_query(is_sponsoring::Variables {
-        name: github_user.to_string(),
-    });
+#[async_trait]
+impl GithubApi for GithubClient {
+    async fn get_user(&self, login: &str) -> GithubResult<GithubUser> {
+        let request_body = GetUser::build_query(get_user::Variables {
+            login: login.to_string(),
+        });
 
-    let client = reqwest::Client::new();
-    let res = client
-        .post("https://api.github.com/graphql")
-        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-        .header(USER_AGENT, HeaderValue::from_static("subd"))
-        .header(AUTHORIZATION, HeaderValue::from_str(&gh_token).unwrap())
-        .json(&request_body)
-        .send()
-        .await?;
+        let data: get_user::ResponseData =
+            client::post_graphql(self.token(), &request_body).await?;
+        let result = data
+            .user
+            .ok_or_else(|| GithubError::NotFound(login.to_string()))?;
 
-    let response_body: Response<is_sponsoring::ResponseData> = res.json().await?;
-    if let Some(errors) = response_body.errors {
-        println!("ERRORS: {:?}", errors);
-        return Ok(false);
+        Ok(GithubUser {
+            id: result.id,
+            login: result.login,
+            name: match result.name {
+                Some(name) => name,
+                None => "UNKNOWN NAME".to_string(),
+            },
+        })
     }
 
-    Ok(response_body.data.unwrap().user.unwrap().is_sponsored_by)
+    async fn is_sponsoring(&self, login: &str) -> GithubResult<bool> {
+        let request_body = IsSponsoring::build_query(is_sponsoring::Variables {
+            sponsorable: self.sponsorable.clone(),
+            name: login.to_string(),
+        });
+
+        let data: is_sponsoring::ResponseData =
+            client::post_graphql(self.token(), &request_body).await?;
+        let sponsorable = data
+            .user
+            .ok_or_else(|| GithubError::NotFound(self.sponsorable.clone()))?;
+
+        Ok(sponsorable.is_sponsored_by)
+    }
+
+    async fn get_sponsor_statuses(
+        &self,
+        logins: &[String],
+    ) -> GithubResult<HashMap<String, GithubSponsorStatus>> {
+        sponsors::fetch_sponsor_statuses(self.token(), &self.sponsorable, logins).await
+    }
+
+    async fn get_sponsors(&self) -> GithubResult<Vec<GithubSponsor>> {
+        let mut sponsors = vec![];
+        let mut after = None;
+        loop {
+            let request_body = GetSponsors::build_query(get_sponsors::Variables {
+                sponsorable: self.sponsorable.clone(),
+                after,
+            });
+
+            let data: get_sponsors::ResponseData =
+                client::post_graphql(self.token(), &request_body).await?;
+            let sponsorships = data
+                .user
+                .ok_or_else(|| GithubError::NotFound(self.sponsorable.clone()))?
+                .sponsorships_as_maintainer;
+
+            sponsors.extend(
+                sponsorships
+                    .nodes
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter_map(sponsor_from_node),
+            );
+
+            if !sponsorships.page_info.has_next_page {
+                return Ok(sponsors);
+            }
+            after = sponsorships.page_info.end_cursor;
+        }
+    }
+
+    async fn get_verification_sources(&self, login: &str) -> GithubResult<Vec<String>> {
+        let request_body =
+            GetVerificationSources::build_query(get_verification_sources::Variables {
+                login: login.to_string(),
+            });
+
+        let data: get_verification_sources::ResponseData =
+            client::post_graphql(self.token(), &request_body).await?;
+        let user = data
+            .user
+            .ok_or_else(|| GithubError::NotFound(login.to_string()))?;
+
+        Ok(verification_sources(user))
+    }
+
+    async fn get_issue(&self, repo: &str, number: i64) -> GithubResult<GithubIssue> {
+        use get_issue::GetIssueRepositoryIssueOrPullRequest as IssueOrPullRequest;
+
+        let (owner, name) = split_repo(repo)?;
+        let request_body = GetIssue::build_query(get_issue::Variables {
+            owner,
+            name,
+            number,
+        });
+
+        let data: get_issue::ResponseData =
+            client::post_graphql(self.token(), &request_body).await?;
+        let issue = data
+            .repository
+            .ok_or_else(|| GithubError::NotFound(repo.to_string()))?
+            .issue_or_pull_request
+            .ok_or_else(|| GithubError::NotFound(format!("{}#{}", repo, number)))?;
+
+        Ok(match issue {
+            IssueOrPullRequest::Issue(issue) => GithubIssue {
+                repo: repo.to_string(),
+                number: issue.number,
+                title: issue.title,
+                state: match issue.state {
+                    get_issue::IssueState::CLOSED => GithubIssueState::Closed,
+                    _ => GithubIssueState::Open,
+                },
+                url: issue.url,
+                is_pull_request: false,
+            },
+            IssueOrPullRequest::PullRequest(pr) => GithubIssue {
+                repo: repo.to_string(),
+                number: pr.number,
+                title: pr.title,
+                state: match pr.state {
+                    get_issue::PullRequestState::CLOSED => GithubIssueState::Closed,
+                    get_issue::PullRequestState::MERGED => GithubIssueState::Merged,
+                    _ => GithubIssueState::Open,
+                },
+                url: pr.url,
+                is_pull_request: true,
+            },
+        })
+    }
+
+    async fn create_issue(&self, repo: &str, title: &str, body: &str) -> GithubResult<GithubIssue> {
+        let (owner, name) = split_repo(repo)?;
+        let request_body =
+            GetRepositoryId::build_query(get_repository_id::Variables { owner, name });
+
+        // The mutation wants the repository's node id, not its name
+        let data: get_repository_id::ResponseData =
+            client::post_graphql(self.token(), &request_body).await?;
+        let repository_id = data
+            .repository
+            .ok_or_else(|| GithubError::NotFound(repo.to_string()))?
+            .id;
+
+        let request_body = CreateIssue::build_query(create_issue::Variables {
+            repository_id,
+            title: title.to_string(),
+            body: Some(body.to_string()),
+        });
+
+        let data: create_issue::ResponseData =
+            client::post_graphql(self.token(), &request_body).await?;
+        let issue = data
+            .create_issue
+            .and_then(|payload| payload.issue)
+            .ok_or_else(|| GithubError::Response(format!("no issue created on {}", repo)))?;
+
+        Ok(GithubIssue {
+            repo: repo.to_string(),
+            number: issue.number,
+            title: issue.title,
+            state: match issue.state {
+                create_issue::IssueState::CLOSED => GithubIssueState::Closed,
+                _ => GithubIssueState::Open,
+            },
+            url: issue.url,
+            is_pull_request: false,
+        })
+    }
+}
+
+/// Split `owner/name`. Anything else can't be a repository.
+fn split_repo(repo: &str) -> GithubResult<(String, String)> {
+    match repo.split_once('/') {
+        Some((owner, name)) if !owner.is_empty() && !name.is_empty() => {
+            Ok((owner.to_string(), name.to_string()))
+        }
+        _ => Err(GithubError::NotFound(repo.to_string())),
+    }
+}
+
+/// Sponsors whose account has since been deleted come back without an entity.
+/// Our own token can see private sponsorships too, and those stay private.
+fn sponsor_from_node(
+    node: get_sponsors::GetSponsorsUserSponsorshipsAsMaintainerNodes,
+) -> Option<GithubSponsor> {
+    use get_sponsors::GetSponsorsUserSponsorshipsAsMaintainerNodesSponsorEntity as Entity;
+
+    if !matches!(node.privacy_level, get_sponsors::SponsorshipPrivacy::PUBLIC) {
+        return None;
+    }
+
+    let (login, name) = match node.sponsor_entity? {
+        Entity::User(user) => (user.login, user.name),
+        Entity::Organization(org) => (org.login, org.name),
+    };
+
+    Some(GithubSponsor {
+        login,
+        name,
+        tier: node.tier.map(|tier| GithubSponsorship {
+            tier_name: tier.name,
+            monthly_dollars: tier.monthly_price_in_dollars,
+        }),
+        is_one_time: node.is_one_time_payment,
+    })
+}
+
+fn verification_sources(user: get_verification_sources::GetVerificationSourcesUser) -> Vec<String> {
+    let mut sources: Vec<String> = user.bio.into_iter().collect();
+
+    for gist in user.gists.nodes.into_iter().flatten().flatten() {
+        sources.extend(gist.description);
+        for file in gist.files.into_iter().flatten().flatten() {
+            sources.extend(file.text);
+        }
+    }
+
+    sources
+}
+
+fn contains_code(sources: &[String], code: &str) -> bool {
+    sources.iter().any(|source| source.contains(code))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_code_in_any_source() {
+        let sources = vec![
+            "neovim enjoyer".to_string(),
+            "my dotfiles\nsubd-1a2b3c4d\n".to_string(),
+        ];
+
+        assert!(contains_code(&sources, "subd-1a2b3c4d"));
+        assert!(!contains_code(&sources, "subd-00000000"));
+        assert!(!contains_code(&[], "subd-1a2b3c4d"));
+    }
+
+    fn sponsor_node(privacy: &str) -> get_sponsors::GetSponsorsUserSponsorshipsAsMaintainerNodes {
+        serde_json::from_value(serde_json::json!({
+            "isOneTimePayment": false,
+            "privacyLevel": privacy,
+            "sponsorEntity": { "__typename": "User", "login": "JesseLeite", "name": "Jesse" },
+            "tier": { "name": "$10 a month", "monthlyPriceInDollars": 10 },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn skips_private_sponsors() {
+        let sponsor = sponsor_from_node(sponsor_node("PUBLIC")).unwrap();
+        assert_eq!(sponsor.display_name(), "Jesse");
+        assert_eq!(sponsor.tier.unwrap().monthly_dollars, 10);
+
+        assert_eq!(sponsor_from_node(sponsor_node("PRIVATE")), None);
+    }
+
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn splits_repos() {
+        assert_eq!(
+            split_repo("tjdevries/subd"),
+            Ok(("tjdevries".to_string(), "subd".to_string()))
+        );
+        assert!(split_repo("subd").is_err());
+        assert!(split_repo("tjdevries/").is_err());
     }
 }
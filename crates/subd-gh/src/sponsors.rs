@@ -0,0 +1,188 @@
+//! Check many logins against our sponsors in as few requests as possible.
+//!
+//! graphql_client needs queries known at compile time, so the batch query is
+//! built by hand: one aliased field per login, with the logins as variables.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use subd_types::{GithubSponsorStatus, GithubSponsorship};
+
+use crate::client;
+use crate::error::{GithubError, GithubResult};
+
+/// Each login costs two fields, so keep batches well under GitHub's node limits
+pub const MAX_BATCH_SIZE: usize = 50;
+
+/// Build the query and variables for one batch of logins
+fn build_query(sponsorable: &str, logins: &[String]) -> Value {
+    let params = (0..logins.len())
+        .map(|i| format!(", $l{}: String!", i))
+        .collect::<String>();
+    let is_sponsored = (0..logins.len())
+        .map(|i| format!(" s{i}: isSponsoredBy(accountLogin: $l{i})", i = i))
+        .collect::<String>();
+    // The tier is only visible when the token belongs to the sponsorable account
+    let tiers = (0..logins.len())
+        .map(|i| {
+            format!(
+                " u{i}: user(login: $l{i}) {{ sponsorshipForViewerAsSponsorable {{ tier {{ name monthlyPriceInDollars }} }} }}",
+                i = i
+            )
+        })
+        .collect::<String>();
+
+    let mut variables = Map::new();
+    variables.insert("sponsorable".to_string(), json!(sponsorable));
+    for (i, login) in logins.iter().enumerate() {
+        variables.insert(format!("l{}", i), json!(login));
+    }
+
+    json!({
+        "query": format!(
+            "query SponsorStatuses($sponsorable: String!{}) {{ sponsorable: user(login: $sponsorable) {{{} }}{} }}",
+            params, is_sponsored, tiers
+        ),
+        "variables": variables,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tier {
+    name: String,
+    monthly_price_in_dollars: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Sponsorship {
+    tier: Option<Tier>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SponsorUser {
+    sponsorship_for_viewer_as_sponsorable: Option<Sponsorship>,
+}
+
+/// Pull each login's status out of a batch response.
+/// Logins that don't exist on GitHub come back as not sponsoring.
+fn parse_response(
+    sponsorable: &str,
+    logins: &[String],
+    mut data: Map<String, Value>,
+) -> GithubResult<HashMap<String, GithubSponsorStatus>> {
+    let sponsored_by = data
+        .remove("sponsorable")
+        .filter(|value| !value.is_null())
+        .ok_or_else(|| GithubError::NotFound(sponsorable.to_string()))?;
+
+    let mut statuses = HashMap::new();
+    for (i, login) in logins.iter().enumerate() {
+        let is_sponsoring = sponsored_by
+            .get(format!("s{}", i))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+
+        let sponsorship = match data.remove(&format!("u{}", i)) {
+            Some(user) if is_sponsoring && !user.is_null() => {
+                serde_json::from_value::<SponsorUser>(user)
+                    .map_err(|err| GithubError::Response(err.to_string()))?
+                    .sponsorship_for_viewer_as_sponsorable
+                    .and_then(|sponsorship| sponsorship.tier)
+                    .map(|tier| GithubSponsorship {
+                        tier_name: tier.name,
+                        monthly_dollars: tier.monthly_price_in_dollars,
+                    })
+            }
+            _ => None,
+        };
+
+        statuses.insert(
+            login.clone(),
+            GithubSponsorStatus {
+                is_sponsoring,
+                sponsorship,
+            },
+        );
+    }
+
+    Ok(statuses)
+}
+
+/// Sponsor status of `sponsorable` for every login, keyed by the login as given.
+/// Sends one request per `MAX_BATCH_SIZE` logins.
+pub(crate) async fn fetch_sponsor_statuses(
+    token: Option<&str>,
+    sponsorable: &str,
+    logins: &[String],
+) -> GithubResult<HashMap<String, GithubSponsorStatus>> {
+    let mut statuses = HashMap::new();
+    for batch in logins.chunks(MAX_BATCH_SIZE) {
+        let data = client::post_graphql(token, &build_query(sponsorable, batch)).await?;
+        statuses.extend(parse_response(sponsorable, batch, data)?);
+    }
+
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logins(logins: &[&str]) -> Vec<String> {
+        logins.iter().map(|login| login.to_string()).collect()
+    }
+
+    #[test]
+    fn builds_one_field_per_login() {
+        let body = build_query("tjdevries", &logins(&["jesseleite", "nyxkrage"]));
+
+        let query = body["query"].as_str().unwrap();
+        assert!(query.contains("$sponsorable: String!, $l0: String!, $l1: String!"));
+        assert!(query.contains("s1: isSponsoredBy(accountLogin: $l1)"));
+        assert!(query.contains("u0: user(login: $l0)"));
+        assert!(!query.contains("jesseleite"));
+
+        assert_eq!(body["variables"]["sponsorable"], "tjdevries");
+        assert_eq!(body["variables"]["l1"], "nyxkrage");
+    }
+
+    #[test]
+    fn parses_batch_response() {
+        let data: Map<String, Value> = serde_json::from_str(
+            r#"{
+                "sponsorable": { "s0": true, "s1": false, "s2": true },
+                "u0": { "sponsorshipForViewerAsSponsorable": { "tier": { "name": "$10 a month", "monthlyPriceInDollars": 10 } } },
+                "u1": { "sponsorshipForViewerAsSponsorable": null },
+                "u2": { "sponsorshipForViewerAsSponsorable": { "tier": null } }
+            }"#,
+        )
+        .unwrap();
+
+        let statuses = parse_response(
+            "tjdevries",
+            &logins(&["jesseleite", "nyxkrage", "custom"]),
+            data,
+        )
+        .unwrap();
+
+        let jesse = &statuses["jesseleite"];
+        assert!(jesse.is_sponsoring);
+        assert_eq!(jesse.sponsorship.as_ref().unwrap().monthly_dollars, 10);
+        assert_eq!(statuses["nyxkrage"], GithubSponsorStatus::default());
+        assert!(statuses["custom"].is_sponsoring);
+        assert!(statuses["custom"].sponsorship.is_none());
+    }
+
+    #[test]
+    fn missing_sponsorable_is_an_error() {
+        let data: Map<String, Value> = serde_json::from_str(r#"{ "sponsorable": null }"#).unwrap();
+
+        assert!(matches!(
+            parse_response("tjdevries", &logins(&["jesseleite"]), data),
+            Err(GithubError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+//! Verification and decoding for GitHub webhook deliveries.
+//!
+//! GitHub signs every delivery with the webhook secret:
+//! `sha256=` + hex(HMAC-SHA256(secret, body)), sent as `X-Hub-Signature-256`.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use subd_types::{
+    GithubRepoActivity, GithubRepoEvent, GithubSponsorship, GithubSponsorshipAction,
+    GithubSponsorshipEvent,
+};
+
+pub const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+pub const EVENT_HEADER: &str = "X-GitHub-Event";
+pub const DELIVERY_HEADER: &str = "X-GitHub-Delivery";
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac_for(secret: &[u8], body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac
+}
+
+/// Compute the `X-Hub-Signature-256` header for a delivery
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        hex::encode(mac_for(secret, body).finalize().into_bytes())
+    )
+}
+
+/// Check the signature in constant time
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let signature = match signature.strip_prefix("sha256=").map(hex::decode) {
+        Some(Ok(signature)) => signature,
+        _ => return false,
+    };
+
+    mac_for(secret, body).verify_slice(&signature).is_ok()
+}
+
+#[derive(Debug, Deserialize)]
+struct Account {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tier {
+    name: String,
+    monthly_price_in_dollars: i64,
+    #[serde(default)]
+    is_one_time: bool,
+}
+
+impl From<Tier> for GithubSponsorship {
+    fn from(tier: Tier) -> Self {
+        GithubSponsorship {
+            tier_name: tier.name,
+            monthly_dollars: tier.monthly_price_in_dollars,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Sponsorship {
+    sponsor: Account,
+    privacy_level: String,
+    tier: Tier,
+}
+
+#[derive(Debug, Deserialize)]
+struct TierChange {
+    from: Tier,
+}
+
+#[derive(Debug, Deserialize)]
+struct Changes {
+    tier: Option<TierChange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SponsorshipPayload {
+    action: String,
+    sponsorship: Sponsorship,
+    changes: Option<Changes>,
+    effective_date: Option<DateTime<Utc>>,
+}
+
+/// Decode the body of a `sponsorship` delivery.
+///
+/// Returns `Ok(None)` for actions we don't know about.
+pub fn parse_sponsorship(body: &[u8]) -> Result<Option<GithubSponsorshipEvent>> {
+    let payload: SponsorshipPayload = serde_json::from_slice(body)?;
+    let action = match GithubSponsorshipAction::from_action(&payload.action) {
+        Some(action) => action,
+        None => return Ok(None),
+    };
+
+    let sponsorship = payload.sponsorship;
+    Ok(Some(GithubSponsorshipEvent {
+        action,
+        sponsor_login: sponsorship.sponsor.login,
+        is_one_time: sponsorship.tier.is_one_time,
+        is_private: sponsorship.privacy_level == "private",
+        tier: sponsorship.tier.into(),
+        previous_tier: payload
+            .changes
+            .and_then(|changes| changes.tier)
+            .map(|change| change.from.into()),
+        effective_date: payload.effective_date,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct Repository {
+    full_name: String,
+    #[serde(default)]
+    stargazers_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Issue {
+    number: i64,
+    title: String,
+    html_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    number: i64,
+    title: String,
+    html_url: String,
+    #[serde(default)]
+    merged: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    name: Option<String>,
+    html_url: String,
+    #[serde(default)]
+    draft: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RepoPayload {
+    action: String,
+    repository: Repository,
+    sender: Account,
+    issue: Option<Issue>,
+    pull_request: Option<PullRequest>,
+    release: Option<Release>,
+}
+
+/// Decode a `star`, `issues`, `pull_request` or `release` delivery.
+///
+/// Returns `Ok(None)` for everything that isn't worth announcing: unstars,
+/// edits, closed-but-not-merged PRs, drafts and other event types.
+pub fn parse_repo_event(event_type: &str, body: &[u8]) -> Result<Option<GithubRepoEvent>> {
+    if !matches!(event_type, "star" | "issues" | "pull_request" | "release") {
+        return Ok(None);
+    }
+
+    let payload: RepoPayload = serde_json::from_slice(body)?;
+    let activity = match (event_type, payload.action.as_str()) {
+        ("star", "created") => GithubRepoActivity::Starred {
+            stargazers: payload.repository.stargazers_count,
+        },
+        ("issues", "opened") => match payload.issue {
+            Some(issue) => GithubRepoActivity::IssueOpened {
+                number: issue.number,
+                title: issue.title,
+                url: issue.html_url,
+            },
+            None => return Ok(None),
+        },
+        ("pull_request", "closed") => match payload.pull_request {
+            Some(pr) if pr.merged => GithubRepoActivity::PullRequestMerged {
+                number: pr.number,
+                title: pr.title,
+                url: pr.html_url,
+            },
+            _ => return Ok(None),
+        },
+        ("release", "published") => match payload.release {
+            Some(release) if !release.draft => GithubRepoActivity::Released {
+                tag: release.tag_name,
+                name: release.name.filter(|name| !name.is_empty()),
+                url: release.html_url,
+            },
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    Ok(Some(GithubRepoEvent {
+        repo: payload.repository.full_name,
+        sender: payload.sender.login,
+        activity,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIER_CHANGED: &str = r#"{
+        "action": "tier_changed",
+        "sponsorship": {
+            "node_id": "MDExOlNwb25zb3JzaGlwMQ==",
+            "created_at": "2019-12-20T19:24:46+00:00",
+            "sponsorable": { "login": "tjdevries", "id": 4466899 },
+            "sponsor": { "login": "jesseleite", "id": 1234 },
+            "privacy_level": "public",
+            "tier": {
+                "node_id": "MDEyOlNwb25zb3JzVGllcjE=",
+                "created_at": "2019-12-20T19:17:05Z",
+                "description": "foo",
+                "monthly_price_in_cents": 2500,
+                "monthly_price_in_dollars": 25,
+                "name": "$25 a month",
+                "is_one_time": false,
+                "is_custom_amount": false
+            }
+        },
+        "changes": {
+            "tier": {
+                "from": {
+                    "node_id": "MDEyOlNwb25zb3JzVGllcjE=",
+                    "created_at": "2019-12-20T19:17:05Z",
+                    "description": "foo",
+                    "monthly_price_in_cents": 500,
+                    "monthly_price_in_dollars": 5,
+                    "name": "$5 a month",
+                    "is_one_time": false,
+                    "is_custom_amount": false
+                }
+            }
+        },
+        "sender": { "login": "jesseleite", "id": 1234 }
+    }"#;
+
+    #[test]
+    fn verifies_signatures() {
+        let body = TIER_CHANGED.as_bytes();
+        let signature = sign(b"secret", body);
+
+        assert!(verify_signature(b"secret", body, &signature));
+        assert!(!verify_signature(b"nope", body, &signature));
+        assert!(!verify_signature(b"secret", b"{}", &signature));
+        assert!(!verify_signature(b"secret", body, "sha1=abc"));
+    }
+
+    #[test]
+    fn parses_tier_change() {
+        let event = parse_sponsorship(TIER_CHANGED.as_bytes()).unwrap().unwrap();
+
+        assert_eq!(event.action, GithubSponsorshipAction::TierChanged);
+        assert_eq!(event.sponsor_login, "jesseleite");
+        assert_eq!(event.tier.monthly_dollars, 25);
+        assert_eq!(event.previous_tier.unwrap().monthly_dollars, 5);
+        assert!(!event.is_private);
+    }
+
+    #[test]
+    fn ignores_unknown_actions() {
+        let body = TIER_CHANGED.replace("tier_changed", "transferred");
+        assert!(parse_sponsorship(body.as_bytes()).unwrap().is_none());
+    }
+
+    const PR_CLOSED: &str = r#"{
+        "action": "closed",
+        "number": 42,
+        "pull_request": {
+            "number": 42,
+            "title": "Add themesong previews",
+            "html_url": "https://github.com/tjdevries/subd/pull/42",
+            "state": "closed",
+            "merged": true
+        },
+        "repository": { "full_name": "tjdevries/subd", "stargazers_count": 69 },
+        "sender": { "login": "nyxkrage", "id": 1234 }
+    }"#;
+
+    #[test]
+    fn parses_merged_pull_request() {
+        let event = parse_repo_event("pull_request", PR_CLOSED.as_bytes())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(event.repo, "tjdevries/subd");
+        assert_eq!(event.sender, "nyxkrage");
+        assert_eq!(
+            event.activity,
+            GithubRepoActivity::PullRequestMerged {
+                number: 42,
+                title: "Add themesong previews".to_string(),
+                url: "https://github.com/tjdevries/subd/pull/42".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn ignores_unannounced_repo_activity() {
+        let closed = PR_CLOSED.replace(r#""merged": true"#, r#""merged": false"#);
+        assert!(parse_repo_event("pull_request", closed.as_bytes())
+            .unwrap()
+            .is_none());
+
+        let unstarred = r#"{
+            "action": "deleted",
+            "repository": { "full_name": "tjdevries/subd", "stargazers_count": 68 },
+            "sender": { "login": "nyxkrage" }
+        }"#;
+        assert!(parse_repo_event("star", unstarred.as_bytes())
+            .unwrap()
+            .is_none());
+        assert!(parse_repo_event("push", b"not even json")
+            .unwrap()
+            .is_none());
+    }
+}
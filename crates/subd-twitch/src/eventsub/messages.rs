@@ -0,0 +1,401 @@
+//! Messages sent by Twitch over the EventSub websocket, and the conversion
+//! from EventSub notifications into `subd_types::Event`s.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use subd_types::{
+    Event, SubMessage, SubMessageEmote, SubTier, SubscriptionKind, SubscriptionUser,
+    TwitchCheerEvent, TwitchFollowEvent, TwitchRaidEvent, TwitchRedemptionEvent,
+    TwitchSubscriptionEvent,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Message {
+    pub metadata: Metadata,
+    pub payload: Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Metadata {
+    pub message_id: String,
+    pub message_type: String,
+    pub message_timestamp: DateTime<Utc>,
+    pub subscription_type: Option<String>,
+    pub subscription_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub status: String,
+    pub keepalive_timeout_seconds: Option<u64>,
+    pub reconnect_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionPayload {
+    pub session: Session,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscriptionInfo {
+    pub id: String,
+    pub status: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationPayload {
+    pub subscription: SubscriptionInfo,
+    pub event: Value,
+}
+
+/// The parts of a websocket message that we act on
+#[derive(Debug, Clone)]
+pub enum WebsocketMessage {
+    Welcome(Session),
+    Keepalive,
+    Notification(NotificationPayload),
+    Reconnect(Session),
+    Revocation(SubscriptionInfo),
+    Unknown(String),
+}
+
+impl Message {
+    pub fn parse(source: &str) -> Result<Self> {
+        Ok(serde_json::from_str(source)?)
+    }
+
+    pub fn into_websocket_message(self) -> Result<WebsocketMessage> {
+        let payload = self.payload;
+        Ok(match self.metadata.message_type.as_str() {
+            "session_welcome" => {
+                WebsocketMessage::Welcome(serde_json::from_value::<SessionPayload>(payload)?.session)
+            }
+            "session_keepalive" => WebsocketMessage::Keepalive,
+            "notification" => WebsocketMessage::Notification(serde_json::from_value(payload)?),
+            "session_reconnect" => WebsocketMessage::Reconnect(
+                serde_json::from_value::<SessionPayload>(payload)?.session,
+            ),
+            "revocation" => WebsocketMessage::Revocation(
+                serde_json::from_value::<NotificationPayload>(payload)?.subscription,
+            ),
+            other => WebsocketMessage::Unknown(other.to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscribeEvent {
+    user_id: String,
+    user_login: String,
+    user_name: String,
+    tier: String,
+    is_gift: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionGiftEvent {
+    user_id: Option<String>,
+    user_login: Option<String>,
+    user_name: Option<String>,
+    tier: String,
+    total: i64,
+    is_anonymous: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionMessageEmote {
+    begin: usize,
+    end: usize,
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionMessageText {
+    text: String,
+    emotes: Option<Vec<SubscriptionMessageEmote>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionMessageEvent {
+    user_id: String,
+    user_login: String,
+    user_name: String,
+    tier: String,
+    message: SubscriptionMessageText,
+    cumulative_months: i64,
+    streak_months: Option<i64>,
+}
+
+fn subscription_user(id: String, login: String, display_name: String) -> SubscriptionUser {
+    SubscriptionUser {
+        id,
+        login,
+        display_name,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CheerEvent {
+    is_anonymous: bool,
+    user_name: Option<String>,
+    message: String,
+    bits: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaidEvent {
+    from_broadcaster_user_id: String,
+    from_broadcaster_user_login: String,
+    from_broadcaster_user_name: String,
+    viewers: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FollowEvent {
+    user_id: String,
+    user_login: String,
+    user_name: String,
+    followed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Reward {
+    id: String,
+    title: String,
+    cost: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RedemptionEvent {
+    user_id: String,
+    user_login: String,
+    user_name: String,
+    user_input: String,
+    reward: Reward,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamOnlineEvent {
+    started_at: DateTime<Utc>,
+}
+
+/// Convert the `event` of a notification into the matching subd event.
+///
+/// Returns `Ok(None)` for subscription types we don't know how to handle.
+pub fn notification_to_event(subscription_type: &str, event: Value) -> Result<Option<Event>> {
+    Ok(Some(match subscription_type {
+        "channel.subscribe" => {
+            let sub: SubscribeEvent = serde_json::from_value(event)?;
+            let user = subscription_user(sub.user_id, sub.user_login, sub.user_name);
+
+            // The gifter for a gifted sub shows up in its own channel.subscription.gift
+            let (kind, user, recipient) = if sub.is_gift {
+                (SubscriptionKind::Gift, None, Some(user))
+            } else {
+                (SubscriptionKind::Sub, Some(user), None)
+            };
+
+            Event::TwitchSubscription(TwitchSubscriptionEvent {
+                kind,
+                tier: SubTier::from_plan(&sub.tier),
+                user,
+                recipient,
+                is_anonymous: false,
+                cumulative_months: None,
+                streak_months: None,
+                message: None,
+            })
+        }
+        "channel.subscription.gift" => {
+            let gift: SubscriptionGiftEvent = serde_json::from_value(event)?;
+            let user = match (gift.is_anonymous, gift.user_id, gift.user_login, gift.user_name) {
+                (false, Some(id), Some(login), Some(name)) => {
+                    Some(subscription_user(id, login, name))
+                }
+                _ => None,
+            };
+
+            Event::TwitchSubscription(TwitchSubscriptionEvent {
+                kind: SubscriptionKind::MysteryGift { count: gift.total },
+                tier: SubTier::from_plan(&gift.tier),
+                is_anonymous: user.is_none(),
+                user,
+                recipient: None,
+                cumulative_months: None,
+                streak_months: None,
+                message: None,
+            })
+        }
+        "channel.subscription.message" => {
+            let resub: SubscriptionMessageEvent = serde_json::from_value(event)?;
+            let emotes = resub
+                .message
+                .emotes
+                .unwrap_or_default()
+                .into_iter()
+                .map(|emote| SubMessageEmote {
+                    id: emote.id,
+                    start: emote.begin,
+                    end: emote.end,
+                })
+                .collect();
+
+            Event::TwitchSubscription(TwitchSubscriptionEvent {
+                kind: SubscriptionKind::Resub,
+                tier: SubTier::from_plan(&resub.tier),
+                user: Some(subscription_user(
+                    resub.user_id,
+                    resub.user_login,
+                    resub.user_name,
+                )),
+                recipient: None,
+                is_anonymous: false,
+                cumulative_months: Some(resub.cumulative_months),
+                streak_months: resub.streak_months,
+                message: Some(SubMessage {
+                    text: resub.message.text,
+                    emotes,
+                }),
+            })
+        }
+        "channel.cheer" => {
+            let cheer: CheerEvent = serde_json::from_value(event)?;
+            Event::TwitchCheer(TwitchCheerEvent {
+                user_name: if cheer.is_anonymous {
+                    None
+                } else {
+                    cheer.user_name
+                },
+                bits: cheer.bits,
+                message: cheer.message,
+            })
+        }
+        "channel.raid" => {
+            let raid: RaidEvent = serde_json::from_value(event)?;
+            Event::TwitchRaid(TwitchRaidEvent {
+                from_broadcaster_id: raid.from_broadcaster_user_id,
+                from_broadcaster_login: raid.from_broadcaster_user_login,
+                from_broadcaster_name: raid.from_broadcaster_user_name,
+                viewers: raid.viewers,
+            })
+        }
+        "channel.follow" => {
+            let follow: FollowEvent = serde_json::from_value(event)?;
+            Event::TwitchFollow(TwitchFollowEvent {
+                user_id: follow.user_id,
+                user_login: follow.user_login,
+                user_name: follow.user_name,
+                followed_at: follow.followed_at,
+            })
+        }
+        "channel.channel_points_custom_reward_redemption.add" => {
+            let redemption: RedemptionEvent = serde_json::from_value(event)?;
+            Event::TwitchChannelPointsRedemption(TwitchRedemptionEvent {
+                user_id: redemption.user_id,
+                user_login: redemption.user_login,
+                user_name: redemption.user_name,
+                reward_id: redemption.reward.id,
+                reward_title: redemption.reward.title,
+                cost: redemption.reward.cost,
+                user_input: redemption.user_input,
+            })
+        }
+        "stream.online" => {
+            let online: StreamOnlineEvent = serde_json::from_value(event)?;
+            Event::TwitchStreamOnline(online.started_at)
+        }
+        "stream.offline" => Event::TwitchStreamOffline,
+        _ => return Ok(None),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WELCOME: &str = r#"{
+        "metadata": {
+            "message_id": "96a3f3b5-5dec-4eed-908e-e11ee657416c",
+            "message_type": "session_welcome",
+            "message_timestamp": "2022-11-16T10:11:12.634234626Z"
+        },
+        "payload": {
+            "session": {
+                "id": "AQoQILE98gtqShGmLD7AM6yJThAB",
+                "status": "connected",
+                "connected_at": "2022-11-16T10:11:12.634234626Z",
+                "keepalive_timeout_seconds": 10,
+                "reconnect_url": null
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_parses_welcome() {
+        let msg = Message::parse(WELCOME).unwrap();
+        match msg.into_websocket_message().unwrap() {
+            WebsocketMessage::Welcome(session) => {
+                assert_eq!(session.id, "AQoQILE98gtqShGmLD7AM6yJThAB");
+                assert_eq!(session.keepalive_timeout_seconds, Some(10));
+            }
+            other => panic!("expected welcome, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_maps_anonymous_gift() {
+        let event = serde_json::json!({
+            "user_id": null,
+            "user_login": null,
+            "user_name": null,
+            "broadcaster_user_id": "114257969",
+            "broadcaster_user_login": "teej_dv",
+            "broadcaster_user_name": "teej_dv",
+            "total": 5,
+            "tier": "1000",
+            "cumulative_total": null,
+            "is_anonymous": true
+        });
+
+        match notification_to_event("channel.subscription.gift", event).unwrap() {
+            Some(Event::TwitchSubscription(sub)) => {
+                assert_eq!(sub.display_name(), "An anonymous gifter");
+                assert_eq!(sub.kind, SubscriptionKind::MysteryGift { count: 5 });
+            }
+            other => panic!("expected subscription, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_maps_raid() {
+        let event = serde_json::json!({
+            "from_broadcaster_user_id": "1234",
+            "from_broadcaster_user_login": "theprimeagen",
+            "from_broadcaster_user_name": "ThePrimeagen",
+            "to_broadcaster_user_id": "114257969",
+            "to_broadcaster_user_login": "teej_dv",
+            "to_broadcaster_user_name": "teej_dv",
+            "viewers": 9001
+        });
+
+        match notification_to_event("channel.raid", event).unwrap() {
+            Some(Event::TwitchRaid(raid)) => {
+                assert_eq!(raid.from_broadcaster_name, "ThePrimeagen");
+                assert_eq!(raid.viewers, 9001);
+            }
+            other => panic!("expected raid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ignores_unknown_subscription_types() {
+        let result = notification_to_event("channel.hype_train.begin", Value::Null).unwrap();
+        assert!(result.is_none());
+    }
+}
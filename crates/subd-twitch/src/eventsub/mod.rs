@@ -0,0 +1,471 @@
+//! Client for the Twitch EventSub websocket transport.
+//!
+//! EventSub replaces PubSub. After connecting, Twitch sends a `session_welcome`
+//! with a session id, and we have a few seconds to create our subscriptions against
+//! that session through Helix. After that it is just notifications and keepalives,
+//! plus the occasional `session_reconnect` that hands us a new URL to move to.
+
+pub mod messages;
+pub mod webhook;
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+use subd_types::Event;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::helix;
+use crate::pubsub::Backoff;
+use messages::{Message, WebsocketMessage};
+
+pub const TWITCH_EVENTSUB_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+pub const HELIX_EVENTSUB_URL: &str = "https://api.twitch.tv/helix/eventsub/subscriptions";
+
+/// How many message ids we remember, so that redelivered messages are only handled once.
+const SEEN_MESSAGE_CAPACITY: usize = 1000;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionRequest {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub version: String,
+    pub condition: Value,
+}
+
+impl SubscriptionRequest {
+    pub fn new(kind: &str, version: &str, condition: Value) -> Self {
+        Self {
+            kind: kind.to_string(),
+            version: version.to_string(),
+            condition,
+        }
+    }
+}
+
+/// Every subscription that subd knows how to turn into an `Event`
+pub fn default_subscriptions(broadcaster_id: &str) -> Vec<SubscriptionRequest> {
+    let broadcaster = serde_json::json!({ "broadcaster_user_id": broadcaster_id });
+
+    vec![
+        SubscriptionRequest::new("channel.subscribe", "1", broadcaster.clone()),
+        SubscriptionRequest::new("channel.subscription.gift", "1", broadcaster.clone()),
+        SubscriptionRequest::new("channel.subscription.message", "1", broadcaster.clone()),
+        SubscriptionRequest::new("channel.cheer", "1", broadcaster.clone()),
+        SubscriptionRequest::new(
+            "channel.raid",
+            "1",
+            serde_json::json!({ "to_broadcaster_user_id": broadcaster_id }),
+        ),
+        SubscriptionRequest::new(
+            "channel.follow",
+            "2",
+            serde_json::json!({
+                "broadcaster_user_id": broadcaster_id,
+                "moderator_user_id": broadcaster_id,
+            }),
+        ),
+        SubscriptionRequest::new(
+            "channel.channel_points_custom_reward_redemption.add",
+            "1",
+            broadcaster.clone(),
+        ),
+        SubscriptionRequest::new("stream.online", "1", broadcaster.clone()),
+        SubscriptionRequest::new("stream.offline", "1", broadcaster),
+    ]
+}
+
+#[derive(Debug, Clone)]
+pub struct EventSubConfig {
+    pub url: String,
+    pub helix_url: String,
+    pub token: String,
+    pub client_id: String,
+    pub subscriptions: Vec<SubscriptionRequest>,
+
+    /// Used until the welcome message tells us the real keepalive timeout
+    pub keepalive_timeout: Duration,
+
+    pub backoff: Backoff,
+}
+
+impl EventSubConfig {
+    pub fn new(token: impl Into<String>, client_id: impl Into<String>, broadcaster_id: &str) -> Self {
+        Self {
+            url: TWITCH_EVENTSUB_URL.to_string(),
+            helix_url: HELIX_EVENTSUB_URL.to_string(),
+            token: token.into(),
+            client_id: client_id.into(),
+            subscriptions: default_subscriptions(broadcaster_id),
+            keepalive_timeout: Duration::from_secs(10),
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+        }
+    }
+
+    /// Look up the client id and broadcaster id that belong to `token`
+    pub async fn from_token(token: impl Into<String>) -> Result<Self> {
+        let token = token.into();
+        let validated = helix::validate_token(&reqwest::Client::new(), &token).await?;
+
+        let user_id = validated.user_id.clone();
+        Ok(Self::new(token, validated.client_id, &user_id))
+    }
+}
+
+/// Why a single session stopped.
+enum SessionEnd {
+    /// Twitch wants us on a new URL. Keep the old socket open until the new one is welcomed.
+    Reconnect(String, WsStream),
+    Timeout,
+    Closed,
+    Error(anyhow::Error),
+}
+
+/// Remembers recently seen message ids. Twitch may deliver the same message more than once.
+pub struct SeenMessages {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl SeenMessages {
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+        }
+    }
+
+    /// Returns true if this is the first time we've seen `id`
+    pub fn insert(&mut self, id: &str) -> bool {
+        if !self.ids.insert(id.to_string()) {
+            return false;
+        }
+
+        self.order.push_back(id.to_string());
+        if self.order.len() > SEEN_MESSAGE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+
+        true
+    }
+}
+
+impl Default for SeenMessages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connect to EventSub and call `on_event` for every notification we can turn into an `Event`.
+///
+/// Only returns if `on_event` fails or Helix refuses our subscriptions.
+pub async fn run<F>(config: EventSubConfig, mut on_event: F) -> Result<()>
+where
+    F: FnMut(Event) -> Result<()>,
+{
+    let client = reqwest::Client::new();
+    let mut backoff = config.backoff.clone();
+    let mut seen = SeenMessages::new();
+
+    let mut url = config.url.clone();
+    let mut previous: Option<WsStream> = None;
+
+    loop {
+        let end = run_session(
+            &config,
+            &client,
+            &url,
+            previous.take(),
+            &mut on_event,
+            &mut seen,
+            &mut backoff,
+        )
+        .await?;
+
+        let delay = match end {
+            SessionEnd::Reconnect(reconnect_url, old) => {
+                println!("[eventsub] moving to new session: {}", reconnect_url);
+                url = reconnect_url;
+                previous = Some(old);
+                continue;
+            }
+            SessionEnd::Timeout => {
+                println!("[eventsub] keepalive timed out");
+                backoff.next_delay()
+            }
+            SessionEnd::Closed => {
+                println!("[eventsub] connection closed");
+                backoff.next_delay()
+            }
+            SessionEnd::Error(err) => {
+                println!("[eventsub] connection error: {:?}", err);
+                backoff.next_delay()
+            }
+        };
+
+        // Any new session that isn't a reconnect starts without subscriptions.
+        url = config.url.clone();
+        println!("[eventsub] reconnecting in {:?}", delay);
+        sleep(delay).await;
+    }
+}
+
+async fn run_session<F>(
+    config: &EventSubConfig,
+    client: &reqwest::Client,
+    url: &str,
+    mut previous: Option<WsStream>,
+    on_event: &mut F,
+    seen: &mut SeenMessages,
+    backoff: &mut Backoff,
+) -> Result<SessionEnd>
+where
+    F: FnMut(Event) -> Result<()>,
+{
+    let (mut ws_stream, _) = match tokio_tungstenite::connect_async(url).await {
+        Ok(connection) => connection,
+        Err(err) => return Ok(SessionEnd::Error(err.into())),
+    };
+
+    let mut keepalive_timeout = config.keepalive_timeout;
+    let mut deadline = Instant::now() + keepalive_timeout;
+
+    loop {
+        let msg = tokio::select! {
+            _ = sleep_until(deadline) => return Ok(SessionEnd::Timeout),
+            msg = ws_stream.next() => msg,
+        };
+
+        let text = match msg {
+            Some(Ok(tungstenite::Message::Text(text))) => text,
+            Some(Ok(tungstenite::Message::Ping(payload))) => {
+                if let Err(err) = ws_stream.send(tungstenite::Message::Pong(payload)).await {
+                    return Ok(SessionEnd::Error(err.into()));
+                }
+                continue;
+            }
+            Some(Ok(tungstenite::Message::Close(frame))) => {
+                println!("[eventsub] closed by twitch: {:?}", frame);
+                return Ok(SessionEnd::Closed);
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(err)) => return Ok(SessionEnd::Error(err.into())),
+            None => return Ok(SessionEnd::Closed),
+        };
+
+        // Any message at all counts as a sign of life.
+        deadline = Instant::now() + keepalive_timeout;
+
+        let message = match Message::parse(&text) {
+            Ok(message) => message,
+            Err(err) => {
+                println!("[eventsub] could not parse message: {:?} // {}", err, text);
+                continue;
+            }
+        };
+
+        if !seen.insert(&message.metadata.message_id) {
+            continue;
+        }
+
+        let message = match message.into_websocket_message() {
+            Ok(message) => message,
+            Err(err) => return Ok(SessionEnd::Error(err)),
+        };
+
+        match message {
+            WebsocketMessage::Welcome(session) => {
+                if let Some(seconds) = session.keepalive_timeout_seconds {
+                    // Give twitch a little bit of slack before we decide it's gone.
+                    keepalive_timeout = Duration::from_secs(seconds + 5);
+                    deadline = Instant::now() + keepalive_timeout;
+                }
+
+                match previous.take() {
+                    // Subscriptions carry over to the new session on a reconnect
+                    Some(mut old) => {
+                        let _ = old.close(None).await;
+                    }
+                    None => {
+                        for subscription in &config.subscriptions {
+                            if let Err(err) =
+                                create_subscription(client, config, &session.id, subscription).await
+                            {
+                                // Only a refused token is worth giving up over, Helix being
+                                // unreachable is handled like any other dropped connection.
+                                if err.downcast_ref::<reqwest::Error>().is_some() {
+                                    return Ok(SessionEnd::Error(err));
+                                }
+                                return Err(err);
+                            }
+                        }
+                    }
+                }
+
+                println!("[eventsub] session {} ready", session.id);
+                backoff.reset();
+            }
+            WebsocketMessage::Keepalive => {}
+            WebsocketMessage::Notification(notification) => {
+                let kind = notification.subscription.kind.as_str();
+                match messages::notification_to_event(kind, notification.event) {
+                    Ok(Some(event)) => on_event(event)?,
+                    Ok(None) => println!("[eventsub] unhandled notification: {}", kind),
+                    Err(err) => println!("[eventsub] bad {} notification: {:?}", kind, err),
+                }
+            }
+            WebsocketMessage::Reconnect(session) => match session.reconnect_url {
+                Some(reconnect_url) => return Ok(SessionEnd::Reconnect(reconnect_url, ws_stream)),
+                None => return Ok(SessionEnd::Closed),
+            },
+            WebsocketMessage::Revocation(subscription) => {
+                println!(
+                    "[eventsub] subscription revoked: {} ({})",
+                    subscription.kind, subscription.status
+                );
+            }
+            WebsocketMessage::Unknown(message_type) => {
+                println!("[eventsub] unknown message type: {}", message_type);
+            }
+        }
+    }
+}
+
+async fn create_subscription(
+    client: &reqwest::Client,
+    config: &EventSubConfig,
+    session_id: &str,
+    subscription: &SubscriptionRequest,
+) -> Result<()> {
+    let body = serde_json::json!({
+        "type": subscription.kind,
+        "version": subscription.version,
+        "condition": subscription.condition,
+        "transport": {
+            "method": "websocket",
+            "session_id": session_id,
+        },
+    });
+
+    let response = client
+        .post(config.helix_url.as_str())
+        .header("Client-Id", config.client_id.as_str())
+        .bearer_auth(config.token.as_str())
+        .json(&body)
+        .send()
+        .await?;
+
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        // Already subscribed, nothing to do
+        StatusCode::CONFLICT => Ok(()),
+        StatusCode::UNAUTHORIZED => Err(anyhow::anyhow!(
+            "not allowed to subscribe to {}: {}",
+            subscription.kind,
+            response.text().await?
+        )),
+        status => {
+            // Usually a missing scope (403) for one topic, the rest can still work
+            println!(
+                "[eventsub] failed to subscribe to {} ({}): {}",
+                subscription.kind,
+                status,
+                response.text().await?
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::WebSocketStream;
+
+    use super::*;
+
+    async fn stand_in() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    }
+
+    async fn send_message(
+        ws: &mut WebSocketStream<TcpStream>,
+        id: &str,
+        kind: &str,
+        payload: Value,
+    ) {
+        let message = serde_json::json!({
+            "metadata": {
+                "message_id": id,
+                "message_type": kind,
+                "message_timestamp": "2022-11-16T10:11:12.634234626Z"
+            },
+            "payload": payload,
+        });
+
+        ws.send(tungstenite::Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    fn welcome() -> Value {
+        serde_json::json!({
+            "session": {
+                "id": "AQoQILE98gtqShGmLD7AM6yJThAB",
+                "status": "connected",
+                "keepalive_timeout_seconds": 10,
+                "reconnect_url": null
+            }
+        })
+    }
+
+    /// An address nothing is listening on
+    async fn unreachable_helix() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_transient_failures() {
+        let (listener, url) = stand_in().await;
+        let config = EventSubConfig {
+            url,
+            helix_url: unreachable_helix().await,
+            keepalive_timeout: Duration::from_secs(10),
+            backoff: Backoff::new(Duration::from_millis(10), Duration::from_millis(50)),
+            ..EventSubConfig::new("token", "client", "114257969")
+        };
+
+        let client = tokio::spawn(run(config, |_| Ok(())));
+
+        // A notification we can't make sense of drops the connection
+        let mut first = accept(&listener).await;
+        send_message(&mut first, "1", "notification", serde_json::json!({})).await;
+
+        // So does Helix being down while we subscribe
+        let mut second = accept(&listener).await;
+        send_message(&mut second, "2", "session_welcome", welcome()).await;
+
+        let _third = accept(&listener).await;
+        assert!(!client.is_finished());
+        client.abort();
+    }
+}
@@ -0,0 +1,185 @@
+//! Verification and decoding for EventSub webhook callbacks.
+//!
+//! Twitch signs every callback with the secret we gave it when the subscription
+//! was created: `sha256=` + hex(HMAC-SHA256(secret, message_id + timestamp + body)).
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use subd_types::Event;
+
+use super::messages::{notification_to_event, NotificationPayload, SubscriptionInfo};
+
+pub const MESSAGE_ID_HEADER: &str = "Twitch-Eventsub-Message-Id";
+pub const MESSAGE_TIMESTAMP_HEADER: &str = "Twitch-Eventsub-Message-Timestamp";
+pub const MESSAGE_SIGNATURE_HEADER: &str = "Twitch-Eventsub-Message-Signature";
+pub const MESSAGE_TYPE_HEADER: &str = "Twitch-Eventsub-Message-Type";
+
+/// Twitch recommends rejecting anything older than 10 minutes to prevent replays
+pub fn max_message_age() -> Duration {
+    Duration::minutes(10)
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac_for(secret: &[u8], message_id: &str, timestamp: &str, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(message_id.as_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(body);
+    mac
+}
+
+/// Compute the `Twitch-Eventsub-Message-Signature` header for a message
+pub fn sign(secret: &[u8], message_id: &str, timestamp: &str, body: &[u8]) -> String {
+    let mac = mac_for(secret, message_id, timestamp, body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Check the signature in constant time
+pub fn verify_signature(
+    secret: &[u8],
+    message_id: &str,
+    timestamp: &str,
+    body: &[u8],
+    signature: &str,
+) -> bool {
+    let signature = match signature.strip_prefix("sha256=").map(hex::decode) {
+        Some(Ok(signature)) => signature,
+        _ => return false,
+    };
+
+    mac_for(secret, message_id, timestamp, body)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+/// Whether `timestamp` is recent enough to accept, relative to `now`
+pub fn is_fresh(timestamp: &str, now: DateTime<Utc>) -> bool {
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(timestamp) => {
+            let age = now - timestamp.with_timezone(&Utc);
+            // Allow a little clock skew in the other direction too
+            age < max_message_age() && age > -Duration::minutes(1)
+        }
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum WebhookMessage {
+    /// Twitch is confirming that we own this callback. Answer with the challenge.
+    Challenge(String),
+    /// `None` when we don't know how to turn the notification into an `Event`
+    Notification(Option<Event>),
+    Revocation(SubscriptionInfo),
+    Unknown(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct ChallengePayload {
+    challenge: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevocationPayload {
+    subscription: SubscriptionInfo,
+}
+
+/// Decode an already verified callback body based on its `Twitch-Eventsub-Message-Type`
+pub fn parse_message(message_type: &str, body: &[u8]) -> Result<WebhookMessage> {
+    Ok(match message_type {
+        "webhook_callback_verification" => {
+            let payload: ChallengePayload = serde_json::from_slice(body)?;
+            WebhookMessage::Challenge(payload.challenge)
+        }
+        "notification" => {
+            let payload: NotificationPayload = serde_json::from_slice(body)?;
+            WebhookMessage::Notification(notification_to_event(
+                &payload.subscription.kind,
+                payload.event,
+            )?)
+        }
+        "revocation" => {
+            let payload: RevocationPayload = serde_json::from_slice(body)?;
+            WebhookMessage::Revocation(payload.subscription)
+        }
+        other => {
+            let _: Value = serde_json::from_slice(body)?;
+            WebhookMessage::Unknown(other.to_string())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"this is a very secret secret";
+    const MESSAGE_ID: &str = "befa7b53-d79d-478f-86b9-120f112b044e";
+    const TIMESTAMP: &str = "2022-11-16T10:11:12.123Z";
+    const BODY: &[u8] = br#"{"subscription":{"id":"f1c2a387-161a-49f9-a165-0f21d7a4e1c4","status":"enabled","type":"channel.follow","version":"2","condition":{},"transport":{"method":"webhook","callback":"https://example.com/webhooks/twitch"},"created_at":"2022-11-16T10:11:12.123Z","cost":0},"event":{"user_id":"1234","user_login":"nyxkrage","user_name":"NyxKrage","broadcaster_user_id":"114257969","broadcaster_user_login":"teej_dv","broadcaster_user_name":"teej_dv","followed_at":"2022-11-16T10:11:11.123Z"}}"#;
+
+    #[test]
+    fn test_accepts_valid_signature() {
+        let signature = sign(SECRET, MESSAGE_ID, TIMESTAMP, BODY);
+        assert!(verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, BODY, &signature));
+    }
+
+    #[test]
+    fn test_rejects_tampered_body() {
+        let signature = sign(SECRET, MESSAGE_ID, TIMESTAMP, BODY);
+        let tampered = String::from_utf8_lossy(BODY).replace("NyxKrage", "TJDeVries");
+        assert!(!verify_signature(
+            SECRET,
+            MESSAGE_ID,
+            TIMESTAMP,
+            tampered.as_bytes(),
+            &signature
+        ));
+    }
+
+    #[test]
+    fn test_rejects_wrong_secret_and_garbage() {
+        let signature = sign(b"some other secret", MESSAGE_ID, TIMESTAMP, BODY);
+        assert!(!verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, BODY, &signature));
+        assert!(!verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, BODY, "sha256=zzzz"));
+        assert!(!verify_signature(SECRET, MESSAGE_ID, TIMESTAMP, BODY, ""));
+    }
+
+    #[test]
+    fn test_rejects_stale_timestamps() {
+        let now = DateTime::parse_from_rfc3339(TIMESTAMP)
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert!(is_fresh(TIMESTAMP, now));
+        assert!(is_fresh(TIMESTAMP, now + Duration::minutes(9)));
+        assert!(!is_fresh(TIMESTAMP, now + Duration::minutes(11)));
+        assert!(!is_fresh("yesterday", now));
+    }
+
+    #[test]
+    fn test_parses_notification() {
+        match parse_message("notification", BODY).unwrap() {
+            WebhookMessage::Notification(Some(Event::TwitchFollow(follow))) => {
+                assert_eq!(follow.user_login, "nyxkrage")
+            }
+            other => panic!("expected follow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parses_challenge() {
+        let body = br#"{"challenge":"pogchamp-kappa-360noscope-vohiyo","subscription":{"id":"f1c2a387-161a-49f9-a165-0f21d7a4e1c4","status":"webhook_callback_verification_pending","type":"channel.follow","version":"2"}}"#;
+        match parse_message("webhook_callback_verification", body).unwrap() {
+            WebhookMessage::Challenge(challenge) => {
+                assert_eq!(challenge, "pogchamp-kappa-360noscope-vohiyo")
+            }
+            other => panic!("expected challenge, got {:?}", other),
+        }
+    }
+}
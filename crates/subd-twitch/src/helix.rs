@@ -0,0 +1,199 @@
+//! Minimal Helix client for the paginated endpoints we sync into the database.
+//!
+//! twitch_api2 covers some of these, but not VIPs, and we want to walk every page
+//! the same way for all of them.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+pub const HELIX_URL: &str = "https://api.twitch.tv/helix";
+pub const TWITCH_VALIDATE_URL: &str = "https://id.twitch.tv/oauth2/validate";
+
+/// Largest page size Helix allows
+const PAGE_SIZE: &str = "100";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ValidatedToken {
+    pub client_id: String,
+    pub login: String,
+    pub user_id: String,
+}
+
+/// Look up who `token` belongs to, and which client id it was issued for
+pub async fn validate_token(client: &reqwest::Client, token: &str) -> Result<ValidatedToken> {
+    Ok(client
+        .get(TWITCH_VALIDATE_URL)
+        .header("Authorization", format!("OAuth {}", token))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Subscriber {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    /// "1000", "2000" or "3000"
+    pub tier: String,
+    pub is_gift: bool,
+}
+
+/// A moderator or VIP of the channel
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ChannelMember {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    data: Vec<T>,
+    #[serde(default)]
+    pagination: Option<Pagination>,
+}
+
+impl<T> Page<T> {
+    fn next_cursor(&self) -> Option<String> {
+        self.pagination
+            .as_ref()
+            .and_then(|pagination| pagination.cursor.clone())
+            .filter(|cursor| !cursor.is_empty())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Helix {
+    client: reqwest::Client,
+    pub base_url: String,
+    pub token: String,
+    pub client_id: String,
+    pub broadcaster_id: String,
+}
+
+impl Helix {
+    pub fn new(
+        token: impl Into<String>,
+        client_id: impl Into<String>,
+        broadcaster_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: HELIX_URL.to_string(),
+            token: token.into(),
+            client_id: client_id.into(),
+            broadcaster_id: broadcaster_id.into(),
+        }
+    }
+
+    /// Helix client for the broadcaster that owns `token`
+    pub async fn from_token(token: impl Into<String>) -> Result<Self> {
+        let token = token.into();
+        let validated = validate_token(&reqwest::Client::new(), &token).await?;
+
+        Ok(Self::new(token, validated.client_id, validated.user_id))
+    }
+
+    /// Every subscriber of the channel, including the broadcaster themselves
+    pub async fn get_subscribers(&self) -> Result<Vec<Subscriber>> {
+        self.get_all("subscriptions").await
+    }
+
+    pub async fn get_moderators(&self) -> Result<Vec<ChannelMember>> {
+        self.get_all("moderation/moderators").await
+    }
+
+    pub async fn get_vips(&self) -> Result<Vec<ChannelMember>> {
+        self.get_all("channels/vips").await
+    }
+
+    async fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let url = format!("{}/{}", self.base_url, path);
+
+        let mut results = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut request = self
+                .client
+                .get(url.as_str())
+                .header("Client-Id", self.client_id.as_str())
+                .bearer_auth(self.token.as_str())
+                .query(&[
+                    ("broadcaster_id", self.broadcaster_id.as_str()),
+                    ("first", PAGE_SIZE),
+                ]);
+            if let Some(cursor) = &cursor {
+                request = request.query(&[("after", cursor.as_str())]);
+            }
+
+            let page: Page<T> = request.send().await?.error_for_status()?.json().await?;
+
+            cursor = page.next_cursor();
+            let is_empty = page.data.is_empty();
+            results.extend(page.data);
+
+            // Some endpoints hand back a cursor for an empty last page
+            if cursor.is_none() || is_empty {
+                return Ok(results);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_subscriber_page() {
+        let page: Page<Subscriber> = serde_json::from_str(
+            r#"{
+                "data": [{
+                    "broadcaster_id": "114257969",
+                    "broadcaster_login": "teej_dv",
+                    "broadcaster_name": "teej_dv",
+                    "gifter_id": "1234",
+                    "gifter_login": "theprimeagen",
+                    "gifter_name": "ThePrimeagen",
+                    "is_gift": true,
+                    "tier": "1000",
+                    "plan_name": "Channel Subscription (teej_dv)",
+                    "user_id": "5678",
+                    "user_name": "NyxKrage",
+                    "user_login": "nyxkrage"
+                }],
+                "pagination": { "cursor": "xxxx" },
+                "total": 13,
+                "points": 13
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(page.next_cursor(), Some("xxxx".to_string()));
+        assert_eq!(page.data[0].user_login, "nyxkrage");
+        assert!(page.data[0].is_gift);
+    }
+
+    #[test]
+    fn test_last_page_has_no_cursor() {
+        let page: Page<ChannelMember> = serde_json::from_str(
+            r#"{
+                "data": [{ "user_id": "1234", "user_login": "theprimeagen", "user_name": "ThePrimeagen" }],
+                "pagination": {}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(page.next_cursor(), None);
+        assert_eq!(page.data.len(), 1);
+    }
+}
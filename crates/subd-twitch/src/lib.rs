@@ -1,2 +1,6 @@
+pub mod eventsub;
+pub mod helix;
+pub mod pubsub;
+
 // TODO(generalize)
 pub const MY_BROADCASTER_ID: &str = "114257969";
@@ -0,0 +1,445 @@
+//! Long running client for the Twitch PubSub websocket.
+//!
+//! Twitch will close any connection that hasn't sent a PING in the last 5 minutes,
+//! and will ask clients to RECONNECT whenever it wants to cycle a server. This keeps
+//! one connection alive for as long as `run` is awaited: it PINGs on an interval,
+//! tracks the PONGs, reconnects with a jittered backoff and re-LISTENs to every topic
+//! each time a new connection is made.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use tokio::time::{sleep, sleep_until, Instant};
+use twitch_api2::pubsub::{self, TopicData, Topics};
+
+pub const TWITCH_PUBSUB_URL: &str = "wss://pubsub-edge.twitch.tv";
+
+const PING_COMMAND: &str = r#"{"type": "PING"}"#;
+
+#[derive(Debug, Clone)]
+pub struct PubSubConfig {
+    pub url: String,
+    pub token: String,
+    pub topics: Vec<Topics>,
+
+    /// How often to send a PING. Twitch wants one at least every 5 minutes.
+    pub ping_interval: Duration,
+
+    /// How long to wait for a PONG before assuming the connection is dead.
+    pub pong_timeout: Duration,
+
+    /// How long to wait for the RESPONSE to our LISTEN before giving up on the connection.
+    pub listen_timeout: Duration,
+
+    pub backoff: Backoff,
+}
+
+impl PubSubConfig {
+    pub fn new(token: impl Into<String>, topics: Vec<Topics>) -> Self {
+        Self {
+            url: TWITCH_PUBSUB_URL.to_string(),
+            token: token.into(),
+            topics,
+            ping_interval: Duration::from_secs(4 * 60),
+            pong_timeout: Duration::from_secs(10),
+            listen_timeout: Duration::from_secs(10),
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+        }
+    }
+}
+
+/// Exponential backoff with jitter, as recommended by the Twitch PubSub docs.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// Returns how long to wait before the next attempt, and doubles the wait for the one after.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current + jitter(self.current / 2);
+        self.current = std::cmp::min(self.current * 2, self.max);
+
+        std::cmp::min(delay, self.max)
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max.as_millis() as u64))
+}
+
+fn make_nonce() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+/// Why a single connection stopped.
+#[derive(Debug)]
+enum Disconnect {
+    /// Twitch sent a RECONNECT
+    Reconnect,
+    /// We did not get a PONG (or a LISTEN response) in time
+    Timeout,
+    /// The socket was closed on us
+    Closed,
+    /// Connecting, reading or writing failed
+    Error(anyhow::Error),
+}
+
+/// Connect to PubSub and call `on_message` for every message received on `config.topics`.
+///
+/// Only returns if `on_message` fails or if Twitch rejects our LISTEN (for example,
+/// because the token is bad). Every other failure results in a reconnect.
+pub async fn run<F>(config: PubSubConfig, mut on_message: F) -> Result<()>
+where
+    F: FnMut(TopicData) -> Result<()>,
+{
+    let mut backoff = config.backoff.clone();
+
+    loop {
+        let reason = run_connection(&config, &mut on_message, &mut backoff).await?;
+        let name = reason_name(&reason);
+        let delay = match reason {
+            // Twitch asks us to reconnect when it is cycling servers,
+            // so we don't need to wait very long before trying again.
+            Disconnect::Reconnect => {
+                backoff.reset();
+                jitter(config.backoff.initial)
+            }
+            Disconnect::Timeout | Disconnect::Closed => backoff.next_delay(),
+            Disconnect::Error(err) => {
+                println!("[pubsub] connection error: {:?}", err);
+                backoff.next_delay()
+            }
+        };
+
+        println!("[pubsub] disconnected ({}), reconnecting in {:?}", name, delay);
+        sleep(delay).await;
+    }
+}
+
+fn reason_name(reason: &Disconnect) -> &'static str {
+    match reason {
+        Disconnect::Reconnect => "reconnect",
+        Disconnect::Timeout => "timeout",
+        Disconnect::Closed => "closed",
+        Disconnect::Error(_) => "error",
+    }
+}
+
+async fn run_connection<F>(
+    config: &PubSubConfig,
+    on_message: &mut F,
+    backoff: &mut Backoff,
+) -> Result<Disconnect>
+where
+    F: FnMut(TopicData) -> Result<()>,
+{
+    let (mut ws_stream, _) = match tokio_tungstenite::connect_async(config.url.as_str()).await {
+        Ok(connection) => connection,
+        Err(err) => return Ok(Disconnect::Error(err.into())),
+    };
+
+    let nonce = make_nonce();
+    let command =
+        pubsub::listen_command(&config.topics, Some(config.token.as_str()), nonce.as_str())?;
+    if let Err(err) = ws_stream.send(tungstenite::Message::Text(command)).await {
+        return Ok(Disconnect::Error(err.into()));
+    }
+
+    let mut listen_deadline = Some(Instant::now() + config.listen_timeout);
+    let mut pong_deadline: Option<Instant> = None;
+    let mut next_ping = Instant::now() + config.ping_interval + jitter(Duration::from_secs(1));
+
+    loop {
+        // Whichever deadline is closest is the one that would kill the connection.
+        let deadline = match (listen_deadline, pong_deadline) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        tokio::select! {
+            _ = sleep_until(next_ping) => {
+                if let Err(err) = ws_stream.send(tungstenite::Message::Text(PING_COMMAND.to_string())).await {
+                    return Ok(Disconnect::Error(err.into()));
+                }
+
+                if pong_deadline.is_none() {
+                    pong_deadline = Some(Instant::now() + config.pong_timeout);
+                }
+                next_ping = Instant::now() + config.ping_interval + jitter(Duration::from_secs(1));
+            }
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                return Ok(Disconnect::Timeout);
+            }
+            msg = ws_stream.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => return Ok(Disconnect::Error(err.into())),
+                    None => return Ok(Disconnect::Closed),
+                };
+
+                let text = match msg {
+                    tungstenite::Message::Text(text) => text,
+                    tungstenite::Message::Ping(payload) => {
+                        if let Err(err) = ws_stream.send(tungstenite::Message::Pong(payload)).await {
+                            return Ok(Disconnect::Error(err.into()));
+                        }
+                        continue;
+                    }
+                    tungstenite::Message::Close(_) => return Ok(Disconnect::Closed),
+                    _ => continue,
+                };
+
+                let parsed = match pubsub::Response::parse(text.as_str()) {
+                    Ok(parsed) => parsed,
+                    Err(err) => {
+                        println!("[pubsub] could not parse message: {:?} // {}", err, text);
+                        continue;
+                    }
+                };
+
+                match parsed {
+                    pubsub::Response::Response(resp) => {
+                        if resp.nonce.as_deref() != Some(nonce.as_str()) {
+                            println!("[pubsub] ignoring response for unknown nonce: {:?}", resp);
+                            continue;
+                        }
+
+                        if !resp.is_successful() {
+                            return Err(anyhow::anyhow!(
+                                "twitch rejected LISTEN: {:?}",
+                                resp.error
+                            ));
+                        }
+
+                        println!("[pubsub] listening to {} topics", config.topics.len());
+                        listen_deadline = None;
+                        backoff.reset();
+                    }
+                    pubsub::Response::Message { data } => on_message(data)?,
+                    pubsub::Response::Pong => pong_deadline = None,
+                    pubsub::Response::Reconnect => return Ok(Disconnect::Reconnect),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::WebSocketStream;
+    use twitch_api2::pubsub::Topic;
+
+    use super::*;
+
+    const SUB_MESSAGE: &str = r#"{"benefit_end_month":11,"user_name":"nyxkrage","display_name":"NyxKrage","channel_name":"teej_dv","user_id":"1234","channel_id":"114257969","time":"2020-10-20T22:17:43.242793831Z","sub_message":{"message":"You are my favorite streamer","emotes":null},"sub_plan":"1000","sub_plan_name":"Channel Subscription (teej_dv)","months":0,"cumulative_months":1,"context":"sub","is_gift":false,"multi_month_duration":0}"#;
+
+    fn test_config(url: String) -> PubSubConfig {
+        let topics = vec![pubsub::channel_subscriptions::ChannelSubscribeEventsV1 {
+            channel_id: 114257969,
+        }
+        .into_topic()];
+
+        PubSubConfig {
+            url,
+            ping_interval: Duration::from_secs(60),
+            pong_timeout: Duration::from_millis(100),
+            listen_timeout: Duration::from_secs(1),
+            backoff: Backoff::new(Duration::from_millis(10), Duration::from_millis(50)),
+            ..PubSubConfig::new("token", topics)
+        }
+    }
+
+    async fn stand_in() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    }
+
+    async fn next_json(ws: &mut WebSocketStream<TcpStream>) -> Value {
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                tungstenite::Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue,
+            }
+        }
+    }
+
+    async fn send_json(ws: &mut WebSocketStream<TcpStream>, value: Value) {
+        ws.send(tungstenite::Message::Text(value.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Reads the LISTEN command and answers it, returning the topics it asked for
+    async fn answer_listen(ws: &mut WebSocketStream<TcpStream>, error: &str) -> Value {
+        let listen = next_json(ws).await;
+        assert_eq!(listen["type"], "LISTEN");
+
+        send_json(
+            ws,
+            serde_json::json!({ "type": "RESPONSE", "nonce": listen["nonce"], "error": error }),
+        )
+        .await;
+
+        listen["data"]["topics"].clone()
+    }
+
+    fn sub_message() -> Value {
+        serde_json::json!({
+            "type": "MESSAGE",
+            "data": {
+                "topic": "channel-subscribe-events-v1.114257969",
+                "message": SUB_MESSAGE,
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resubscribes_after_reconnect() {
+        let (listener, url) = stand_in().await;
+        let (msg_tx, mut msg_rx) = mpsc::unbounded_channel();
+
+        let client = tokio::spawn(run(test_config(url), move |data| {
+            msg_tx.send(data)?;
+            Ok(())
+        }));
+
+        let mut first = accept(&listener).await;
+        let first_topics = answer_listen(&mut first, "").await;
+        send_json(&mut first, serde_json::json!({ "type": "RECONNECT" })).await;
+
+        let mut second = accept(&listener).await;
+        let second_topics = answer_listen(&mut second, "").await;
+        assert_eq!(first_topics, second_topics);
+
+        send_json(&mut second, sub_message()).await;
+        let data = msg_rx.recv().await.unwrap();
+        assert!(matches!(data, TopicData::ChannelSubscribeEventsV1 { .. }));
+
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_when_pong_is_missing() {
+        let (listener, url) = stand_in().await;
+        let config = PubSubConfig {
+            ping_interval: Duration::from_millis(20),
+            ..test_config(url)
+        };
+
+        let client = tokio::spawn(run(config, |_| Ok(())));
+
+        // Never answer the PING, the client should give up on this connection.
+        let mut first = accept(&listener).await;
+        answer_listen(&mut first, "").await;
+        assert_eq!(next_json(&mut first).await["type"], "PING");
+
+        let mut second = accept(&listener).await;
+        answer_listen(&mut second, "").await;
+
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn test_keeps_connection_when_pong_arrives() {
+        let (listener, url) = stand_in().await;
+        let config = PubSubConfig {
+            ping_interval: Duration::from_millis(20),
+            ..test_config(url)
+        };
+
+        let client = tokio::spawn(run(config, |_| Ok(())));
+
+        let mut ws = accept(&listener).await;
+        answer_listen(&mut ws, "").await;
+        for _ in 0..3 {
+            assert_eq!(next_json(&mut ws).await["type"], "PING");
+            send_json(&mut ws, serde_json::json!({ "type": "PONG" })).await;
+        }
+
+        // Still on the same connection after several PING/PONG rounds.
+        assert!(!client.is_finished());
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn test_ignores_response_with_wrong_nonce() {
+        let (listener, url) = stand_in().await;
+        let client = tokio::spawn(run(test_config(url), |_| Ok(())));
+
+        let mut ws = accept(&listener).await;
+        next_json(&mut ws).await;
+        send_json(
+            &mut ws,
+            serde_json::json!({ "type": "RESPONSE", "nonce": "not-ours", "error": "ERR_BADAUTH" }),
+        )
+        .await;
+
+        // The bad response was not for us, so the listen times out and we try again.
+        let mut second = accept(&listener).await;
+        answer_listen(&mut second, "").await;
+
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn test_fails_when_listen_is_rejected() {
+        let (listener, url) = stand_in().await;
+        let client = tokio::spawn(run(test_config(url), |_| Ok(())));
+
+        let mut ws = accept(&listener).await;
+        answer_listen(&mut ws, "ERR_BADAUTH").await;
+
+        let result = client.await.unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        let first = backoff.next_delay();
+        assert!(first >= Duration::from_millis(100) && first <= Duration::from_millis(150));
+
+        for _ in 0..10 {
+            assert!(backoff.next_delay() <= Duration::from_secs(1));
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(150));
+    }
+}
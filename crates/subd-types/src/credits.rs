@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// One name in the credits, with how much they did (bits, gifts, messages, ...)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreditsEntry {
+    pub name: String,
+    pub amount: Option<i64>,
+}
+
+impl CreditsEntry {
+    pub fn name(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            amount: None,
+        }
+    }
+
+    pub fn with_amount(name: impl Into<String>, amount: i64) -> Self {
+        Self {
+            name: name.into(),
+            amount: Some(amount),
+        }
+    }
+}
+
+/// Everyone to thank at the end of a stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct StreamCredits {
+    pub since: Option<DateTime<Utc>>,
+    pub new_subs: Vec<CreditsEntry>,
+    /// Amount is the number of subs gifted
+    pub gifters: Vec<CreditsEntry>,
+    /// Amount is bits
+    pub cheerers: Vec<CreditsEntry>,
+    /// Amount is viewers brought along
+    pub raiders: Vec<CreditsEntry>,
+    pub new_sponsors: Vec<CreditsEntry>,
+    pub themesongs: Vec<CreditsEntry>,
+    /// Amount is messages sent
+    pub top_chatters: Vec<CreditsEntry>,
+}
+
+impl StreamCredits {
+    /// Sections in the order they roll, skipping empty ones
+    pub fn sections(&self) -> Vec<(&'static str, &[CreditsEntry])> {
+        [
+            ("New Subscribers", self.new_subs.as_slice()),
+            ("Gifted Subs", self.gifters.as_slice()),
+            ("Cheers", self.cheerers.as_slice()),
+            ("Raids", self.raiders.as_slice()),
+            ("New GitHub Sponsors", self.new_sponsors.as_slice()),
+            ("Themesongs", self.themesongs.as_slice()),
+            ("Top Chatters", self.top_chatters.as_slice()),
+        ]
+        .into_iter()
+        .filter(|(_, entries)| !entries.is_empty())
+        .collect()
+    }
+}
@@ -1,83 +1,306 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 pub use twitch_api2::pubsub::channel_subscriptions::ChannelSubscribeEventsV1Reply;
 use twitch_irc::message::PrivmsgMessage;
 
+mod credits;
+mod repo_activity;
+mod sponsorship;
+mod subscription;
+pub use credits::*;
+pub use repo_activity::*;
+pub use sponsorship::*;
+pub use subscription::*;
+
 pub type UserID = i64;
 
+/// The twitch user id on our stand-in events, which never belongs to a real user
+pub const STAND_IN_USER_ID: &str = "1234";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Event {
     // Info
     TwitchChatMessage(PrivmsgMessage),
     TwitchSubscriptionCount(usize),
+    TwitchFollowerCount(usize),
     TwitchSubscription(TwitchSubscriptionEvent),
-    GithubSponsorshipEvent,
+    TwitchCheer(TwitchCheerEvent),
+    TwitchRaid(TwitchRaidEvent),
+    TwitchFollow(TwitchFollowEvent),
+    TwitchChannelPointsRedemption(TwitchRedemptionEvent),
+    TwitchStreamOnline(DateTime<Utc>),
+    TwitchStreamOffline,
+    GithubSponsorshipEvent(GithubSponsorshipEvent),
+    GithubSponsorWall(Vec<GithubSponsor>),
+    GithubRepoEvent(GithubRepoEvent),
+    UserRolesChanged(UserRolesChange),
+    StreamCredits(StreamCredits),
 
     // UserEvents
     ThemesongDownload(ThemesongDownload),
     ThemesongPlay(ThemesongPlay),
+    ThemesongPreview(ThemesongPreview),
+    ThemesongControl(ThemesongControl),
+    /// Who has a themesong waiting for a mod to review it
+    ThemesongsPending(Vec<String>),
 
     // Requests
     RequestTwitchSubCount,
+    RequestStreamCredits,
+    RequestSponsorWall,
+    RequestThemesongsPending,
 
     // Control
     Shutdown,
 }
 
+impl Event {
+    /// Made up to test the overlay, nobody really did this
+    pub fn is_stand_in(&self) -> bool {
+        match self {
+            Event::TwitchSubscription(sub) => sub.is_stand_in(),
+            Event::TwitchFollow(follow) => follow.is_stand_in(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ThemesongDownload {
-    Request { msg: PrivmsgMessage },
-    Start { display_name: String },
-    Finish { display_name: String, success: bool },
-    Format { sender: String },
+    Request {
+        msg: PrivmsgMessage,
+    },
+    Queued {
+        display_name: String,
+        /// How many downloads are ahead of it
+        position: usize,
+    },
+    Start {
+        display_name: String,
+    },
+    Progress {
+        display_name: String,
+        stage: ThemesongDownloadStage,
+    },
+    Finish {
+        display_name: String,
+        success: bool,
+        /// Why it failed, in words chat can understand
+        error: Option<String>,
+    },
+    Format {
+        sender: String,
+    },
+}
+
+/// How far along a themesong download is, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThemesongDownloadStage {
+    Checking,
+    Downloading,
+    Normalizing,
+    Saving,
 }
 
+impl ThemesongDownloadStage {
+    /// Roughly how much of the download is done once this stage starts
+    pub fn percent(&self) -> u8 {
+        match self {
+            ThemesongDownloadStage::Checking => 5,
+            ThemesongDownloadStage::Downloading => 20,
+            ThemesongDownloadStage::Normalizing => 70,
+            ThemesongDownloadStage::Saving => 95,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThemesongDownloadStage::Checking => "checking",
+            ThemesongDownloadStage::Downloading => "downloading",
+            ThemesongDownloadStage::Normalizing => "normalizing",
+            ThemesongDownloadStage::Saving => "saving",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ThemesongPlay {
+    /// Someone with a themesong chatted, and it hasn't played for them yet this
+    /// stream session (or whatever window the replay rule sets)
+    Request {
+        user_id: UserID,
+        display_name: String,
+    },
+    Start {
+        user_id: UserID,
+        display_name: String,
+    },
+    Finish {
+        user_id: UserID,
+        display_name: String,
+        /// A mod cut it short with `!skip` or `!stopsong`
+        skipped: bool,
+    },
+}
+
+/// Mod controls for the themesong player
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ThemesongControl {
+    /// Stop the song that's playing, and go on with the queue
+    Skip,
+    /// Stop the song that's playing, and drop everything queued
+    Stop,
+    /// Between 0 and 1
+    Volume(f32),
+}
+
+/// Play a themesong once without counting it as played
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ThemesongPlay {
+pub struct ThemesongPreview {
     pub user_id: UserID,
     pub display_name: String,
+    /// Play it where only the streamer hears it, not on stream
+    pub private: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct GithubUser {
     pub id: String,
     pub login: String,
     pub name: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GithubSponsorship {
+    pub tier_name: String,
+    pub monthly_dollars: i64,
+}
+
+/// Someone currently sponsoring us on GitHub, for the sponsor wall
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GithubSponsor {
+    pub login: String,
+    pub name: Option<String>,
+    /// `None` for custom amounts, which don't have a tier
+    pub tier: Option<GithubSponsorship>,
+    pub is_one_time: bool,
+}
+
+impl GithubSponsor {
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.login)
+    }
+}
+
+/// What GitHub told us about one login the last time we asked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct GithubSponsorStatus {
+    pub is_sponsoring: bool,
+    /// `None` for custom amounts, which don't have a tier
+    pub sponsorship: Option<GithubSponsorship>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct UserRoles {
     pub is_github_sponsor: bool,
     pub is_twitch_mod: bool,
     pub is_twitch_vip: bool,
     pub is_twitch_founder: bool,
     pub is_twitch_sub: bool,
+
+    /// None when we don't know the tier, or they aren't subbed
+    pub twitch_sub_tier: Option<SubTier>,
+    pub twitch_sub_months: Option<i64>,
+    /// None when they aren't sponsoring on GitHub
+    pub github_sponsorship: Option<GithubSponsorship>,
+}
+
+impl UserRoles {
+    fn names(&self) -> Vec<&'static str> {
+        [
+            (self.is_github_sponsor, "github sponsor"),
+            (self.is_twitch_mod, "moderator"),
+            (self.is_twitch_vip, "vip"),
+            (self.is_twitch_founder, "founder"),
+            (self.is_twitch_sub, "subscriber"),
+        ]
+        .into_iter()
+        .filter_map(|(has_role, name)| if has_role { Some(name) } else { None })
+        .collect()
+    }
 }
 
+/// Someone gained or lost a role since we last checked
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct TwitchSubscriptionEvent {
-    subscription: ChannelSubscribeEventsV1Reply,
+pub struct UserRolesChange {
+    pub user_id: UserID,
+    pub twitch_login: String,
+    pub before: UserRoles,
+    pub after: UserRoles,
 }
 
-impl From<ChannelSubscribeEventsV1Reply> for TwitchSubscriptionEvent {
-    fn from(subscription: ChannelSubscribeEventsV1Reply) -> Self {
-        Self { subscription }
+impl UserRolesChange {
+    pub fn gained(&self) -> Vec<&'static str> {
+        let before = self.before.names();
+        self.after
+            .names()
+            .into_iter()
+            .filter(|name| !before.contains(name))
+            .collect()
+    }
+
+    pub fn lost(&self) -> Vec<&'static str> {
+        let after = self.after.names();
+        self.before
+            .names()
+            .into_iter()
+            .filter(|name| !after.contains(name))
+            .collect()
     }
 }
 
-impl TwitchSubscriptionEvent {
-    pub fn display_name(&self) -> String {
-        match &self.subscription {
-            ChannelSubscribeEventsV1Reply::Sub(sub) => &sub.display_name,
-            ChannelSubscribeEventsV1Reply::ReSub(sub) => &sub.display_name,
-            ChannelSubscribeEventsV1Reply::SubGift(sub) => &sub.display_name,
-            ChannelSubscribeEventsV1Reply::ResubGift(sub) => &sub.display_name,
-            ChannelSubscribeEventsV1Reply::ExtendSub(sub) => &sub.display_name,
-            _ => unimplemented!(),
-        }
-        .to_string()
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwitchCheerEvent {
+    /// None when the cheer is anonymous
+    pub user_name: Option<String>,
+    pub bits: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwitchRaidEvent {
+    pub from_broadcaster_id: String,
+    pub from_broadcaster_login: String,
+    pub from_broadcaster_name: String,
+    pub viewers: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwitchFollowEvent {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub followed_at: DateTime<Utc>,
+}
+
+impl TwitchFollowEvent {
+    /// Made up by `get_fake_follow`, only meant for the overlay
+    pub fn is_stand_in(&self) -> bool {
+        self.user_id == STAND_IN_USER_ID
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwitchRedemptionEvent {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub reward_id: String,
+    pub reward_title: String,
+    pub cost: i64,
+    pub user_input: String,
+}
+
 // const MY_CHANNEL: UserId = 114257969;
 
 pub fn get_nyx_sub() -> TwitchSubscriptionEvent {
@@ -104,8 +327,8 @@ pub fn get_nyx_sub() -> TwitchSubscriptionEvent {
 }
 "##;
 
-    let subscription = serde_json::from_str(message).unwrap();
-    TwitchSubscriptionEvent { subscription }
+    let subscription: ChannelSubscribeEventsV1Reply = serde_json::from_str(message).unwrap();
+    subscription.into()
 }
 
 pub fn get_prime_sub() -> TwitchSubscriptionEvent {
@@ -122,7 +345,7 @@ pub fn get_prime_sub() -> TwitchSubscriptionEvent {
         "message": "You are my favorite streamer",
         "emotes": null
     },
-    "sub_plan": "1000",
+    "sub_plan": "Prime",
     "sub_plan_name": "Channel Subscription (emilgardis)",
     "months": 0,
     "cumulative_months": 1,
@@ -132,6 +355,15 @@ pub fn get_prime_sub() -> TwitchSubscriptionEvent {
 }
 "##;
 
-    let subscription = serde_json::from_str(message).unwrap();
-    TwitchSubscriptionEvent { subscription }
+    let subscription: ChannelSubscribeEventsV1Reply = serde_json::from_str(message).unwrap();
+    subscription.into()
+}
+
+pub fn get_fake_follow(login: &str) -> TwitchFollowEvent {
+    TwitchFollowEvent {
+        user_id: STAND_IN_USER_ID.to_string(),
+        user_login: login.to_lowercase(),
+        user_name: login.to_string(),
+        followed_at: Utc::now(),
+    }
 }
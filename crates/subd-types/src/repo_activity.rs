@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+/// The kinds of repository activity we can announce, so each can be toggled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GithubRepoEventType {
+    Star,
+    Issue,
+    PullRequest,
+    Release,
+}
+
+impl GithubRepoEventType {
+    pub const ALL: [GithubRepoEventType; 4] = [
+        GithubRepoEventType::Star,
+        GithubRepoEventType::Issue,
+        GithubRepoEventType::PullRequest,
+        GithubRepoEventType::Release,
+    ];
+
+    /// Parse a toggle from config. Accepts the `X-GitHub-Event` names too.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.trim().to_lowercase().as_str() {
+            "star" | "stars" => Self::Star,
+            "issue" | "issues" => Self::Issue,
+            "pr" | "prs" | "pull_request" | "pull_requests" => Self::PullRequest,
+            "release" | "releases" => Self::Release,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GithubRepoActivity {
+    Starred {
+        stargazers: i64,
+    },
+    IssueOpened {
+        number: i64,
+        title: String,
+        url: String,
+    },
+    PullRequestMerged {
+        number: i64,
+        title: String,
+        url: String,
+    },
+    Released {
+        tag: String,
+        name: Option<String>,
+        url: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GithubRepoEvent {
+    /// `owner/name`
+    pub repo: String,
+    /// Who starred, opened, merged or published
+    pub sender: String,
+    pub activity: GithubRepoActivity,
+}
+
+impl GithubRepoEvent {
+    pub fn event_type(&self) -> GithubRepoEventType {
+        match self.activity {
+            GithubRepoActivity::Starred { .. } => GithubRepoEventType::Star,
+            GithubRepoActivity::IssueOpened { .. } => GithubRepoEventType::Issue,
+            GithubRepoActivity::PullRequestMerged { .. } => GithubRepoEventType::PullRequest,
+            GithubRepoActivity::Released { .. } => GithubRepoEventType::Release,
+        }
+    }
+
+    /// What to say about it, on the overlay and in chat. Titles can be left
+    /// empty to keep them off stream.
+    pub fn announcement(&self) -> String {
+        match &self.activity {
+            GithubRepoActivity::Starred { stargazers } => format!(
+                "{} starred {}! That's {} stars",
+                self.sender, self.repo, stargazers
+            ),
+            GithubRepoActivity::IssueOpened { number, title, .. } if title.is_empty() => {
+                format!("{} opened {}#{}", self.sender, self.repo, number)
+            }
+            GithubRepoActivity::IssueOpened { number, title, .. } => {
+                format!("{} opened {}#{}: {}", self.sender, self.repo, number, title)
+            }
+            GithubRepoActivity::PullRequestMerged { number, title, .. } if title.is_empty() => {
+                format!("{}#{} was merged (by {})", self.repo, number, self.sender)
+            }
+            GithubRepoActivity::PullRequestMerged { number, title, .. } => format!(
+                "{}#{} was merged: {} (by {})",
+                self.repo, number, title, self.sender
+            ),
+            GithubRepoActivity::Released { tag, name, .. } => format!(
+                "{} {} is out!{}",
+                self.repo,
+                tag,
+                name.as_ref()
+                    .filter(|name| *name != tag)
+                    .map(|name| format!(" {}", name))
+                    .unwrap_or_default()
+            ),
+        }
+    }
+
+    /// Where chat can go to see it
+    pub fn url(&self) -> String {
+        match &self.activity {
+            GithubRepoActivity::Starred { .. } => format!("https://github.com/{}", self.repo),
+            GithubRepoActivity::IssueOpened { url, .. }
+            | GithubRepoActivity::PullRequestMerged { url, .. }
+            | GithubRepoActivity::Released { url, .. } => url.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GithubIssueState {
+    Open,
+    Closed,
+    /// Only for pull requests
+    Merged,
+}
+
+impl GithubIssueState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GithubIssueState::Open => "open",
+            GithubIssueState::Closed => "closed",
+            GithubIssueState::Merged => "merged",
+        }
+    }
+}
+
+/// An issue or pull request, GitHub numbers them together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GithubIssue {
+    /// `owner/name`
+    pub repo: String,
+    pub number: i64,
+    pub title: String,
+    pub state: GithubIssueState,
+    pub url: String,
+    pub is_pull_request: bool,
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::GithubSponsorship;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GithubSponsorshipAction {
+    Created,
+    TierChanged,
+    /// The sponsorship ends at `effective_date`, it still counts until then
+    PendingCancellation,
+    PendingTierChange,
+    Cancelled,
+    Edited,
+}
+
+impl GithubSponsorshipAction {
+    /// Parse the `action` of a GitHub `sponsorship` webhook
+    pub fn from_action(action: &str) -> Option<Self> {
+        Some(match action {
+            "created" => Self::Created,
+            "tier_changed" => Self::TierChanged,
+            "pending_cancellation" => Self::PendingCancellation,
+            "pending_tier_change" => Self::PendingTierChange,
+            "cancelled" => Self::Cancelled,
+            "edited" => Self::Edited,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GithubSponsorshipEvent {
+    pub action: GithubSponsorshipAction,
+    pub sponsor_login: String,
+    pub tier: GithubSponsorship,
+    /// Set when the tier changed (or is about to)
+    pub previous_tier: Option<GithubSponsorship>,
+    pub is_one_time: bool,
+    /// Private sponsors shouldn't be shown on stream
+    pub is_private: bool,
+    pub effective_date: Option<DateTime<Utc>>,
+}
+
+impl GithubSponsorshipEvent {
+    /// Name to show on stream, respecting private sponsorships
+    pub fn display_name(&self) -> String {
+        if self.is_private {
+            "A secret sponsor".to_string()
+        } else {
+            self.sponsor_login.clone()
+        }
+    }
+}
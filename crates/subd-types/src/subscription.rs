@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use twitch_api2::pubsub::channel_subscriptions::ChannelSubscribeEventsV1Reply;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubTier {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+}
+
+impl SubTier {
+    /// Parse the plan as twitch sends it: "Prime", "1000", "2000" or "3000"
+    pub fn from_plan(plan: &str) -> Self {
+        match plan {
+            "Prime" | "prime" => SubTier::Prime,
+            "2000" => SubTier::Tier2,
+            "3000" => SubTier::Tier3,
+            _ => SubTier::Tier1,
+        }
+    }
+
+    /// The number stored in `twitch_subscriptions.tier`, where 0 is Prime
+    pub fn as_number(&self) -> i64 {
+        match self {
+            SubTier::Prime => 0,
+            SubTier::Tier1 => 1,
+            SubTier::Tier2 => 2,
+            SubTier::Tier3 => 3,
+        }
+    }
+
+    pub fn from_number(tier: i64) -> Self {
+        match tier {
+            0 => SubTier::Prime,
+            2 => SubTier::Tier2,
+            3 => SubTier::Tier3,
+            _ => SubTier::Tier1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubscriptionKind {
+    /// First time subscribing (including Prime)
+    Sub,
+    /// Sharing a resub message
+    Resub,
+    /// A sub given to one specific `recipient`
+    Gift,
+    /// Someone giving away `count` subs to random viewers
+    MysteryGift { count: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionUser {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubMessageEmote {
+    pub id: String,
+    /// Character index of the first character of the emote
+    pub start: usize,
+    /// Character index of the last character of the emote (inclusive)
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubMessage {
+    pub text: String,
+    pub emotes: Vec<SubMessageEmote>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwitchSubscriptionEvent {
+    pub kind: SubscriptionKind,
+    pub tier: SubTier,
+
+    /// The subscriber, or the gifter for gifts. None when the gifter is anonymous.
+    pub user: Option<SubscriptionUser>,
+    /// Who received the gift, for `SubscriptionKind::Gift`
+    pub recipient: Option<SubscriptionUser>,
+    pub is_anonymous: bool,
+
+    pub cumulative_months: Option<i64>,
+    pub streak_months: Option<i64>,
+    pub message: Option<SubMessage>,
+}
+
+impl TwitchSubscriptionEvent {
+    /// Who to thank for this subscription
+    pub fn display_name(&self) -> String {
+        match (&self.user, &self.recipient) {
+            (Some(user), _) => user.display_name.clone(),
+            (None, _) if self.is_anonymous => "An anonymous gifter".to_string(),
+            // EventSub tells us who received a gift separately from who gave it
+            (None, Some(recipient)) => recipient.display_name.clone(),
+            (None, None) => "Someone".to_string(),
+        }
+    }
+
+    pub fn is_prime(&self) -> bool {
+        self.tier == SubTier::Prime
+    }
+
+    pub fn is_gift(&self) -> bool {
+        matches!(
+            self.kind,
+            SubscriptionKind::Gift | SubscriptionKind::MysteryGift { .. }
+        )
+    }
+
+    /// Made up by `get_nyx_sub` or `get_prime_sub`, only meant for the overlay
+    pub fn is_stand_in(&self) -> bool {
+        [&self.user, &self.recipient]
+            .into_iter()
+            .flatten()
+            .any(|user| user.id == crate::STAND_IN_USER_ID)
+    }
+}
+
+fn string_field(value: &Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+fn int_field(value: &Value, key: &str) -> Option<i64> {
+    value.get(key).and_then(|v| v.as_i64()).filter(|v| *v > 0)
+}
+
+fn user_from(value: &Value, id: &str, login: &str, display_name: &str) -> Option<SubscriptionUser> {
+    let id = string_field(value, id)?;
+    let login = string_field(value, login).unwrap_or_default();
+    let display_name = string_field(value, display_name).unwrap_or_else(|| login.clone());
+
+    Some(SubscriptionUser {
+        id,
+        login,
+        display_name,
+    })
+}
+
+fn message_from(value: &Value) -> Option<SubMessage> {
+    let sub_message = value.get("sub_message")?;
+    let text = string_field(sub_message, "message")?;
+
+    let emotes = sub_message
+        .get("emotes")
+        .and_then(|emotes| emotes.as_array())
+        .map(|emotes| {
+            emotes
+                .iter()
+                .filter_map(|emote| {
+                    Some(SubMessageEmote {
+                        id: emote.get("id")?.as_str()?.to_string(),
+                        start: emote.get("start")?.as_u64()? as usize,
+                        end: emote.get("end")?.as_u64()? as usize,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(SubMessage { text, emotes })
+}
+
+impl From<ChannelSubscribeEventsV1Reply> for TwitchSubscriptionEvent {
+    fn from(reply: ChannelSubscribeEventsV1Reply) -> Self {
+        // The pubsub reply has a different struct for each context, and newer
+        // contexts (anonymous gifts, extended subs) keep being added. Every context
+        // shares the same field names though, so read them generically.
+        let value = serde_json::to_value(&reply).unwrap_or(Value::Null);
+
+        let context = string_field(&value, "context").unwrap_or_default();
+        let is_anonymous = context.starts_with("anon");
+        let kind = match context.as_str() {
+            "sub" => SubscriptionKind::Sub,
+            "subgift" | "resubgift" | "anonsubgift" | "anonresubgift" => SubscriptionKind::Gift,
+            _ => SubscriptionKind::Resub,
+        };
+
+        let user = if is_anonymous {
+            None
+        } else {
+            user_from(&value, "user_id", "user_name", "display_name")
+        };
+
+        let recipient = match kind {
+            SubscriptionKind::Gift => user_from(
+                &value,
+                "recipient_id",
+                "recipient_user_name",
+                "recipient_display_name",
+            ),
+            _ => None,
+        };
+
+        Self {
+            kind,
+            tier: SubTier::from_plan(&string_field(&value, "sub_plan").unwrap_or_default()),
+            user,
+            recipient,
+            is_anonymous,
+            cumulative_months: int_field(&value, "cumulative_months"),
+            streak_months: int_field(&value, "streak_months"),
+            message: message_from(&value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(json: &str) -> TwitchSubscriptionEvent {
+        let reply: ChannelSubscribeEventsV1Reply = serde_json::from_str(json).unwrap();
+        reply.into()
+    }
+
+    #[test]
+    fn test_converts_resub_with_emotes() {
+        let sub = reply(
+            r#"{
+                "benefit_end_month": 0,
+                "user_name": "nyxkrage",
+                "display_name": "NyxKrage",
+                "channel_name": "teej_dv",
+                "user_id": "1234",
+                "channel_id": "114257969",
+                "time": "2020-10-20T22:17:43.242793831Z",
+                "sub_message": {
+                    "message": "Kappa twelve months!",
+                    "emotes": [{ "start": 0, "end": 4, "id": "25" }]
+                },
+                "sub_plan": "2000",
+                "sub_plan_name": "Channel Subscription (teej_dv)",
+                "months": 0,
+                "cumulative_months": 12,
+                "streak_months": 3,
+                "context": "resub",
+                "is_gift": false,
+                "multi_month_duration": 0
+            }"#,
+        );
+
+        assert_eq!(sub.kind, SubscriptionKind::Resub);
+        assert_eq!(sub.tier, SubTier::Tier2);
+        assert_eq!(sub.display_name(), "NyxKrage");
+        assert_eq!(sub.cumulative_months, Some(12));
+        assert_eq!(sub.streak_months, Some(3));
+
+        let message = sub.message.unwrap();
+        assert_eq!(message.text, "Kappa twelve months!");
+        assert_eq!(message.emotes[0].id, "25");
+    }
+
+    #[test]
+    fn test_converts_gift() {
+        let sub = reply(
+            r#"{
+                "benefit_end_month": 0,
+                "user_name": "theprimeagen",
+                "display_name": "ThePrimeagen",
+                "channel_name": "teej_dv",
+                "user_id": "1234",
+                "channel_id": "114257969",
+                "time": "2020-10-20T22:17:43.242793831Z",
+                "sub_plan": "1000",
+                "sub_plan_name": "Channel Subscription (teej_dv)",
+                "months": 1,
+                "context": "subgift",
+                "is_gift": true,
+                "sub_message": { "message": "", "emotes": null },
+                "recipient_id": "5678",
+                "recipient_user_name": "nyxkrage",
+                "recipient_display_name": "NyxKrage",
+                "multi_month_duration": 1
+            }"#,
+        );
+
+        assert_eq!(sub.kind, SubscriptionKind::Gift);
+        assert_eq!(sub.display_name(), "ThePrimeagen");
+        assert_eq!(sub.recipient.unwrap().display_name, "NyxKrage");
+        assert!(sub.message.is_none());
+    }
+
+    #[test]
+    fn test_knows_stand_ins() {
+        assert!(crate::get_nyx_sub().is_stand_in());
+        assert!(crate::get_prime_sub().is_stand_in());
+    }
+
+    #[test]
+    fn test_tiers_round_trip() {
+        for tier in [
+            SubTier::Prime,
+            SubTier::Tier1,
+            SubTier::Tier2,
+            SubTier::Tier3,
+        ] {
+            assert_eq!(SubTier::from_number(tier.as_number()), tier);
+        }
+
+        assert_eq!(SubTier::from_plan("Prime"), SubTier::Prime);
+        assert_eq!(SubTier::from_plan("3000"), SubTier::Tier3);
+    }
+}
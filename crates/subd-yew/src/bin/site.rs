@@ -2,12 +2,23 @@ use std::collections::VecDeque;
 
 use chrono::{self, Utc};
 use subd_types::Event as SubdEvent;
+use subd_types::GithubSponsorshipAction;
+use subd_types::ThemesongPlay;
+use subd_yew::components::credits_roll::CreditsRoll;
+use subd_yew::components::follow_notification::FollowNotification;
+use subd_yew::components::repo_activity_notification::RepoActivityNotification;
+use subd_yew::components::sponsor_notification::SponsorNotification;
 use subd_yew::components::sub_notification::SubNotification;
 use subd_yew::components::themesong_downloader::ThemesongDownloader;
+use subd_yew::components::themesong_player::ThemesongPlayer;
+use subd_yew::components::themesong_review::ThemesongReview;
 use twitch_irc::message::{Badge, Emote, PrivmsgMessage};
 use yew::prelude::*;
 use yew_hooks::{use_list, use_web_socket};
 
+// TODO(generalize)
+const FOLLOWER_GOAL: usize = 1000;
+
 // use_reducer or use_reducer_eq
 //  Probably what we want to end up using to dispatch over Event
 // Might not need to though
@@ -164,9 +175,16 @@ fn default_messages() -> Vec<PrivmsgMessage> {
 fn reducer() -> Html {
     let history = use_list(default_messages());
     let subcount = use_state(|| 0);
+    let followercount = use_state(|| 0);
 
     let new_sub = use_state(|| None);
+    let new_follow = use_state(|| None);
+    let new_sponsor = use_state(|| None);
+    let repo_event = use_state(|| None);
     let themesong = use_state(|| None);
+    let pending_themesongs = use_state(Vec::new);
+    let playing_themesong = use_state(|| None);
+    let credits = use_state(|| None);
 
     // let animation_state = use_state(|| true);
     // {
@@ -177,14 +195,21 @@ fn reducer() -> Html {
     //     timeout.forget();
     // }
 
-    let ws = use_web_socket("ws://192.168.4.97:9001".to_string());
+    let ws = use_web_socket(subd_yew::WEBSOCKET_URL.to_string());
 
     {
         let history = history.clone();
         let ws = ws.clone();
         let subcount = subcount.clone();
+        let followercount = followercount.clone();
         let new_sub = new_sub.clone();
+        let new_follow = new_follow.clone();
+        let new_sponsor = new_sponsor.clone();
+        let repo_event = repo_event.clone();
         let themesong = themesong.clone();
+        let pending_themesongs = pending_themesongs.clone();
+        let playing_themesong = playing_themesong.clone();
+        let credits = credits.clone();
 
         // Receive message by depending on `ws.message`.
         use_effect_with_deps(
@@ -201,7 +226,27 @@ fn reducer() -> Html {
                             // handle_twitch_sub(subscription)
                             new_sub.set(Some(subscription))
                         }
+                        SubdEvent::TwitchFollowerCount(count) => followercount.set(count),
+                        SubdEvent::TwitchFollow(follow) => new_follow.set(Some(follow)),
+                        SubdEvent::GithubSponsorshipEvent(sponsorship) => match sponsorship.action {
+                            GithubSponsorshipAction::Created
+                            | GithubSponsorshipAction::TierChanged => {
+                                new_sponsor.set(Some(sponsorship))
+                            }
+                            _ => {}
+                        },
+                        SubdEvent::GithubRepoEvent(event) => repo_event.set(Some(event)),
                         SubdEvent::ThemesongDownload(download) => themesong.set(Some(download)),
+                        SubdEvent::ThemesongsPending(pending) => pending_themesongs.set(pending),
+                        SubdEvent::ThemesongPlay(ThemesongPlay::Start { display_name, .. }) => {
+                            playing_themesong.set(Some(display_name))
+                        }
+                        SubdEvent::ThemesongPlay(ThemesongPlay::Finish { .. }) => {
+                            playing_themesong.set(None)
+                        }
+                        SubdEvent::StreamCredits(stream_credits) => {
+                            credits.set(Some(stream_credits))
+                        }
                         _ => {}
                     }
                 }
@@ -223,6 +268,30 @@ fn reducer() -> Html {
         None => html! {},
     };
 
+    let follow_notification = match &(*new_follow) {
+        Some(follow) => {
+            let follow = follow.clone();
+            html! { <FollowNotification follow={follow} /> }
+        }
+        None => html! {},
+    };
+
+    let sponsor_notification = match &(*new_sponsor) {
+        Some(sponsorship) => {
+            let sponsorship = sponsorship.clone();
+            html! { <SponsorNotification sponsorship={sponsorship} /> }
+        }
+        None => html! {},
+    };
+
+    let repo_notification = match &(*repo_event) {
+        Some(event) => {
+            let event = event.clone();
+            html! { <RepoActivityNotification event={event} /> }
+        }
+        None => html! {},
+    };
+
     let themesong = match &(*themesong) {
         Some(themesong) => {
             let themesong = themesong.clone();
@@ -231,11 +300,22 @@ fn reducer() -> Html {
         None => html! {},
     };
 
+    let credits = match &(*credits) {
+        Some(credits) => {
+            let credits = credits.clone();
+            html! { <CreditsRoll credits={credits} /> }
+        }
+        None => html! {},
+    };
+
     html! {
         <div class={ "subd" }>
             <div class={"subd-goal"}>
                 <p>{ format!("{} / 420", *subcount) }</p>
             </div>
+            <div class={"subd-follower-goal"}>
+                <p>{ format!("{} / {} followers", *followercount, FOLLOWER_GOAL) }</p>
+            </div>
             <div class={"subd-chat"}>
             {
                 {
@@ -248,7 +328,13 @@ fn reducer() -> Html {
             }
             </div>
             <> { notification } </>
+            <> { follow_notification } </>
+            <> { sponsor_notification } </>
+            <> { repo_notification } </>
             <> { themesong } </>
+            <ThemesongReview pending={(*pending_themesongs).clone()} />
+            <ThemesongPlayer playing={(*playing_themesong).clone()} />
+            <> { credits } </>
         </div>
     }
 }
@@ -0,0 +1,38 @@
+use subd_types::Event as SubdEvent;
+use subd_yew::components::sponsor_wall::SponsorWall;
+use yew::prelude::*;
+use yew_hooks::use_web_socket;
+
+/// Always-on list of current GitHub sponsors, for its own OBS browser source
+#[function_component(SponsorWallPage)]
+fn sponsor_wall_page() -> Html {
+    let sponsors = use_state(Vec::new);
+    let ws = use_web_socket(subd_yew::WEBSOCKET_URL.to_string());
+
+    {
+        let sponsors = sponsors.clone();
+
+        use_effect_with_deps(
+            move |message| {
+                if let Some(message) = &**message {
+                    match serde_json::from_str(message) {
+                        Ok(SubdEvent::GithubSponsorWall(current)) => sponsors.set(current),
+                        Ok(_) => {}
+                        Err(err) => log::warn!("Unknown event: {:?}", err),
+                    }
+                }
+                || ()
+            },
+            ws.message,
+        );
+    }
+
+    html! {
+        <SponsorWall sponsors={(*sponsors).clone()} />
+    }
+}
+
+fn main() {
+    wasm_logger::init(wasm_logger::Config::default());
+    yew::start_app::<SponsorWallPage>();
+}
@@ -0,0 +1,127 @@
+use gloo_timers::callback::Timeout;
+use subd_types::{CreditsEntry, StreamCredits};
+use yew::prelude::*;
+
+/// How long each line of the credits stays on screen, in milliseconds
+const MS_PER_LINE: u32 = 1000;
+const MIN_DURATION_MS: u32 = 10_000;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub credits: StreamCredits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Msg {
+    Roll,
+    Finish,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Rolling,
+    Done,
+}
+
+#[derive(Debug)]
+pub struct CreditsRoll {
+    state: State,
+
+    #[allow(unused)]
+    timeout: Timeout,
+}
+
+/// Long enough for every section title and name to scroll past
+fn roll_duration(credits: &StreamCredits) -> u32 {
+    let lines: usize = credits
+        .sections()
+        .iter()
+        .map(|(_, entries)| entries.len() + 1)
+        .sum();
+
+    (lines as u32 * MS_PER_LINE).max(MIN_DURATION_MS)
+}
+
+impl CreditsRoll {
+    fn get_timeout(ctx: &Context<Self>) -> Timeout {
+        let link = ctx.link().clone();
+        Timeout::new(roll_duration(&ctx.props().credits), move || {
+            link.send_message(Msg::Finish)
+        })
+    }
+}
+
+fn render_entry(entry: &CreditsEntry) -> Html {
+    match entry.amount {
+        Some(amount) => html! {
+            <p>{ entry.name.clone() } <span class={"subd-credits-amount"}>{ format!(" ({})", amount) }</span></p>
+        },
+        None => html! { <p>{ entry.name.clone() }</p> },
+    }
+}
+
+impl Component for CreditsRoll {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            state: State::Rolling,
+            timeout: Self::get_timeout(ctx),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Roll => {
+                self.state = State::Rolling;
+                self.timeout = Self::get_timeout(ctx);
+            }
+            Msg::Finish => {
+                self.state = State::Done;
+            }
+        }
+
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        if self.state == State::Done {
+            return html! {};
+        }
+
+        let credits = &ctx.props().credits;
+        let sections = credits.sections();
+        let body = if sections.is_empty() {
+            html! { <p>{ "Thanks for watching!" }</p> }
+        } else {
+            sections
+                .iter()
+                .map(|(title, entries)| {
+                    html! {
+                        <div class={"subd-credits-section"}>
+                            <h2>{ *title }</h2>
+                            { entries.iter().map(render_entry).collect::<Html>() }
+                        </div>
+                    }
+                })
+                .collect::<Html>()
+        };
+
+        html! {
+            <div class={"subd-credits"}>
+                <div
+                    class={"subd-credits-roll"}
+                    style={format!("animation-duration: {}ms", roll_duration(credits))}
+                >
+                    { body }
+                </div>
+            </div>
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        ctx.link().send_message(Msg::Roll);
+        true
+    }
+}
@@ -0,0 +1,80 @@
+use gloo_timers::callback::Timeout;
+use subd_types::TwitchFollowEvent;
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub follow: TwitchFollowEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Msg {
+    ShowNotification,
+    HideNotification,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Show,
+    Hide,
+}
+
+#[derive(Debug)]
+pub struct FollowNotification {
+    state: State,
+
+    #[allow(unused)]
+    timeout: Timeout,
+}
+
+impl FollowNotification {
+    fn get_timeout(ctx: &Context<Self>) -> Timeout {
+        let link = ctx.link().clone();
+        Timeout::new(3000, move || link.send_message(Msg::HideNotification))
+    }
+}
+
+impl Component for FollowNotification {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            state: State::Show,
+            timeout: Self::get_timeout(ctx),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::ShowNotification => {
+                self.state = State::Show;
+                self.timeout = Self::get_timeout(ctx);
+            }
+            Msg::HideNotification => {
+                self.state = State::Hide;
+            }
+        }
+
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let name = ctx.props().follow.user_name.clone();
+        let animation = match self.state {
+            State::Show => "animate__bounceInDown",
+            State::Hide => "animate__bounceOutLeft",
+        };
+
+        html! {
+            <div class={format!("subd-follow animate__animated {}", animation)}>
+                { name } { " is now following!" }
+            </div>
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        ctx.link().send_message(Msg::ShowNotification);
+        true
+    }
+}
@@ -1,2 +1,9 @@
+pub mod credits_roll;
+pub mod follow_notification;
+pub mod repo_activity_notification;
+pub mod sponsor_notification;
+pub mod sponsor_wall;
 pub mod sub_notification;
 pub mod themesong_downloader;
+pub mod themesong_player;
+pub mod themesong_review;
@@ -0,0 +1,89 @@
+use gloo_timers::callback::Timeout;
+use subd_types::{GithubRepoEvent, GithubRepoEventType};
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub event: GithubRepoEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Msg {
+    ShowNotification,
+    HideNotification,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Show,
+    Hide,
+}
+
+#[derive(Debug)]
+pub struct RepoActivityNotification {
+    state: State,
+
+    #[allow(unused)]
+    timeout: Timeout,
+}
+
+impl RepoActivityNotification {
+    fn get_timeout(ctx: &Context<Self>) -> Timeout {
+        let link = ctx.link().clone();
+        Timeout::new(5000, move || link.send_message(Msg::HideNotification))
+    }
+}
+
+fn type_class(event: &GithubRepoEvent) -> &'static str {
+    match event.event_type() {
+        GithubRepoEventType::Star => "subd-repo-activity-star",
+        GithubRepoEventType::Issue => "subd-repo-activity-issue",
+        GithubRepoEventType::PullRequest => "subd-repo-activity-pr",
+        GithubRepoEventType::Release => "subd-repo-activity-release",
+    }
+}
+
+impl Component for RepoActivityNotification {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            state: State::Show,
+            timeout: Self::get_timeout(ctx),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::ShowNotification => {
+                self.state = State::Show;
+                self.timeout = Self::get_timeout(ctx);
+            }
+            Msg::HideNotification => {
+                self.state = State::Hide;
+            }
+        }
+
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let animation = match self.state {
+            State::Show => "animate__bounceInDown",
+            State::Hide => "animate__bounceOutLeft",
+        };
+
+        let event = &ctx.props().event;
+        html! {
+            <div class={format!("subd-repo-activity {} animate__animated {}", type_class(event), animation)}>
+                { event.announcement() }
+            </div>
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        ctx.link().send_message(Msg::ShowNotification);
+        true
+    }
+}
@@ -0,0 +1,89 @@
+use gloo_timers::callback::Timeout;
+use subd_types::{GithubSponsorshipAction, GithubSponsorshipEvent};
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub sponsorship: GithubSponsorshipEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Msg {
+    ShowNotification,
+    HideNotification,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Show,
+    Hide,
+}
+
+#[derive(Debug)]
+pub struct SponsorNotification {
+    state: State,
+
+    #[allow(unused)]
+    timeout: Timeout,
+}
+
+impl SponsorNotification {
+    fn get_timeout(ctx: &Context<Self>) -> Timeout {
+        let link = ctx.link().clone();
+        Timeout::new(5000, move || link.send_message(Msg::HideNotification))
+    }
+}
+
+fn alert_text(sponsorship: &GithubSponsorshipEvent) -> String {
+    let name = sponsorship.display_name();
+    let tier = &sponsorship.tier.tier_name;
+    match sponsorship.action {
+        GithubSponsorshipAction::TierChanged => format!("{} is now sponsoring at {}!", name, tier),
+        _ if sponsorship.is_one_time => format!("{} sponsored on GitHub ({})!", name, tier),
+        _ => format!("{} is now a GitHub sponsor ({})!", name, tier),
+    }
+}
+
+impl Component for SponsorNotification {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            state: State::Show,
+            timeout: Self::get_timeout(ctx),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::ShowNotification => {
+                self.state = State::Show;
+                self.timeout = Self::get_timeout(ctx);
+            }
+            Msg::HideNotification => {
+                self.state = State::Hide;
+            }
+        }
+
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let animation = match self.state {
+            State::Show => "animate__bounceInDown",
+            State::Hide => "animate__bounceOutLeft",
+        };
+
+        html! {
+            <div class={format!("subd-sponsor animate__animated {}", animation)}>
+                { alert_text(&ctx.props().sponsorship) }
+            </div>
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        ctx.link().send_message(Msg::ShowNotification);
+        true
+    }
+}
@@ -0,0 +1,33 @@
+use subd_types::GithubSponsor;
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub sponsors: Vec<GithubSponsor>,
+}
+
+fn render_sponsor(sponsor: &GithubSponsor) -> Html {
+    let tier = match &sponsor.tier {
+        Some(tier) => tier.tier_name.clone(),
+        None if sponsor.is_one_time => "One time".to_string(),
+        None => "Custom amount".to_string(),
+    };
+
+    html! {
+        <div class={"subd-sponsor-wall-entry"}>
+            <img src={format!("https://github.com/{}.png?size=64", sponsor.login)} alt={"avatar"} />
+            <p>{ sponsor.display_name() }</p>
+            <p class={"subd-sponsor-wall-tier"}>{ tier }</p>
+        </div>
+    }
+}
+
+#[function_component(SponsorWall)]
+pub fn sponsor_wall(props: &Props) -> Html {
+    html! {
+        <div class={"subd-sponsor-wall"}>
+            <h2>{ "GitHub Sponsors" }</h2>
+            { props.sponsors.iter().map(render_sponsor).collect::<Html>() }
+        </div>
+    }
+}
@@ -1,7 +1,7 @@
 #![allow(unused_variables)]
 
 use gloo_timers::callback::Timeout;
-use subd_types::TwitchSubscriptionEvent;
+use subd_types::{SubMessage, SubTier, SubscriptionKind, TwitchSubscriptionEvent};
 use yew::prelude::*;
 
 #[derive(Clone, PartialEq, Properties)]
@@ -63,21 +63,24 @@ impl Component for SubNotification {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let sub = ctx.props().subscription.clone();
-        let name = sub.display_name();
-
-        if self.state == State::Show {
-            html! {
-                <div class={"subd-notification animate__animated animate__bounceInDown"}>
-                    { name } { " has subscribed!" }
-                </div>
-            }
-        } else {
-            html! {
-                <div class={"subd-notification animate__animated animate__bounceOutLeft"}>
-                    { name } { " has subscribed!" }
-                </div>
-            }
+        let sub = &ctx.props().subscription;
+        let animation = match self.state {
+            State::Show => "animate__bounceInDown",
+            State::Hide => "animate__bounceOutLeft",
+        };
+
+        let message = match &sub.message {
+            Some(message) => html! {
+                <div class={"subd-notification-message"}>{ render_sub_message(message) }</div>
+            },
+            None => html! {},
+        };
+
+        html! {
+            <div class={format!("subd-notification {} animate__animated {}", alert_class(sub), animation)}>
+                <p>{ alert_text(sub) }</p>
+                { message }
+            </div>
         }
     }
 
@@ -91,3 +94,93 @@ impl Component for SubNotification {
 
     fn destroy(&mut self, ctx: &Context<Self>) {}
 }
+
+fn alert_class(sub: &TwitchSubscriptionEvent) -> &'static str {
+    match sub.kind {
+        SubscriptionKind::Sub if sub.is_prime() => "subd-notification-prime",
+        SubscriptionKind::Sub => "subd-notification-sub",
+        SubscriptionKind::Resub => "subd-notification-resub",
+        SubscriptionKind::Gift => "subd-notification-gift",
+        SubscriptionKind::MysteryGift { .. } => "subd-notification-mystery-gift",
+    }
+}
+
+fn tier_text(tier: SubTier) -> &'static str {
+    match tier {
+        SubTier::Prime => "with Prime",
+        SubTier::Tier1 => "",
+        SubTier::Tier2 => "at Tier 2",
+        SubTier::Tier3 => "at Tier 3",
+    }
+}
+
+fn alert_text(sub: &TwitchSubscriptionEvent) -> String {
+    let name = sub.display_name();
+    let tier = tier_text(sub.tier);
+
+    match &sub.kind {
+        SubscriptionKind::Sub if tier.is_empty() => format!("{} has subscribed!", name),
+        SubscriptionKind::Sub => format!("{} has subscribed {}!", name, tier),
+        SubscriptionKind::Resub => {
+            let months = sub.cumulative_months.unwrap_or(1);
+            match sub.streak_months {
+                Some(streak) if streak > 1 => format!(
+                    "{} resubscribed for {} months ({} in a row)!",
+                    name, months, streak
+                ),
+                _ => format!("{} resubscribed for {} months!", name, months),
+            }
+        }
+        SubscriptionKind::Gift => match (&sub.user, &sub.recipient) {
+            (Some(gifter), Some(recipient)) => format!(
+                "{} gifted a sub to {}!",
+                gifter.display_name, recipient.display_name
+            ),
+            (None, Some(recipient)) if sub.is_anonymous => {
+                format!("An anonymous gifter gifted a sub to {}!", recipient.display_name)
+            }
+            (None, Some(recipient)) => format!("{} got a gifted sub!", recipient.display_name),
+            _ => format!("{} gifted a sub!", name),
+        },
+        SubscriptionKind::MysteryGift { count } => {
+            let plural = if *count == 1 { "" } else { "s" };
+            format!("{} is gifting {} sub{} to chat!", name, count, plural)
+        }
+    }
+}
+
+fn make_emote_url(id: &str) -> String {
+    format!(
+        "https://static-cdn.jtvnw.net/emoticons/v2/{}/default/dark/2.0",
+        id
+    )
+}
+
+fn render_sub_message(message: &SubMessage) -> Html {
+    let chars = message.text.chars().collect::<Vec<char>>();
+    let mut emotes = message.emotes.clone();
+    emotes.sort_by_key(|emote| emote.start);
+
+    let mut pieces: Vec<Html> = vec![];
+    let mut position = 0;
+    for emote in emotes {
+        if emote.start < position || emote.end >= chars.len() {
+            continue;
+        }
+
+        let segment = chars[position..emote.start].iter().collect::<String>();
+        if !segment.is_empty() {
+            pieces.push(html! { <span> { segment } </span> });
+        }
+
+        pieces.push(html! { <img src={make_emote_url(&emote.id)} alt={"emote"} /> });
+        position = emote.end + 1;
+    }
+
+    let remaining = chars[position..].iter().collect::<String>();
+    if !remaining.is_empty() {
+        pieces.push(html! { <span> { remaining } </span> });
+    }
+
+    html! { <p> { pieces } </p> }
+}
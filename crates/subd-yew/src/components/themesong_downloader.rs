@@ -31,6 +31,16 @@ impl Component for ThemesongDownloader {
                     </div>
                 }
             }
+            ThemesongDownload::Queued {
+                display_name,
+                position,
+            } => {
+                html! {
+                    <div class={"subd-themesong"}>
+                        { format!("Themesong for {} is queued, {} ahead", display_name, position) }
+                    </div>
+                }
+            }
             ThemesongDownload::Start { display_name } => {
                 html! {
                     <div class={"subd-themesong"}>
@@ -38,9 +48,27 @@ impl Component for ThemesongDownloader {
                     </div>
                 }
             }
+            ThemesongDownload::Progress {
+                display_name,
+                stage,
+            } => {
+                html! {
+                    <div class={"subd-themesong"}>
+                        { format!("Downloading themesong for: {}", display_name) }
+                        <span class={"subd-themesong-progress"}>
+                            <span
+                                class={"subd-themesong-progress-bar"}
+                                style={format!("width: {}%", stage.percent())}
+                            />
+                        </span>
+                        <span class={"subd-themesong-stage"}>{ stage.as_str() }</span>
+                    </div>
+                }
+            }
             ThemesongDownload::Finish {
                 display_name,
                 success,
+                error,
             } => {
                 if *success {
                     html! {
@@ -52,6 +80,9 @@ impl Component for ThemesongDownloader {
                     html! {
                         <div class={"subd-themesong"}>
                             { format!("Failed to downloaded themesong: {}", display_name) }
+                            { for error.iter().map(|error| html! {
+                                <span class={"subd-themesong-error"}>{ error }</span>
+                            }) }
                         </div>
                     }
                 }
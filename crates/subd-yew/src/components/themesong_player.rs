@@ -0,0 +1,19 @@
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    /// Whose themesong is playing, if anyone's
+    pub playing: Option<String>,
+}
+
+#[function_component(ThemesongPlayer)]
+pub fn themesong_player(props: &Props) -> Html {
+    match &props.playing {
+        Some(display_name) => html! {
+            <div class={"subd-themesong-playing"}>
+                <p>{ format!("♪ {}'s themesong", display_name) }</p>
+            </div>
+        },
+        None => html! {},
+    }
+}
@@ -0,0 +1,25 @@
+use yew::prelude::*;
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    /// Who has a themesong waiting for a mod
+    pub pending: Vec<String>,
+}
+
+#[function_component(ThemesongReview)]
+pub fn themesong_review(props: &Props) -> Html {
+    if props.pending.is_empty() {
+        return html! {};
+    }
+
+    let text = match props.pending.len() {
+        1 => "1 themesong waiting for review".to_string(),
+        count => format!("{} themesongs waiting for review", count),
+    };
+
+    html! {
+        <div class={"subd-themesong-review"}>
+            <p>{ text }</p>
+        </div>
+    }
+}
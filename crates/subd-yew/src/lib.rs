@@ -1 +1,4 @@
 pub mod components;
+
+/// Where chat.rs serves events to the overlays
+pub const WEBSOCKET_URL: &str = "ws://192.168.4.97:9001";
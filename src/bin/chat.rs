@@ -7,28 +7,45 @@
 //      - Add a sound
 //          - Download the sound locally
 //          - Associated sound w/ user_id
-//      - Approve/Reject a sound
 
+use std::collections::HashMap;
 use std::env;
 use std::time::Duration;
 
 use anyhow::Result;
+use chrono::Utc;
 use clap::Parser;
 use either::Either;
-use futures::SinkExt;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use obws::requests::SceneItemProperties;
 use obws::requests::SourceFilterVisibility;
 use obws::Client as OBSClient;
 use reqwest::Client as ReqwestClient;
 use server::commands;
+use server::credits;
+use server::follows;
+use server::issues;
+use server::perks;
+use server::subscriptions;
 use server::themesong;
+use server::themesong::clip::{ClipRange, CLIP_HELP};
+use server::themesong::jobs::JobOutcome;
 use server::users;
+use server::users::sync::ChannelRoles;
+use server::webhooks;
+use subd_db::ThemesongJobStatus;
+use subd_gh::{GithubApi, GithubClient};
+use subd_types::get_fake_follow;
 use subd_types::get_nyx_sub;
 use subd_types::get_prime_sub;
 use subd_types::Event;
+use subd_types::ThemesongControl;
 use subd_types::ThemesongDownload;
 use subd_types::ThemesongPlay;
+use subd_types::ThemesongPreview;
+use subd_twitch::eventsub::EventSubConfig;
+use subd_twitch::helix::Helix;
+use subd_twitch::pubsub::PubSubConfig;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
@@ -45,15 +62,20 @@ use twitch_irc::TwitchIRCClient;
 
 const CONNECT_OBS: bool = false;
 
+/// `can_preview_off_stream` is whether `$SUBD_PREVIEW_AUDIO_DEVICE` was opened
 async fn handle_twitch_msg(
     tx: broadcast::Sender<Event>,
     mut rx: broadcast::Receiver<Event>,
+    can_preview_off_stream: bool,
 ) -> Result<()> {
     let mut conn = subd_db::get_handle().await;
+    let github = GithubClient::from_env();
 
     let config = get_chat_config();
     let (_, client) = TwitchIRCClient::<SecureTCPTransport, StaticLoginCredentials>::new(config);
 
+    let mut preview_cooldown = themesong::commands::PreviewCooldown::new();
+
     loop {
         let event = rx.recv().await?;
         let msg = match event {
@@ -70,11 +92,11 @@ async fn handle_twitch_msg(
         subd_db::save_twitch_message(&mut conn, &msg.sender.id, &msg.message_text).await?;
 
         let user_id = subd_db::get_user_from_twitch_user(&mut conn, &msg.sender.id).await?;
-        users::update_user_roles_once_per_day(&mut conn, &user_id, &msg).await?;
+        users::update_user_roles_once_per_day(&mut conn, &github, &user_id, &msg).await?;
 
         if themesong::should_play_themesong(&mut conn, &user_id).await? {
             println!("  Sending themesong play event...");
-            tx.send(Event::ThemesongPlay(ThemesongPlay {
+            tx.send(Event::ThemesongPlay(ThemesongPlay::Request {
                 user_id,
                 display_name: msg.sender.name.clone(),
             }))?;
@@ -93,6 +115,75 @@ async fn handle_twitch_msg(
                     let _ = client.say("teej_dv".to_string(), echo.contents).await;
                 }
             }
+            "!followage" => {
+                let login = splitmsg.get(1).unwrap_or(&msg.sender.login);
+                say(&client, follows::followage(&mut conn, login).await?).await?;
+            }
+            "!topgifters" => {
+                say(&client, subscriptions::top_gifters(&mut conn).await?).await?;
+            }
+            "!subhistory" => {
+                let login = splitmsg.get(1).unwrap_or(&msg.sender.login);
+                say(&client, subscriptions::sub_history(&mut conn, login).await?).await?;
+            }
+            "!issue" => {
+                let arg = splitmsg.get(1).map(String::as_str).unwrap_or("");
+                let repo = issues::issues_repo();
+                say(&client, issues::issue(&github, &repo, &msg.sender.name, arg).await?).await?;
+            }
+            "!suggest" if is_mod_or_broadcaster(&msg) => {
+                let text = splitmsg[1..].join(" ");
+                let repo = issues::issues_repo();
+                let reply =
+                    issues::suggest(&mut conn, &github, &repo, &msg.sender.name, &text).await?;
+                say(&client, reply).await?;
+            }
+            "!credits" if is_mod_or_broadcaster(&msg) => {
+                tx.send(Event::RequestStreamCredits)?;
+            }
+            "!skip" | "!stopsong" if is_mod_or_broadcaster(&msg) => {
+                tx.send(Event::ThemesongControl(match splitmsg[0].as_str() {
+                    "!skip" => ThemesongControl::Skip,
+                    _ => ThemesongControl::Stop,
+                }))?;
+            }
+            "!volume" if is_mod_or_broadcaster(&msg) => {
+                match splitmsg
+                    .get(1)
+                    .and_then(|percent| themesong::player::parse_volume(percent))
+                {
+                    Some(volume) => {
+                        tx.send(Event::ThemesongControl(ThemesongControl::Volume(volume)))?;
+                        say(&client, format!("Themesong volume: {:.0}%", volume * 100.)).await?;
+                    }
+                    None => say(&client, "!volume <0-100>").await?,
+                }
+            }
+            "!session" if is_mod_or_broadcaster(&msg) => {
+                let reply = match splitmsg.get(1).map(String::as_str) {
+                    Some("start") => {
+                        let session = subd_db::start_stream_session(&mut conn, Utc::now()).await?;
+                        session_status(Some(session))
+                    }
+                    Some("status") => {
+                        session_status(subd_db::get_current_stream_session(&mut conn).await?)
+                    }
+                    Some("end") => {
+                        match subd_db::end_stream_session(&mut conn, Utc::now()).await? {
+                            Some(_) => "Session ended, themesongs play again next session",
+                            None => "No session going",
+                        }
+                        .to_string()
+                    }
+                    _ => "!session start|end|status".to_string(),
+                };
+                say(&client, reply).await?;
+            }
+            // Local stand-in for EventSub, so the follow alert can be tested without a real follow
+            "!testfollow" if msg.badges.iter().any(|badge| badge.name == "broadcaster") => {
+                let login = splitmsg.get(1).unwrap_or(&msg.sender.login);
+                tx.send(Event::TwitchFollow(get_fake_follow(login)))?;
+            }
             _ => {}
         };
 
@@ -113,14 +204,66 @@ async fn handle_twitch_msg(
         }
 
         if msg.message_text.starts_with("!themesong") {
-            tx.send(Event::ThemesongDownload(ThemesongDownload::Request {
-                msg: msg.clone(),
-            }))?;
+            let is_moderator = is_mod_or_broadcaster(&msg);
+
+            match splitmsg.get(1).map(String::as_str) {
+                Some("approve" | "reject" | "pending" | "review") => {
+                    if is_moderator {
+                        handle_themesong_review(
+                            &mut conn,
+                            &tx,
+                            &client,
+                            &user_id,
+                            &splitmsg[1..],
+                            can_preview_off_stream,
+                        )
+                        .await?;
+                    }
+                }
+                Some("info") => {
+                    let reply =
+                        themesong::commands::info(&mut conn, &user_id, &msg.sender.name).await?;
+                    say(&client, reply).await?;
+                }
+                Some("delete") => {
+                    let reply =
+                        themesong::commands::delete(&mut conn, &user_id, &msg.sender.name).await?;
+                    say(&client, reply).await?;
+                    tx.send(Event::RequestThemesongsPending)?;
+                }
+                Some("preview") => {
+                    let preview = themesong::commands::preview(
+                        &mut conn,
+                        &mut preview_cooldown,
+                        &user_id,
+                        &msg.sender.name,
+                        is_moderator,
+                        std::time::Instant::now(),
+                    )
+                    .await?;
+
+                    match preview {
+                        themesong::commands::Preview::Play => {
+                            tx.send(Event::ThemesongPreview(ThemesongPreview {
+                                user_id,
+                                display_name: msg.sender.name.clone(),
+                                private: false,
+                            }))?;
+                        }
+                        themesong::commands::Preview::Refuse(reply) => say(&client, reply).await?,
+                    }
+                }
+                _ => {
+                    tx.send(Event::ThemesongDownload(ThemesongDownload::Request {
+                        msg: msg.clone(),
+                    }))?;
+                }
+            }
         }
 
         if msg.message_text.starts_with("!set ") {
             println!("  ... doing set command: {:?}", msg.message_text);
-            let set_result = handle_set_command(&mut conn, &client, msg).await;
+            let set_result = handle_set_command(&mut conn, &github, &client, msg).await;
             if let Err(err) = set_result {
                 say(&client, format!("Error while setting: {:?}", err)).await?;
             }
@@ -143,6 +286,7 @@ async fn handle_set_command<
     L: twitch_irc::login::LoginCredentials,
 >(
     conn: &mut sqlx::SqliteConnection,
+    github: &GithubClient,
     client: &TwitchIRCClient<T, L>,
     msg: twitch_irc::message::PrivmsgMessage,
 ) -> Result<()> {
@@ -153,6 +297,7 @@ async fn handle_set_command<
         .collect::<Vec<String>>();
 
     // !set github <login>
+    // !set github verify
     if splitmsg[1] == "github" {
         println!("  ... split msg: {:?}", splitmsg);
 
@@ -162,17 +307,13 @@ async fn handle_set_command<
         }
 
         let user_id = subd_db::get_user_from_twitch_user(conn, &msg.sender.id).await?;
-
-        let github_login = splitmsg[2].clone();
-        subd_db::set_github_info_for_user(conn, &user_id, github_login.as_str()).await?;
-        say(
-            client,
-            format!(
-                "Succesfully set: twitch {} -> github {}",
-                msg.sender.name, github_login
-            ),
-        )
-        .await?;
+        let reply =
+            users::github::set_github(conn, github, &user_id, &msg.sender.name, &splitmsg[2])
+                .await?;
+        if reply.verified {
+            users::update_user_roles(conn, github, &user_id, &msg).await?;
+        }
+        say(client, reply.message).await?;
     }
 
     // TODO(user_roles)
@@ -237,9 +378,311 @@ async fn handle_twitch_chat(
     Ok(())
 }
 
+async fn handle_twitch_follows(
+    tx: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        let event = rx.recv().await?;
+        let follow = match event {
+            Event::TwitchFollow(follow) => follow,
+            _ => continue,
+        };
+
+        println!("New follow: {:?}", follow.user_name);
+        // `!testfollow` only needs the alert, not a made up follower in the database
+        if follow.is_stand_in() {
+            continue;
+        }
+
+        let count = follows::record_follow(&mut conn, &follow).await?;
+        tx.send(Event::TwitchFollowerCount(count))?;
+    }
+}
+
+async fn handle_twitch_subscriptions(
+    _: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        let event = rx.recv().await?;
+        let sub = match event {
+            Event::TwitchSubscription(sub) => sub,
+            _ => continue,
+        };
+
+        // The subs `main` sends at startup are for testing the overlay, nobody really subbed
+        if sub.is_stand_in() {
+            continue;
+        }
+
+        // A bad event shouldn't stop us from recording the next one
+        if let Err(err) = subscriptions::record_subscription(&mut conn, &sub).await {
+            println!("Failed to save subscription: {:?} -> {:?}", sub, err);
+        }
+    }
+}
+
+async fn handle_github_sponsorships(
+    tx: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        let event = rx.recv().await?;
+        let sponsorship = match event {
+            Event::GithubSponsorshipEvent(sponsorship) => sponsorship,
+            _ => continue,
+        };
+
+        println!(
+            "GitHub sponsorship: {} {:?} ({})",
+            sponsorship.sponsor_login, sponsorship.action, sponsorship.tier.tier_name
+        );
+        if let Some(change) = users::sponsors::update_sponsor_roles(&mut conn, &sponsorship).await? {
+            tx.send(Event::UserRolesChanged(change))?;
+        }
+    }
+}
+
+async fn handle_github_repo_activity(
+    _: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let config = get_chat_config();
+    let (_, client) = TwitchIRCClient::<SecureTCPTransport, StaticLoginCredentials>::new(config);
+
+    loop {
+        let event = rx.recv().await?;
+        let event = match event {
+            Event::GithubRepoEvent(event) => event,
+            _ => continue,
+        };
+
+        say(&client, format!("{} {}", event.announcement(), event.url())).await?;
+    }
+}
+
+/// File the `!suggest`ions that were made while GitHub was unreachable
+async fn handle_pending_suggestions(
+    _: broadcast::Sender<Event>,
+    _: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+    let github = GithubClient::from_env();
+
+    let config = get_chat_config();
+    let (_, client) = TwitchIRCClient::<SecureTCPTransport, StaticLoginCredentials>::new(config);
+
+    let mut interval = tokio::time::interval(Duration::from_secs(5 * 60));
+    loop {
+        interval.tick().await;
+
+        for issue in issues::retry_pending_suggestions(&mut conn, &github).await? {
+            say(&client, format!("Filed a suggestion from earlier: {}", issue.url)).await?;
+        }
+    }
+}
+
+async fn handle_event_journal(
+    _: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        let event = rx.recv().await?;
+        // Otherwise the startup stand-ins would roll in every credits
+        if event.is_stand_in() {
+            continue;
+        }
+
+        if let Err(err) = subd_db::record_event(&mut conn, &event).await {
+            println!("Failed to journal event: {:?} -> {:?}", event, err);
+        }
+    }
+}
+
+fn session_status(session: Option<subd_db::StreamSession>) -> String {
+    match session {
+        Some(session) => format!(
+            "Session going since {}",
+            session.started_at.format("%H:%M UTC")
+        ),
+        None => "No session going".to_string(),
+    }
+}
+
+/// Keep `stream_sessions` in step with going live, so themesongs replay once per stream
+async fn handle_stream_sessions(
+    _: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        match rx.recv().await? {
+            Event::TwitchStreamOnline(started_at) => {
+                subd_db::start_stream_session(&mut conn, started_at).await?;
+            }
+            Event::TwitchStreamOffline => {
+                subd_db::end_stream_session(&mut conn, Utc::now()).await?;
+            }
+            _ => continue,
+        };
+    }
+}
+
+async fn handle_stream_credits(
+    tx: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        match rx.recv().await? {
+            Event::RequestStreamCredits => {}
+            _ => continue,
+        };
+
+        match credits::stream_credits(&mut conn).await {
+            Ok(credits) => {
+                tx.send(Event::StreamCredits(credits))?;
+            }
+            Err(err) => println!("Failed to build stream credits: {:?}", err),
+        }
+    }
+}
+
+async fn handle_sponsor_wall(
+    tx: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let github = GithubClient::from_env();
+
+    let mut sponsors = None;
+    let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+    loop {
+        let refresh = tokio::select! {
+            _ = interval.tick() => true,
+            event = rx.recv() => match event? {
+                Event::GithubSponsorshipEvent(_) => true,
+                // A new overlay connected, it just needs what we already have
+                Event::RequestSponsorWall => sponsors.is_none(),
+                _ => continue,
+            },
+        };
+
+        if refresh {
+            match github.get_sponsors().await {
+                Ok(current) => sponsors = Some(current),
+                Err(err) => println!("Failed to fetch github sponsors: {}", err),
+            }
+        }
+
+        if let Some(sponsors) = &sponsors {
+            tx.send(Event::GithubSponsorWall(sponsors.clone()))?;
+        }
+    }
+}
+
+/// Roll the credits whenever OBS switches to the credits scene
+async fn handle_obs_credits_scene(
+    tx: broadcast::Sender<Event>,
+    _: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let credits_scene = env::var("SUBD_CREDITS_SCENE").unwrap_or_else(|_| "Credits".to_string());
+
+    let obs_client = OBSClient::connect("192.168.4.22", 4444).await?;
+    let events = obs_client.events()?;
+    futures::pin_mut!(events);
+
+    while let Some(event) = events.next().await {
+        if let obws::events::EventType::SwitchScenes { scene_name, .. } = event.ty {
+            if scene_name == credits_scene {
+                tx.send(Event::RequestStreamCredits)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_twitch_role_sync(
+    tx: broadcast::Sender<Event>,
+    _: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    let token = env::var("TWITCH_OAUTH")
+        .expect("$TWITCH_OAUTH must be set")
+        .replace("oauth:", "");
+    let github = GithubClient::from_env();
+
+    let minutes = env::var("SUBD_ROLE_SYNC_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(30);
+    let mut interval = tokio::time::interval(Duration::from_secs(minutes * 60));
+    let mut helix = None;
+
+    loop {
+        interval.tick().await;
+
+        // Helix being down for a bit shouldn't stop the next sync
+        if helix.is_none() {
+            match Helix::from_token(token.clone()).await {
+                Ok(client) => helix = Some(client),
+                Err(err) => {
+                    println!("Failed to connect to helix: {:?}", err);
+                    continue;
+                }
+            }
+        }
+        let helix = helix.as_ref().expect("helix to be connected");
+
+        let channel = match ChannelRoles::fetch(helix).await {
+            Ok(channel) => channel,
+            Err(err) => {
+                println!("Failed to fetch channel roles: {:?}", err);
+                continue;
+            }
+        };
+
+        let broadcaster_id = &helix.broadcaster_id;
+        let mut changes =
+            match users::sync::sync_twitch_roles(&mut conn, broadcaster_id, &channel).await {
+                Ok(changes) => changes,
+                Err(err) => {
+                    println!("Failed to sync twitch roles: {:?}", err);
+                    continue;
+                }
+            };
+        match users::sponsors::sync_github_sponsors(&mut conn, &github).await {
+            Ok(sponsor_changes) => changes.extend(sponsor_changes),
+            Err(err) => println!("Failed to sync github sponsors: {:?}", err),
+        }
+
+        for change in changes {
+            println!(
+                "  Role change: {} gained {:?}, lost {:?}",
+                change.twitch_login,
+                change.gained(),
+                change.lost()
+            );
+            tx.send(Event::UserRolesChanged(change))?;
+        }
+    }
+}
+
 async fn yew_inner_loop(
     stream: TcpStream,
-    _: broadcast::Sender<Event>,
+    tx: broadcast::Sender<Event>,
     mut rx: broadcast::Receiver<Event>,
 ) -> Result<()> {
     stream
@@ -259,13 +702,26 @@ async fn yew_inner_loop(
     //     .expect("Failed to forward messages")
 
     println!("Looping new yew inner loop");
+
+    // The sponsor wall is always on, so fill it in as soon as an overlay connects
+    tx.send(Event::RequestSponsorWall)?;
+    tx.send(Event::RequestThemesongsPending)?;
+
     loop {
         let event = rx.recv().await?;
         match event {
             Event::TwitchChatMessage(_)
             | Event::ThemesongDownload(_)
             | Event::TwitchSubscriptionCount(_)
-            | Event::TwitchSubscription(_) => {
+            | Event::TwitchSubscription(_)
+            | Event::TwitchFollow(_)
+            | Event::TwitchFollowerCount(_)
+            | Event::GithubSponsorshipEvent(_)
+            | Event::GithubSponsorWall(_)
+            | Event::GithubRepoEvent(_)
+            | Event::ThemesongsPending(_)
+            | Event::ThemesongPlay(_)
+            | Event::StreamCredits(_) => {
                 ws_stream
                     .send(tungstenite::Message::Text(serde_json::to_string(&event)?))
                     .await?;
@@ -299,6 +755,17 @@ async fn handle_yew(tx: broadcast::Sender<Event>, _: broadcast::Receiver<Event>)
     Ok(())
 }
 
+async fn handle_webhooks(tx: broadcast::Sender<Event>, _: broadcast::Receiver<Event>) -> Result<()> {
+    // TODO(generalize)
+    let addr = env::var("SUBD_WEBHOOK_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
+
+    axum::Server::bind(&addr.parse()?)
+        .serve(webhooks::router(tx).into_make_service())
+        .await?;
+
+    Ok(())
+}
+
 async fn handle_twitch_sub_count(
     tx: broadcast::Sender<Event>,
     mut rx: broadcast::Receiver<Event>,
@@ -344,9 +811,37 @@ async fn handle_twitch_notifications(
     tx: broadcast::Sender<Event>,
     _: broadcast::Receiver<Event>,
 ) -> Result<()> {
-    // TODO(update_sub)
-    // let mut conn = subd_db::get_handle().await;
+    let token = env::var("TWITCH_OAUTH")
+        .expect("$TWITCH_OAUTH must be set")
+        .replace("oauth:", "");
+
+    // PubSub is deprecated, but keep it around in case EventSub is having a bad day
+    match env::var("SUBD_TWITCH_TRANSPORT").as_deref() {
+        Ok("pubsub") => handle_twitch_pubsub(tx, token).await,
+        _ => handle_twitch_eventsub(tx, token).await,
+    }
+}
+
+async fn handle_twitch_eventsub(tx: broadcast::Sender<Event>, token: String) -> Result<()> {
+    let config = EventSubConfig::from_token(token).await?;
+
+    subd_twitch::eventsub::run(config, |event| {
+        let is_subscription = matches!(event, Event::TwitchSubscription(_));
+        tx.send(event)?;
+        if is_subscription {
+            tx.send(Event::RequestTwitchSubCount)?;
+        }
+
+        Ok(())
+    })
+    .await?;
+
+    println!("Oh no, exiting");
+
+    Ok(())
+}
 
+async fn handle_twitch_pubsub(tx: broadcast::Sender<Event>, token: String) -> Result<()> {
     // Listen to subscriptions as well
     let subscriptions = pubsub::channel_subscriptions::ChannelSubscribeEventsV1 {
         channel_id: 114257969,
@@ -358,247 +853,456 @@ async fn handle_twitch_notifications(
     }
     .into_topic();
 
-    // Create the topic command to send to twitch
-    let command = pubsub::listen_command(
-        // &[/* chat_mod_actions,  */ subsriptions],
-        &[redeems, subscriptions],
-        Some(
-            env::var("TWITCH_OAUTH")
-                .expect("$TWITCH_OAUTH must be set")
-                .replace("oauth:", "")
-                .as_str(),
-        ),
-        "",
-    )
-    .expect("serializing failed");
+    let config = PubSubConfig::new(token, vec![redeems, subscriptions]);
 
-    // Send the message with your favorite websocket client
+    subd_twitch::pubsub::run(config, |data| {
+        match data {
+            pubsub::TopicData::ChannelPointsChannelV1 { topic, reply: _ } => {
+                println!("POINTS: {:?}", topic);
+            }
+            pubsub::TopicData::ChannelSubscribeEventsV1 { topic, reply } => {
+                println!("SUBSCRIBE: {:?}", topic);
+                tx.send(Event::TwitchSubscription((*reply).into()))?;
+                tx.send(Event::RequestTwitchSubCount)?;
+            }
+            // pubsub::TopicData::ChatModeratorActions { topic, reply } => todo!(),
+            // pubsub::TopicData::ChannelBitsEventsV2 { topic, reply } => todo!(),
+            // pubsub::TopicData::ChannelBitsBadgeUnlocks { topic, reply } => todo!(),
+            // pubsub::TopicData::AutoModQueue { topic, reply } => todo!(),
+            // pubsub::TopicData::UserModerationNotifications { topic, reply } => todo!(),
+            _ => {}
+        }
 
-    println!("trying to connect to stream...");
-    // let stream = TcpStream::connect("pubsub-edge.twitch.tv:443")
-    //     .await
-    //     .unwrap();
-    println!("part 1");
-    let (mut ws_stream, _resp) = tokio_tungstenite::connect_async("wss://pubsub-edge.twitch.tv")
-        .await
-        .expect("asdfasdfasdf");
+        Ok(())
+    })
+    .await?;
 
-    ws_stream.send(tungstenite::Message::Text(command)).await?;
-    ws_stream
-        .send(tungstenite::Message::Text(
-            r#"{"type": "PING"}"#.to_string(),
-        ))
-        .await?;
+    println!("Oh no, exiting");
 
-    while let Some(msg) = ws_stream.next().await {
-        match msg {
-            Ok(msg) => {
-                match msg {
-                    tungstenite::Message::Text(msg) => {
-                        let parsed = pubsub::Response::parse(msg.as_str())?;
-                        match parsed {
-                            pubsub::Response::Response(resp) => {
-                                println!(
-                                    "[handle_twitch_notifications] got new response: {:?}",
-                                    resp
-                                );
-                            }
-                            pubsub::Response::Message { data } => {
-                                // println!("[handle_twitch_notifications] new msg data: {:?}", data);
-                                match data {
-                                    pubsub::TopicData::ChannelPointsChannelV1 {
-                                        topic,
-                                        reply: _,
-                                    } => {
-                                        println!("POINTS: {:?}", topic);
-                                        // tx.send(Event::RequestTwitchSubCount)?;
-                                    }
-                                    pubsub::TopicData::ChannelSubscribeEventsV1 {
-                                        topic,
-                                        reply,
-                                    } => {
-                                        println!("SUBSCRIBE: {:?}", topic);
-                                        tx.send(Event::TwitchSubscription((*reply).into()))?;
-                                        tx.send(Event::RequestTwitchSubCount)?;
-                                    }
-                                    // pubsub::TopicData::ChatModeratorActions { topic, reply } => todo!(),
-                                    // pubsub::TopicData::ChannelBitsEventsV2 { topic, reply } => todo!(),
-                                    // pubsub::TopicData::ChannelBitsBadgeUnlocks { topic, reply } => todo!(),
-                                    // pubsub::TopicData::AutoModQueue { topic, reply } => todo!(),
-                                    // pubsub::TopicData::UserModerationNotifications { topic, reply } => todo!(),
-                                    _ => continue,
-                                }
-                            }
-                            pubsub::Response::Pong => continue,
-                            pubsub::Response::Reconnect => todo!(),
-                        }
-                    }
-                    _ => {}
+    Ok(())
+}
+
+async fn handle_themesong_review(
+    conn: &mut sqlx::SqliteConnection,
+    tx: &broadcast::Sender<Event>,
+    client: &TwitchIRCClient<SecureTCPTransport, StaticLoginCredentials>,
+    reviewer: &subd_types::UserID,
+    args: &[String],
+    can_preview_off_stream: bool,
+) -> Result<()> {
+    match args[0].as_str() {
+        "pending" => {
+            let pending = themesong::review::get_pending_themesongs(conn).await?;
+            say(client, themesong::review::pending_summary(&pending)).await?;
+        }
+        "review" => {
+            let target = args.get(1).map(|t| t.replace("@", "").to_lowercase());
+            let pending = themesong::review::get_pending_themesongs(conn)
+                .await?
+                .into_iter()
+                .find(|song| Some(&song.login) == target.as_ref());
+
+            match pending {
+                Some(song) if !can_preview_off_stream => {
+                    say(
+                        client,
+                        format!(
+                            "No $SUBD_PREVIEW_AUDIO_DEVICE to preview on, {}'s themesong is from: {}",
+                            song.login,
+                            song.source_link().unwrap_or_else(|| "(no link saved)".to_string())
+                        ),
+                    )
+                    .await?;
                 }
+                Some(song) => {
+                    tx.send(Event::ThemesongPreview(ThemesongPreview {
+                        user_id: song.user_id,
+                        display_name: song.login.clone(),
+                        private: true,
+                    }))?;
+                    say(
+                        client,
+                        format!(
+                            "Previewing {}'s themesong off stream: {}",
+                            song.login,
+                            song.source_link().unwrap_or_else(|| "(no link saved)".to_string())
+                        ),
+                    )
+                    .await?;
+                }
+                None => say(client, "!themesong review @user, see !themesong pending").await?,
             }
-            Err(err) => {
-                println!("Error in twitch notifications: {:?}", err);
-            }
-        }
-        println!("received new msg");
-        tokio::time::sleep(Duration::from_secs(5)).await;
-        println!("... waiting complete");
-        tx.send(Event::RequestTwitchSubCount)?;
-    }
-
-    // let ws = TcpListener::bind(TWITCH_PUBSUB_URL.as_str()).await?;
-    // let (mut stream, resp) = tungstenite::connect("wss://pubsub-edge.twitch.tv".to_string())?;
-    // println!("  Response: {:?}", resp);
-    //
-    // while let Ok(msg) = stream.read_message() {
-    //     match msg {
-    //         tungstenite::Message::Text(msg) => {
-    //             let parsed = pubsub::Response::parse(msg.as_str())?;
-    //             match parsed {
-    //                 pubsub::Response::Response(resp) => {
-    //                     println!("[handle_twitch_notifications] got new response: {:?}", resp);
-    //                 }
-    //                 pubsub::Response::Message { data } => {
-    //                     // println!("[handle_twitch_notifications] new msg data: {:?}", data);
-    //                     match data {
-    //                         pubsub::TopicData::ChannelPointsChannelV1 { topic, reply } => {
-    //                             println!("POINTS: {:?}", topic);
-    //                             tx.send(Event::RequestTwitchSubCount)?;
-    //                         }
-    //                         pubsub::TopicData::ChannelSubscribeEventsV1 { topic, reply } => {
-    //                             println!("SUBSCRIBE: {:?}", topic);
-    //                             tx.send(Event::RequestTwitchSubCount)?;
-    //                         }
-    //                         // pubsub::TopicData::ChatModeratorActions { topic, reply } => todo!(),
-    //                         // pubsub::TopicData::ChannelBitsEventsV2 { topic, reply } => todo!(),
-    //                         // pubsub::TopicData::ChannelBitsBadgeUnlocks { topic, reply } => todo!(),
-    //                         // pubsub::TopicData::AutoModQueue { topic, reply } => todo!(),
-    //                         // pubsub::TopicData::UserModerationNotifications { topic, reply } => todo!(),
-    //                         _ => continue,
-    //                     }
-    //                 }
-    //                 pubsub::Response::Pong => continue,
-    //                 pubsub::Response::Reconnect => todo!(),
-    //             }
-    //         }
-    //         _ => {
-    //             println!("unexpected new msg: {:?}", msg);
-    //         }
-    //     }
-    // }
-    //
-    println!("Oh no, exiting");
+        }
+        _ => {
+            say(client, themesong::review::review_command(conn, reviewer, args).await?).await?;
+            tx.send(Event::RequestThemesongsPending)?;
+        }
+    }
 
     Ok(())
 }
 
+/// Keep the overlay's review indicator up to date
+async fn handle_themesongs_pending(
+    tx: broadcast::Sender<Event>,
+    mut rx: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+
+    loop {
+        match rx.recv().await? {
+            Event::RequestThemesongsPending
+            | Event::ThemesongDownload(ThemesongDownload::Finish { success: true, .. }) => {}
+            _ => continue,
+        };
+
+        let pending = themesong::review::get_pending_themesongs(&mut conn)
+            .await?
+            .into_iter()
+            .map(|song| song.login)
+            .collect();
+        tx.send(Event::ThemesongsPending(pending))?;
+    }
+}
+
 async fn handle_themesong_download(
     tx: broadcast::Sender<Event>,
     mut rx: broadcast::Receiver<Event>,
 ) -> Result<()> {
     let mut conn = subd_db::get_handle().await;
+    let sources = themesong::source::Sources::new();
+    let jobs_config = themesong::jobs::JobConfig::from_env();
 
     let config = get_chat_config();
     let (_, client) = TwitchIRCClient::<SecureTCPTransport, StaticLoginCredentials>::new(config);
 
+    // Whatever was downloading when we went down starts over
+    let requeued = subd_db::requeue_running_themesong_jobs(&mut conn).await?;
+    if requeued > 0 {
+        println!("themesong: picking {} downloads back up", requeued);
+    }
+
+    let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut running: HashMap<i64, tokio::task::JoinHandle<()>> = HashMap::new();
+    // Retries become due without anything else happening
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
     loop {
-        let event = rx.recv().await?;
-        let msg = match event {
-            Event::ThemesongDownload(ThemesongDownload::Request { msg }) => msg,
-            _ => continue,
+        tokio::select! {
+            event = rx.recv() => {
+                let msg = match event? {
+                    Event::ThemesongDownload(ThemesongDownload::Request { msg }) => msg,
+                    _ => continue,
+                };
+
+                for id in queue_themesong_download(&mut conn, &tx, &client, msg).await? {
+                    if let Some(job) = running.remove(&id) {
+                        job.abort();
+                    }
+                }
+            }
+            Some(id) = done_rx.recv() => {
+                running.remove(&id);
+            }
+            _ = interval.tick() => {}
         };
 
-        let user_id = subd_db::get_user_from_twitch_user(&mut conn, &msg.sender.id).await?;
-        let user_roles = subd_db::get_user_roles(&mut conn, &user_id).await?;
+        while running.len() < jobs_config.concurrency {
+            let job = match subd_db::claim_next_themesong_job(&mut conn, Utc::now()).await? {
+                Some(job) => job,
+                None => break,
+            };
 
-        let splitmsg = msg
-            .message_text
-            .split(" ")
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
+            let id = job.id;
+            let download = run_themesong_download(
+                tx.clone(),
+                client.clone(),
+                sources.clone(),
+                jobs_config.clone(),
+                job,
+            );
+            let done_tx = done_tx.clone();
+            running.insert(
+                id,
+                tokio::spawn(async move {
+                    if let Err(err) = download.await {
+                        println!("themesong: download {} failed: {:?}", id, err);
+                    }
+                    let _ = done_tx.send(id);
+                }),
+            );
+        }
+    }
+}
 
-        if splitmsg.len() == 1 {
-            say(&client, "format: !themesong <url> 00:00.00 00:00.00").await?;
-            tx.send(Event::ThemesongDownload(ThemesongDownload::Format {
-                sender: msg.sender.name.clone(),
-            }))?;
-            continue;
-        } else if splitmsg.len() != 4 {
-            say(
-                &client,
-                "Incorrect themesong format. Required: !themesong <url> 00:00 00:00",
-            )
-            .await?;
+/// Check a `!themesong` and queue its download. Returns the jobs it replaced.
+async fn queue_themesong_download<
+    T: twitch_irc::transport::Transport,
+    L: twitch_irc::login::LoginCredentials,
+>(
+    conn: &mut sqlx::SqliteConnection,
+    tx: &broadcast::Sender<Event>,
+    client: &TwitchIRCClient<T, L>,
+    msg: twitch_irc::message::PrivmsgMessage,
+) -> Result<Vec<i64>> {
+    let requested_by = subd_db::get_user_from_twitch_user(conn, &msg.sender.id).await?;
+
+    let mut splitmsg = msg
+        .message_text
+        .split(" ")
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+
+    // `!themesong set @user <url> <start> <end>` lets mods fix someone else's clip
+    let is_mod_set = splitmsg.get(1).map(String::as_str) == Some("set");
+    let (user_id, display_name) = if is_mod_set {
+        if !is_mod_or_broadcaster(&msg) {
+            return Ok(vec![]);
+        }
+
+        let target = splitmsg
+            .get(2)
+            .map(|t| t.replace("@", ""))
+            .unwrap_or_default();
+        match subd_db::get_user_from_twitch_user_name(conn, &target).await? {
+            Some(user_id) => {
+                splitmsg.drain(1..3);
+                (user_id, target)
+            }
+            None => {
+                say(client, "Format: !themesong set @user <url> <start> <end>").await?;
+                return Ok(vec![]);
+            }
+        }
+    } else {
+        (requested_by, msg.sender.name.clone())
+    };
+    let user_roles = subd_db::get_user_roles(conn, &user_id).await?;
+
+    if splitmsg.len() == 1 {
+        say(client, format!("format: {}", CLIP_HELP)).await?;
+        tx.send(Event::ThemesongDownload(ThemesongDownload::Format {
+            sender: msg.sender.name.clone(),
+        }))?;
+        return Ok(vec![]);
+    }
+
+    if !themesong::can_user_access_themesong(&user_roles) {
+        say(client, "You must be a GH Sponsor or sub/mod/VIP to do this").await?;
+        return Ok(vec![]);
+    }
+
+    // Anything we can tell without the network is said right away instead of after the queue
+    let url = splitmsg[1].as_str();
+    let args = splitmsg[2..].iter().map(String::as_str).collect::<Vec<_>>();
+    let perks = perks::perks_for(&user_roles);
+    let max_seconds = perks.themesong_max_seconds;
+    let clip = match ClipRange::parse(url, &args).and_then(|clip| {
+        themesong::validate_themesong(url)?;
+        clip.check_length(max_seconds)?;
+        Ok(clip)
+    }) {
+        Ok(clip) => clip,
+        Err(err) => {
+            say(client, format!("@{}: {}", msg.sender.name, err)).await?;
             tx.send(Event::ThemesongDownload(ThemesongDownload::Finish {
-                display_name: msg.sender.name.clone(),
+                display_name,
                 success: false,
+                error: Some(err.to_string()),
             }))?;
-            continue;
+            return Ok(vec![]);
         }
+    };
+
+    let queued = themesong::jobs::enqueue(
+        conn,
+        &subd_db::NewThemesongJob {
+            user_id,
+            display_name: display_name.clone(),
+            requested_by,
+            requester_name: msg.sender.name.clone(),
+            url: url.to_string(),
+            start_seconds: clip.start,
+            end_seconds: clip.end,
+            max_seconds,
+            approve: is_mod_set,
+            priority: perks.queue_priority.into(),
+        },
+    )
+    .await?;
 
-        if themesong::can_user_access_themesong(&user_roles) {
-            // Notify that we are starting a download
-            tx.send(Event::ThemesongDownload(ThemesongDownload::Start {
-                display_name: msg.sender.name.clone(),
-            }))?;
-
-            match themesong::download_themesong(
-                &mut conn,
-                &user_id,
-                splitmsg[1].as_str(),
-                splitmsg[2].as_str(),
-                splitmsg[3].as_str(),
-            )
-            .await
-            {
-                Ok(_) => {
-                    println!("Successfully downloaded themesong");
-                    tx.send(Event::ThemesongDownload(ThemesongDownload::Finish {
-                        display_name: msg.sender.name.clone(),
-                        success: true,
-                    }))?;
+    if !queued.cancelled.is_empty() {
+        println!(
+            "themesong: {} asked again, cancelled {:?}",
+            display_name, queued.cancelled
+        );
+    }
+    tx.send(Event::ThemesongDownload(ThemesongDownload::Queued {
+        display_name,
+        position: queued.position,
+    }))?;
 
-                    continue;
-                }
-                Err(err) => {
-                    say(&client, format!("Failed to download: {:?}", err)).await?;
-                    tx.send(Event::ThemesongDownload(ThemesongDownload::Finish {
-                        display_name: msg.sender.name.clone(),
-                        success: false,
-                    }))?;
+    Ok(queued.cancelled)
+}
 
-                    continue;
-                }
+async fn run_themesong_download(
+    tx: broadcast::Sender<Event>,
+    client: TwitchIRCClient<SecureTCPTransport, StaticLoginCredentials>,
+    sources: themesong::source::Sources,
+    config: themesong::jobs::JobConfig,
+    job: subd_db::ThemesongJob,
+) -> Result<()> {
+    let mut conn = subd_db::get_handle().await;
+    let display_name = job.display_name.clone();
+
+    tx.send(Event::ThemesongDownload(ThemesongDownload::Start {
+        display_name: display_name.clone(),
+    }))?;
+
+    let (progress_tx, progress_name) = (tx.clone(), display_name.clone());
+    let progress = move |stage| {
+        let _ = progress_tx.send(Event::ThemesongDownload(ThemesongDownload::Progress {
+            display_name: progress_name.clone(),
+            stage,
+        }));
+    };
+
+    let result = themesong::jobs::run_job(&mut conn, &sources, &config, &job, &progress).await;
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            // Otherwise it stays running until the bot restarts
+            let error = "Failed to download";
+            subd_db::finish_themesong_job(&mut conn, job.id, ThemesongJobStatus::Failed, Some(error))
+                .await?;
+            tx.send(Event::ThemesongDownload(ThemesongDownload::Finish {
+                display_name,
+                success: false,
+                error: Some(error.to_string()),
+            }))?;
+            return Err(err);
+        }
+    };
+
+    match outcome {
+        JobOutcome::Done => {
+            println!("Successfully downloaded themesong");
+            let reply = if job.approve {
+                format!(
+                    "@{}: {} updated your themesong",
+                    display_name, job.requester_name
+                )
+            } else {
+                format!(
+                    "@{}: got it! A mod will review your themesong before it plays",
+                    display_name
+                )
             };
-        } else {
-            say(
-                &client,
-                "You must be a GH Sponsor or sub/mod/VIP to do this",
-            )
-            .await?;
+            say(&client, reply).await?;
+            tx.send(Event::ThemesongDownload(ThemesongDownload::Finish {
+                display_name,
+                success: true,
+                error: None,
+            }))?;
+        }
+        JobOutcome::Retrying { attempts, error } => {
+            println!(
+                "themesong: {}'s download failed ({}), attempt {} of {}",
+                display_name, error, attempts, config.max_attempts
+            );
+            let position = subd_db::queued_themesong_jobs_ahead(&mut conn, job.id).await?;
+            tx.send(Event::ThemesongDownload(ThemesongDownload::Queued {
+                display_name,
+                position: position as usize,
+            }))?;
+        }
+        JobOutcome::Failed(error) => {
+            say(&client, format!("@{}: {}", display_name, error)).await?;
+            tx.send(Event::ThemesongDownload(ThemesongDownload::Finish {
+                display_name,
+                success: false,
+                error: Some(error),
+            }))?;
         }
     }
+
+    Ok(())
 }
 
 async fn handle_themesong_play(
-    _: broadcast::Sender<Event>,
+    tx: broadcast::Sender<Event>,
     mut rx: broadcast::Receiver<Event>,
     sink: &rodio::Sink,
+    preview_sink: Option<&rodio::Sink>,
 ) -> Result<()> {
     let mut conn = subd_db::get_handle().await;
 
+    let config = themesong::player::PlayerConfig::from_env();
+    let mut player = themesong::player::ThemesongPlayer::new(&config);
+    sink.set_volume(config.volume);
+
+    let mut interval = tokio::time::interval(Duration::from_millis(250));
+
     loop {
-        let event = rx.recv().await?;
-        let user_id = match event {
-            Event::ThemesongPlay(ThemesongPlay { user_id, .. }) => user_id,
-            _ => continue,
+        let event = tokio::select! {
+            event = rx.recv() => event?,
+            _ = interval.tick() => {
+                for event in player.tick(&mut conn, sink, std::time::Instant::now()).await? {
+                    tx.send(Event::ThemesongPlay(event))?;
+                }
+                continue;
+            }
         };
 
-        println!("=> Playing themesong");
-        themesong::play_themesong_for_today(&mut conn, &user_id, &sink).await?;
+        match event {
+            Event::ThemesongPlay(ThemesongPlay::Request {
+                user_id,
+                display_name,
+            }) => {
+                let priority = themesong_priority(&mut conn, &user_id).await?;
+                let queued = player.request(user_id, display_name, false, priority);
+                println!("=> Queueing themesong: {:?}", queued);
+            }
+            Event::ThemesongPreview(ThemesongPreview {
+                user_id,
+                display_name,
+                private: false,
+            }) => {
+                let priority = themesong_priority(&mut conn, &user_id).await?;
+                let queued = player.request(user_id, display_name, true, priority);
+                println!("=> Queueing preview: {:?}", queued);
+            }
+            Event::ThemesongControl(control) => player.control(sink, &control),
+            Event::ThemesongPreview(ThemesongPreview {
+                user_id,
+                private: true,
+                ..
+            }) => match preview_sink {
+                Some(preview_sink) => {
+                    println!("=> Previewing themesong off stream");
+                    themesong::preview_themesong(&mut conn, &user_id, preview_sink).await?;
+                }
+                None => println!("=> No $SUBD_PREVIEW_AUDIO_DEVICE, can't preview off stream"),
+            },
+            _ => continue,
+        };
     }
 }
 
+async fn themesong_priority(
+    conn: &mut sqlx::SqliteConnection,
+    user_id: &subd_types::UserID,
+) -> Result<u8> {
+    let user_roles = subd_db::get_user_roles(conn, user_id).await?;
+    Ok(perks::perks_for(&user_roles).queue_priority)
+}
+
+fn is_mod_or_broadcaster(msg: &twitch_irc::message::PrivmsgMessage) -> bool {
+    msg.badges
+        .iter()
+        .any(|badge| badge.name == "broadcaster" || badge.name == "moderator")
+}
+
 async fn say<T: twitch_irc::transport::Transport, L: twitch_irc::login::LoginCredentials>(
     client: &TwitchIRCClient<T, L>,
     msg: impl Into<String>,
@@ -633,21 +1337,51 @@ async fn main() -> Result<()> {
     let (_stream, handle) = rodio::OutputStream::try_default().unwrap();
     let sink = rodio::Sink::try_new(&handle).unwrap();
 
+    // Mods preview pending themesongs here, e.g. headphones that OBS doesn't capture.
+    // The stream has to stay alive (and on this thread) for as long as the sink is used.
+    let (_preview_stream, preview_sink) = match env::var("SUBD_PREVIEW_AUDIO_DEVICE")
+        .ok()
+        .and_then(|name| themesong::open_output_device(&name))
+    {
+        Some((stream, sink)) => (Some(stream), Some(sink)),
+        None => (None, None),
+    };
+
+    let can_preview_off_stream = preview_sink.is_some();
+
     makechan!(handle_twitch_chat);
-    makechan!(handle_twitch_msg);
+    makechan!(|tx, rx| {
+        handle_twitch_msg(tx, rx, can_preview_off_stream)
+            .await
+            .expect("this should work")
+    });
     makechan!(handle_yew);
     makechan!(handle_twitch_sub_count);
     makechan!(handle_twitch_notifications);
+    makechan!(handle_twitch_follows);
+    makechan!(handle_twitch_subscriptions);
+    makechan!(handle_twitch_role_sync);
+    makechan!(handle_github_sponsorships);
+    makechan!(handle_github_repo_activity);
+    makechan!(handle_pending_suggestions);
+    makechan!(handle_webhooks);
+    makechan!(handle_event_journal);
+    makechan!(handle_stream_sessions);
+    makechan!(handle_stream_credits);
+    makechan!(handle_sponsor_wall);
 
     // Themesong functions
     makechan!(handle_themesong_download);
+    makechan!(handle_themesongs_pending);
     makechan!(|tx, rx| {
-        handle_themesong_play(tx, rx, &sink)
+        handle_themesong_play(tx, rx, &sink, preview_sink.as_ref())
             .await
             .expect("Handles playing themesongs")
     });
 
     if CONNECT_OBS {
+        makechan!(handle_obs_credits_scene);
+
         // Connect to the OBS instance through obs-websocket.
         let obs_client = OBSClient::connect("192.168.4.22", 4444).await?;
 
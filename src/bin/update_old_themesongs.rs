@@ -1,24 +1,23 @@
 use anyhow::Result;
+use server::themesong::backfill;
 
-// -- new columsn: url, start, end
-// --
-// -- THIS ISN"T RIGHT
-// SELECT DISTINCT TWITCH_CHAT_HISTORY.user_id, msg, timestamp
-//   FROM TWITCH_CHAT_HISTORY
-//     INNER JOIN USER_THEME_SONGS on USER_THEME_SONGS.user_id = TWITCH_CHAT_HISTORY.user_id
-//   WHERE msg LIKE '!themesong https%'
-//     GROUP BY TWITCH_CHAT_HISTORY.user_id
-//     ORDER BY timestamp DESC, TWITCH_CHAT_HISTORY.user_id DESC;
-// 
-// -- select * from USER_THEME_SONGS;
-// 
-// -- SELECT COUNT(*) FROM TWITCH_CHAT_HISTORY WHERE user_id = 138;
+/// Backfill url, clip range and request time for themesongs downloaded before
+/// we saved them, using the `!themesong` messages in TWITCH_CHAT_HISTORY.
+///
+/// Safe to run more than once, only songs without a url are touched.
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Pre: Update all the themesong code to save the url, start and end times
-    //
-    // Get all the themesong requests
-    // Insert info into DB
-    // update db to have these be NOT NULL because NOT NULL is good for living
+    let mut conn = subd_db::get_handle().await;
+
+    let summary = backfill::backfill_themesong_metadata(&mut conn).await?;
+    println!("Updated {} themesongs", summary.updated);
+
+    if !summary.missing.is_empty() {
+        println!(
+            "No usable !themesong in chat history for users: {:?}",
+            summary.missing
+        );
+    }
+
     Ok(())
 }
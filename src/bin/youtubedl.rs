@@ -1,3 +1,5 @@
+use server::themesong::clip::ClipRange;
+use server::themesong::source::Sources;
 use server::themesong::{download_themesong, play_themesong};
 use subd_db::get_handle;
 
@@ -9,7 +11,16 @@ async fn main() -> anyhow::Result<()> {
     let url = "https://www.youtube.com/watch?v=jOpzP33_USs";
 
     if true {
-        download_themesong(&mut db, &user_id, url, "00:01:03", "00:01:10").await?;
+        download_themesong(
+            &mut db,
+            &Sources::new(),
+            &user_id,
+            &user_id,
+            url,
+            &ClipRange::parse(url, &["00:01:03", "00:01:10"])?,
+            10.,
+        )
+        .await?;
     }
 
     let (_stream, handle) = rodio::OutputStream::try_default().unwrap();
@@ -0,0 +1,196 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::SqliteConnection;
+use subd_types::{CreditsEntry, Event, GithubSponsorshipAction, StreamCredits, SubscriptionKind};
+
+/// How far back the credits go when we never saw the stream go live
+const FALLBACK_HOURS: i64 = 12;
+const TOP_CHATTERS: i64 = 5;
+
+/// Add `amount` to `name`'s entry, keeping the order people first showed up in
+fn add_to(entries: &mut Vec<CreditsEntry>, name: String, amount: i64) {
+    match entries.iter_mut().find(|entry| entry.name == name) {
+        Some(entry) => entry.amount = Some(entry.amount.unwrap_or(0) + amount),
+        None => entries.push(CreditsEntry::with_amount(name, amount)),
+    }
+}
+
+fn add_name(entries: &mut Vec<CreditsEntry>, name: String) {
+    if !entries.iter().any(|entry| entry.name == name) {
+        entries.push(CreditsEntry::name(name));
+    }
+}
+
+/// Fill in the sections that only the event journal knows about
+pub fn add_journal_events(credits: &mut StreamCredits, events: &[Event]) {
+    for event in events {
+        match event {
+            Event::TwitchSubscription(sub) => match sub.kind {
+                SubscriptionKind::Sub | SubscriptionKind::Resub => {
+                    add_name(&mut credits.new_subs, sub.display_name())
+                }
+                // Gifts come from twitch_gifted_subscriptions, which knows who gave them
+                SubscriptionKind::Gift | SubscriptionKind::MysteryGift { .. } => {}
+            },
+            Event::TwitchCheer(cheer) => add_to(
+                &mut credits.cheerers,
+                cheer
+                    .user_name
+                    .clone()
+                    .unwrap_or_else(|| "Anonymous".to_string()),
+                cheer.bits,
+            ),
+            Event::TwitchRaid(raid) => add_to(
+                &mut credits.raiders,
+                raid.from_broadcaster_name.clone(),
+                raid.viewers,
+            ),
+            Event::GithubSponsorshipEvent(sponsorship)
+                if sponsorship.action == GithubSponsorshipAction::Created =>
+            {
+                add_name(&mut credits.new_sponsors, sponsorship.display_name())
+            }
+            _ => {}
+        }
+    }
+
+    credits
+        .cheerers
+        .sort_by_key(|entry| std::cmp::Reverse(entry.amount));
+}
+
+/// Credits for the current stream, or the last `FALLBACK_HOURS` if we don't know when it started
+pub async fn stream_credits(conn: &mut SqliteConnection) -> Result<StreamCredits> {
+    let since = subd_db::get_last_stream_start(conn)
+        .await?
+        .unwrap_or_else(|| Utc::now() - Duration::hours(FALLBACK_HOURS));
+
+    credits_since(conn, since).await
+}
+
+pub async fn credits_since(
+    conn: &mut SqliteConnection,
+    since: DateTime<Utc>,
+) -> Result<StreamCredits> {
+    let mut credits = StreamCredits {
+        since: Some(since),
+        gifters: subd_db::get_gifters_since(conn, since)
+            .await?
+            .into_iter()
+            .map(|gifter| CreditsEntry::with_amount(gifter.display_name, gifter.gifted))
+            .collect(),
+        themesongs: subd_db::get_themesong_players_since(conn, since)
+            .await?
+            .into_iter()
+            .map(CreditsEntry::name)
+            .collect(),
+        top_chatters: subd_db::get_top_chatters_since(conn, since, TOP_CHATTERS)
+            .await?
+            .into_iter()
+            .map(|(name, messages)| CreditsEntry::with_amount(name, messages))
+            .collect(),
+        ..Default::default()
+    };
+
+    let events = subd_db::get_journal_since(conn, since)
+        .await?
+        .into_iter()
+        .map(|entry| entry.event)
+        .collect::<Vec<_>>();
+    add_journal_events(&mut credits, &events);
+
+    Ok(credits)
+}
+
+#[cfg(test)]
+mod test {
+    use subd_types::{
+        get_nyx_sub, GithubSponsorship, GithubSponsorshipEvent, TwitchCheerEvent, TwitchRaidEvent,
+    };
+
+    use super::*;
+    use crate::test_utils::test_conn;
+
+    fn cheer(user_name: Option<&str>, bits: i64) -> Event {
+        Event::TwitchCheer(TwitchCheerEvent {
+            user_name: user_name.map(|name| name.to_string()),
+            bits,
+            message: "".to_string(),
+        })
+    }
+
+    fn sponsorship(action: GithubSponsorshipAction) -> Event {
+        Event::GithubSponsorshipEvent(GithubSponsorshipEvent {
+            action,
+            sponsor_login: "jesseleite".to_string(),
+            tier: GithubSponsorship {
+                tier_name: "$5 a month".to_string(),
+                monthly_dollars: 5,
+            },
+            previous_tier: None,
+            is_one_time: false,
+            is_private: false,
+            effective_date: None,
+        })
+    }
+
+    #[test]
+    fn totals_journal_events() {
+        let mut credits = StreamCredits::default();
+        add_journal_events(
+            &mut credits,
+            &[
+                cheer(Some("nyxkrage"), 100),
+                cheer(None, 50),
+                cheer(Some("theprimeagen"), 500),
+                cheer(Some("nyxkrage"), 100),
+                Event::TwitchSubscription(get_nyx_sub()),
+                Event::TwitchSubscription(get_nyx_sub()),
+                Event::TwitchRaid(TwitchRaidEvent {
+                    from_broadcaster_id: "1".to_string(),
+                    from_broadcaster_login: "theprimeagen".to_string(),
+                    from_broadcaster_name: "ThePrimeagen".to_string(),
+                    viewers: 420,
+                }),
+                sponsorship(GithubSponsorshipAction::Created),
+                sponsorship(GithubSponsorshipAction::TierChanged),
+            ],
+        );
+
+        assert_eq!(
+            credits.cheerers,
+            vec![
+                CreditsEntry::with_amount("theprimeagen", 500),
+                CreditsEntry::with_amount("nyxkrage", 200),
+                CreditsEntry::with_amount("Anonymous", 50),
+            ]
+        );
+        assert_eq!(credits.new_subs, vec![CreditsEntry::name("NyxKrage")]);
+        assert_eq!(credits.raiders[0].amount, Some(420));
+        assert_eq!(credits.new_sponsors, vec![CreditsEntry::name("jesseleite")]);
+        assert_eq!(credits.sections().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn only_counts_the_current_stream() -> Result<()> {
+        let mut conn = test_conn().await?;
+
+        subd_db::record_event(&mut conn, &cheer(Some("early_bird"), 10)).await?;
+        sqlx::query!("UPDATE event_journal SET recorded_at = datetime('now', '-2 hours')")
+            .execute(&mut conn)
+            .await?;
+
+        let started_at = Utc::now() - Duration::hours(1);
+        subd_db::record_event(&mut conn, &Event::TwitchStreamOnline(started_at)).await?;
+        subd_db::record_event(&mut conn, &cheer(Some("nyxkrage"), 100)).await?;
+
+        let credits = stream_credits(&mut conn).await?;
+        assert_eq!(credits.since, Some(started_at));
+        assert_eq!(
+            credits.cheerers,
+            vec![CreditsEntry::with_amount("nyxkrage", 100)]
+        );
+
+        Ok(())
+    }
+}
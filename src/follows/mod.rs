@@ -0,0 +1,79 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+use subd_types::TwitchFollowEvent;
+
+/// Save the follow and return the new follower count
+pub async fn record_follow(
+    conn: &mut SqliteConnection,
+    follow: &TwitchFollowEvent,
+) -> Result<usize> {
+    subd_db::save_twitch_follow(conn, follow).await?;
+    Ok(subd_db::get_twitch_follower_count(conn).await? as usize)
+}
+
+pub async fn followage(conn: &mut SqliteConnection, login: &str) -> Result<String> {
+    let login = login.replace("@", "");
+    Ok(match subd_db::get_twitch_followed_at(conn, &login).await? {
+        Some(followed_at) => format_followage(&login, followed_at, Utc::now()),
+        None => format!(
+            "{} isn't following (or followed before I was paying attention)",
+            login
+        ),
+    })
+}
+
+pub fn format_followage(name: &str, followed_at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let days = (now - followed_at).num_days().max(0);
+    let (years, days) = (days / 365, days % 365);
+    let (months, days) = (days / 30, days % 30);
+
+    let mut pieces = vec![];
+    for (amount, unit) in [(years, "year"), (months, "month"), (days, "day")] {
+        if amount > 0 {
+            let plural = if amount == 1 { "" } else { "s" };
+            pieces.push(format!("{} {}{}", amount, unit, plural));
+        }
+    }
+
+    if pieces.is_empty() {
+        format!("{} followed today! Welcome :)", name)
+    } else {
+        format!(
+            "{} has been following for {} (since {})",
+            name,
+            pieces.join(", "),
+            followed_at.format("%Y-%m-%d")
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn formats_followed_today() {
+        let now = at("2022-07-04T18:00:00Z");
+        assert_eq!(
+            format_followage("nyxkrage", now - Duration::hours(3), now),
+            "nyxkrage followed today! Welcome :)"
+        );
+    }
+
+    #[test]
+    fn formats_long_followage() {
+        let now = at("2022-07-04T18:00:00Z");
+        let followed_at = now - Duration::days(365 + 2 * 30 + 1);
+        assert_eq!(
+            format_followage("nyxkrage", followed_at, now),
+            "nyxkrage has been following for 1 year, 2 months, 1 day (since 2021-05-04)"
+        );
+    }
+}
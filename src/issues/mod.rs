@@ -0,0 +1,222 @@
+//! `!issue <n>` and `!suggest <text>`
+
+use std::env;
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_gh::{GithubApi, GithubError};
+use subd_types::GithubIssue;
+
+use crate::text::shorten_title;
+
+/// Tries before a saved suggestion is given up on, about an hour of retries
+const MAX_SUGGESTION_ATTEMPTS: i64 = 12;
+
+/// The repo `!issue` and `!suggest` talk about, from `$SUBD_GITHUB_REPO`
+pub fn issues_repo() -> String {
+    env::var("SUBD_GITHUB_REPO").unwrap_or_else(|_| "tjdevries/subd".to_string())
+}
+
+fn describe(issue: &GithubIssue) -> String {
+    format!(
+        "{} #{} [{}] {} {}",
+        if issue.is_pull_request { "PR" } else { "Issue" },
+        issue.number,
+        issue.state.as_str(),
+        issue.title,
+        issue.url
+    )
+}
+
+/// Handle `!issue <n>`, `n` may start with a `#`
+pub async fn issue(github: &dyn GithubApi, repo: &str, sender: &str, arg: &str) -> Result<String> {
+    let number = match arg.trim_start_matches('#').parse::<i64>() {
+        Ok(number) if number > 0 => number,
+        _ => return Ok(format!("@{}: !issue <number>", sender)),
+    };
+
+    match github.get_issue(repo, number).await {
+        Ok(issue) => Ok(describe(&issue)),
+        Err(GithubError::NotFound(_)) => {
+            Ok(format!("@{}: {}#{} doesn't exist", sender, repo, number))
+        }
+        Err(err) => {
+            println!("Failed to get {}#{}: {}", repo, number, err);
+            Ok(format!(
+                "@{}: couldn't reach GitHub, try again in a bit",
+                sender
+            ))
+        }
+    }
+}
+
+fn suggestion_body(text: &str, sender: &str) -> String {
+    format!("{}\n\nSuggested on stream by {}", text.trim(), sender)
+}
+
+/// Whether a failed request might work later. A missing repo, a bad token or a
+/// response we don't understand will fail the same way every time.
+fn is_retryable(err: &GithubError) -> bool {
+    matches!(
+        err,
+        GithubError::Network(_) | GithubError::RateLimited { .. }
+    )
+}
+
+/// Handle `!suggest <text>`. When GitHub can't be reached the suggestion is
+/// saved, and `retry_pending_suggestions` files it later.
+pub async fn suggest(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+    repo: &str,
+    sender: &str,
+    text: &str,
+) -> Result<String> {
+    if text.trim().is_empty() {
+        return Ok(format!("@{}: !suggest <text>", sender));
+    }
+
+    let title = shorten_title(text);
+    let body = suggestion_body(text, sender);
+    match github.create_issue(repo, &title, &body).await {
+        Ok(issue) => Ok(format!("@{}: thanks! {}", sender, issue.url)),
+        Err(err) if is_retryable(&err) => {
+            println!("Saving suggestion for later: {}", err);
+            subd_db::add_pending_suggestion(conn, repo, &title, &body, sender).await?;
+            Ok(format!(
+                "@{}: couldn't reach GitHub, I'll file it once I can",
+                sender
+            ))
+        }
+        Err(err) => Ok(format!("@{}: couldn't file that: {}", sender, err)),
+    }
+}
+
+/// Try to file every saved suggestion, returning the issues that were created.
+///
+/// Suggestions that can never be filed, or that failed `MAX_SUGGESTION_ATTEMPTS`
+/// times, are dropped so they don't sit in the queue forever.
+pub async fn retry_pending_suggestions(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+) -> Result<Vec<GithubIssue>> {
+    let mut filed = vec![];
+    for pending in subd_db::get_pending_suggestions(conn).await? {
+        match github
+            .create_issue(&pending.repo, &pending.title, &pending.body)
+            .await
+        {
+            Ok(issue) => {
+                subd_db::delete_pending_suggestion(conn, pending.id).await?;
+                filed.push(issue);
+            }
+            Err(err) if is_retryable(&err) && pending.attempts + 1 < MAX_SUGGESTION_ATTEMPTS => {
+                subd_db::record_suggestion_failure(conn, pending.id, &err.to_string()).await?;
+            }
+            Err(err) => {
+                println!("Dropping suggestion {:?}: {}", pending, err);
+                subd_db::delete_pending_suggestion(conn, pending.id).await?;
+            }
+        }
+    }
+
+    Ok(filed)
+}
+
+#[cfg(test)]
+mod test {
+    use subd_gh::FakeGithub;
+    use subd_types::GithubIssueState;
+
+    use super::*;
+    use crate::test_utils::test_conn;
+
+    const REPO: &str = "tjdevries/subd";
+
+    #[tokio::test]
+    async fn describes_issues() -> Result<()> {
+        let github = FakeGithub::new().with_issue(GithubIssue {
+            repo: REPO.to_string(),
+            number: 42,
+            title: "Add themesong previews".to_string(),
+            state: GithubIssueState::Merged,
+            url: "https://github.com/tjdevries/subd/pull/42".to_string(),
+            is_pull_request: true,
+        });
+
+        assert_eq!(
+            issue(&github, REPO, "nyxkrage", "#42").await?,
+            "PR #42 [merged] Add themesong previews https://github.com/tjdevries/subd/pull/42"
+        );
+        assert_eq!(
+            issue(&github, REPO, "nyxkrage", "7").await?,
+            "@nyxkrage: tjdevries/subd#7 doesn't exist"
+        );
+        assert_eq!(
+            issue(&github, REPO, "nyxkrage", "lol").await?,
+            "@nyxkrage: !issue <number>"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn files_suggestions_once_github_is_back() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let github = FakeGithub::new();
+
+        github.fail_with(GithubError::Network("offline".to_string()));
+        let reply = suggest(&mut conn, &github, REPO, "nyxkrage", "add a !lurk command").await?;
+        assert!(reply.contains("I'll file it once I can"));
+
+        // Still offline, so it stays pending
+        assert!(retry_pending_suggestions(&mut conn, &github)
+            .await?
+            .is_empty());
+        let pending = subd_db::get_pending_suggestions(&mut conn).await?;
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(pending[0].suggested_by, "nyxkrage");
+
+        github.recover();
+        let filed = retry_pending_suggestions(&mut conn, &github).await?;
+        assert_eq!(filed[0].title, "add a !lurk command");
+        assert_eq!(github.issues().len(), 1);
+        assert!(subd_db::get_pending_suggestions(&mut conn)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_suggestions_that_keep_failing() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let github = FakeGithub::new();
+
+        github.fail_with(GithubError::Network("offline".to_string()));
+        suggest(&mut conn, &github, REPO, "nyxkrage", "add a !lurk command").await?;
+        suggest(&mut conn, &github, REPO, "theprimeagen", "rewrite it in go").await?;
+
+        // One failing doesn't stop the next from being tried
+        let before = github.requests();
+        retry_pending_suggestions(&mut conn, &github).await?;
+        assert_eq!(github.requests() - before, 2);
+
+        for _ in 1..MAX_SUGGESTION_ATTEMPTS {
+            retry_pending_suggestions(&mut conn, &github).await?;
+        }
+        assert!(subd_db::get_pending_suggestions(&mut conn)
+            .await?
+            .is_empty());
+
+        // A bad token won't fix itself, so there's no point saving it
+        github.fail_with(GithubError::Unauthorized);
+        let reply = suggest(&mut conn, &github, REPO, "nyxkrage", "add a !lurk command").await?;
+        assert!(reply.contains("couldn't file that"));
+        assert!(subd_db::get_pending_suggestions(&mut conn)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+}
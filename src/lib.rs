@@ -1,3 +1,13 @@
 pub mod commands;
+pub mod credits;
+pub mod follows;
+pub mod issues;
+pub mod perks;
+pub mod subscriptions;
+pub mod text;
 pub mod themesong;
 pub mod users;
+pub mod webhooks;
+
+#[cfg(test)]
+mod test_utils;
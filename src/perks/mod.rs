@@ -0,0 +1,164 @@
+use subd_types::{SubTier, UserRoles};
+
+/// How much someone supports the stream. Each level unlocks the perks in `PERK_TABLE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SupportLevel {
+    None,
+    /// Mods, VIPs, founders, Prime and tier 1 subs, and any GitHub sponsor
+    Community,
+    /// Tier 2 subs, or sponsors at $10/month and up
+    Supporter,
+    /// Tier 3 subs, or sponsors at $25/month and up
+    Champion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Perks {
+    /// Longest themesong clip someone can set, 0 means no themesong
+    pub themesong_max_seconds: f64,
+    /// Higher goes first in the themesong download and play queues
+    pub queue_priority: u8,
+}
+
+pub const PERK_TABLE: &[(SupportLevel, Perks)] = &[
+    (
+        SupportLevel::None,
+        Perks {
+            themesong_max_seconds: 0.,
+            queue_priority: 0,
+        },
+    ),
+    (
+        SupportLevel::Community,
+        Perks {
+            themesong_max_seconds: 10.,
+            queue_priority: 1,
+        },
+    ),
+    (
+        SupportLevel::Supporter,
+        Perks {
+            themesong_max_seconds: 15.,
+            queue_priority: 2,
+        },
+    ),
+    (
+        SupportLevel::Champion,
+        Perks {
+            themesong_max_seconds: 20.,
+            queue_priority: 3,
+        },
+    ),
+];
+
+/// Monthly GitHub sponsorship needed for each level, highest first
+const SPONSOR_DOLLARS: &[(i64, SupportLevel)] = &[
+    (25, SupportLevel::Champion),
+    (10, SupportLevel::Supporter),
+    (0, SupportLevel::Community),
+];
+
+fn twitch_level(roles: &UserRoles) -> SupportLevel {
+    match roles.twitch_sub_tier {
+        Some(SubTier::Tier3) => SupportLevel::Champion,
+        Some(SubTier::Tier2) => SupportLevel::Supporter,
+        Some(SubTier::Tier1) | Some(SubTier::Prime) => SupportLevel::Community,
+        None if roles.is_twitch_sub
+            || roles.is_twitch_mod
+            || roles.is_twitch_vip
+            || roles.is_twitch_founder =>
+        {
+            SupportLevel::Community
+        }
+        None => SupportLevel::None,
+    }
+}
+
+fn github_level(roles: &UserRoles) -> SupportLevel {
+    match &roles.github_sponsorship {
+        Some(sponsorship) => SPONSOR_DOLLARS
+            .iter()
+            .find(|(dollars, _)| sponsorship.monthly_dollars >= *dollars)
+            .map(|(_, level)| *level)
+            .unwrap_or(SupportLevel::Community),
+        // Sponsors from before we tracked tiers
+        None if roles.is_github_sponsor => SupportLevel::Community,
+        None => SupportLevel::None,
+    }
+}
+
+/// The best level someone has across twitch and GitHub
+pub fn support_level(roles: &UserRoles) -> SupportLevel {
+    twitch_level(roles).max(github_level(roles))
+}
+
+pub fn perks_for(roles: &UserRoles) -> Perks {
+    let level = support_level(roles);
+    PERK_TABLE
+        .iter()
+        .find(|(perk_level, _)| *perk_level == level)
+        .map(|(_, perks)| *perks)
+        .expect("every support level is in the perk table")
+}
+
+#[cfg(test)]
+mod test {
+    use subd_types::GithubSponsorship;
+
+    use super::*;
+
+    fn sponsor(monthly_dollars: i64) -> UserRoles {
+        UserRoles {
+            is_github_sponsor: true,
+            github_sponsorship: Some(GithubSponsorship {
+                tier_name: format!("${} a month", monthly_dollars),
+                monthly_dollars,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_roles_no_perks() {
+        assert_eq!(support_level(&UserRoles::default()), SupportLevel::None);
+        assert_eq!(perks_for(&UserRoles::default()).themesong_max_seconds, 0.);
+    }
+
+    #[test]
+    fn tiers_map_to_levels() {
+        let prime = UserRoles {
+            is_twitch_sub: true,
+            twitch_sub_tier: Some(SubTier::Prime),
+            ..Default::default()
+        };
+        let tier3 = UserRoles {
+            is_twitch_sub: true,
+            twitch_sub_tier: Some(SubTier::Tier3),
+            ..Default::default()
+        };
+        let moderator = UserRoles {
+            is_twitch_mod: true,
+            ..Default::default()
+        };
+
+        assert_eq!(support_level(&prime), SupportLevel::Community);
+        assert_eq!(support_level(&moderator), SupportLevel::Community);
+        assert_eq!(support_level(&tier3), SupportLevel::Champion);
+        assert_eq!(support_level(&sponsor(5)), SupportLevel::Community);
+        assert_eq!(support_level(&sponsor(10)), SupportLevel::Supporter);
+        assert_eq!(support_level(&sponsor(100)), SupportLevel::Champion);
+    }
+
+    #[test]
+    fn best_level_wins() {
+        let roles = UserRoles {
+            is_twitch_sub: true,
+            twitch_sub_tier: Some(SubTier::Tier1),
+            ..sponsor(10)
+        };
+
+        let perks = perks_for(&roles);
+        assert_eq!(perks.themesong_max_seconds, 15.);
+        assert_eq!(perks.queue_priority, 2);
+    }
+}
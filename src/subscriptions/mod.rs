@@ -0,0 +1,115 @@
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_db::{GifterCount, SubscriptionHistoryEntry};
+use subd_types::SubTier;
+
+/// Save any subscription event we see, so gifts and history can be queried later
+pub async fn record_subscription(
+    conn: &mut SqliteConnection,
+    sub: &subd_types::TwitchSubscriptionEvent,
+) -> Result<()> {
+    subd_db::save_twitch_subscription(conn, subd_twitch::MY_BROADCASTER_ID, sub).await
+}
+
+pub async fn top_gifters(conn: &mut SqliteConnection) -> Result<String> {
+    Ok(format_top_gifters(
+        &subd_db::get_top_gifters(conn, 5).await?,
+    ))
+}
+
+pub async fn sub_history(conn: &mut SqliteConnection, login: &str) -> Result<String> {
+    let login = login.replace("@", "");
+    let history = subd_db::get_subscription_history(conn, &login).await?;
+    Ok(format_sub_history(&login, &history))
+}
+
+pub fn format_top_gifters(gifters: &[GifterCount]) -> String {
+    if gifters.is_empty() {
+        return "Nobody has gifted any subs yet".to_string();
+    }
+
+    let gifters = gifters
+        .iter()
+        .enumerate()
+        .map(|(idx, gifter)| format!("{}. {} ({})", idx + 1, gifter.display_name, gifter.gifted))
+        .collect::<Vec<_>>();
+
+    format!("Top gifters: {}", gifters.join(", "))
+}
+
+fn tier_name(tier: SubTier) -> &'static str {
+    match tier {
+        SubTier::Prime => "Prime",
+        SubTier::Tier1 => "Tier 1",
+        SubTier::Tier2 => "Tier 2",
+        SubTier::Tier3 => "Tier 3",
+    }
+}
+
+pub fn format_sub_history(login: &str, history: &[SubscriptionHistoryEntry]) -> String {
+    let latest = match history.first() {
+        Some(latest) => latest,
+        None => return format!("No subscriptions recorded for {}", login),
+    };
+
+    let mut summary = format!("{}: {} {}", login, tier_name(latest.tier), latest.kind);
+    if let Some(months) = latest.cumulative_months {
+        summary.push_str(&format!(", {} months", months));
+    }
+    if let Some(gifter) = &latest.gifter {
+        summary.push_str(&format!(", gifted by {}", gifter));
+    }
+
+    let gifted = history
+        .iter()
+        .filter(|entry| entry.gifter.is_some())
+        .count();
+    format!(
+        "{} (last seen {}). {} subscription events recorded, {} gifted.",
+        summary,
+        latest.noted_date.format("%Y-%m-%d"),
+        history.len(),
+        gifted
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_top_gifters() {
+        let gifters = vec![
+            GifterCount {
+                display_name: "ThePrimeagen".to_string(),
+                gifted: 50,
+            },
+            GifterCount {
+                display_name: "NyxKrage".to_string(),
+                gifted: 3,
+            },
+        ];
+
+        assert_eq!(
+            format_top_gifters(&gifters),
+            "Top gifters: 1. ThePrimeagen (50), 2. NyxKrage (3)"
+        );
+        assert_eq!(format_top_gifters(&[]), "Nobody has gifted any subs yet");
+    }
+
+    #[test]
+    fn formats_sub_history() {
+        let history = vec![SubscriptionHistoryEntry {
+            tier: SubTier::Tier1,
+            kind: "gift".to_string(),
+            cumulative_months: None,
+            gifter: Some("ThePrimeagen".to_string()),
+            noted_date: "2022-07-05T18:00:00Z".parse().unwrap(),
+        }];
+
+        assert_eq!(
+            format_sub_history("nyxkrage", &history),
+            "nyxkrage: Tier 1 gift, gifted by ThePrimeagen (last seen 2022-07-05). 1 subscription events recorded, 1 gifted."
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use sqlx::{Connection, SqliteConnection};
+use subd_types::UserID;
+
+/// A fresh in-memory database with every migration applied
+pub async fn test_conn() -> anyhow::Result<SqliteConnection> {
+    let mut conn = SqliteConnection::connect(":memory:").await?;
+    sqlx::migrate!("crates/subd-db/migrations")
+        .run(&mut conn)
+        .await?;
+
+    Ok(conn)
+}
+
+/// A user that has chatted as `login`
+pub async fn chatter(conn: &mut SqliteConnection, twitch_id: &str, login: &str) -> UserID {
+    subd_db::create_twitch_user_chat(conn, twitch_id, login)
+        .await
+        .unwrap();
+    subd_db::get_user_from_twitch_user(conn, twitch_id)
+        .await
+        .unwrap()
+}
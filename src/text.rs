@@ -0,0 +1,32 @@
+//! Helpers for text that came from someone else and ends up somewhere short
+
+/// Longest title we read out on stream or give a suggestion
+pub const MAX_TITLE_CHARS: usize = 80;
+
+/// `text` on one line, cut to `MAX_TITLE_CHARS` with a `…` when it's longer
+pub fn shorten_title(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= MAX_TITLE_CHARS {
+        return text;
+    }
+
+    let cut = text.chars().take(MAX_TITLE_CHARS - 1).collect::<String>();
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shortens_titles() {
+        assert_eq!(
+            shorten_title("  add a !lurk\ncommand "),
+            "add a !lurk command"
+        );
+
+        let title = shorten_title(&"a".repeat(200));
+        assert_eq!(title.chars().count(), MAX_TITLE_CHARS);
+        assert!(title.ends_with('…'));
+    }
+}
@@ -0,0 +1,211 @@
+//! Recover the metadata of themesongs downloaded before we saved it,
+//! from the `!themesong <url> <start> <end>` that requested them.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+use subd_types::UserID;
+
+use super::clip::ClipRange;
+use super::validate_themesong;
+
+/// Longest clip the old downloader would take
+const OLD_MAX_SECONDS: f64 = 10.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThemesongRequest {
+    pub url: String,
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+}
+
+/// Parse a `!themesong <url> <mm:ss> <mm:ss>` chat message, if the old
+/// downloader would have accepted it
+pub fn parse_themesong_request(msg: &str) -> Option<ThemesongRequest> {
+    let parts = msg.split_whitespace().collect::<Vec<_>>();
+    let (url, args) = match parts.as_slice() {
+        ["!themesong", url, start, end] => (url, [*start, *end]),
+        _ => return None,
+    };
+
+    // It only knew `mm:ss`, anything else was refused
+    if args.iter().any(|arg| arg.matches(':').count() != 1) {
+        return None;
+    }
+
+    validate_themesong(url).ok()?;
+    let clip = ClipRange::parse(url, &args).ok()?;
+    clip.check_length(OLD_MAX_SECONDS).ok()?;
+
+    Some(ThemesongRequest {
+        url: url.to_string(),
+        start_seconds: clip.start,
+        end_seconds: clip.end,
+    })
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BackfillSummary {
+    pub updated: usize,
+    /// Songs with no usable `!themesong` in chat history
+    pub missing: Vec<UserID>,
+}
+
+/// The most recent request `user_id` made in chat that the old downloader
+/// would have accepted. That's our best guess at their song, though a
+/// download that failed anyway can still be picked.
+async fn find_latest_request(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+) -> Result<Option<(ThemesongRequest, DateTime<Utc>)>> {
+    let messages = sqlx::query!(
+        r#"
+        SELECT msg as "msg!", timestamp as "timestamp!: DateTime<Utc>"
+            FROM TWITCH_CHAT_HISTORY
+            WHERE user_id = ?1 AND msg LIKE '!themesong http%'
+            ORDER BY timestamp DESC, id DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(messages.into_iter().find_map(|message| {
+        parse_themesong_request(&message.msg).map(|request| (request, message.timestamp))
+    }))
+}
+
+/// Fill in url, clip range and request time for every song that doesn't have them
+pub async fn backfill_themesong_metadata(conn: &mut SqliteConnection) -> Result<BackfillSummary> {
+    let user_ids = sqlx::query!(
+        r#"SELECT user_id as "user_id!: i64" FROM USER_THEME_SONGS WHERE url IS NULL"#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut summary = BackfillSummary::default();
+    for record in user_ids {
+        let user_id = record.user_id;
+        let (request, requested_at) = match find_latest_request(conn, &user_id).await? {
+            Some(found) => found,
+            None => {
+                summary.missing.push(user_id);
+                continue;
+            }
+        };
+
+        sqlx::query!(
+            "UPDATE USER_THEME_SONGS
+                SET url = ?2, start_seconds = ?3, end_seconds = ?4,
+                    requested_at = datetime(?5), requested_by = coalesce(requested_by, ?1),
+                    file_format = coalesce(file_format, 'mp3'),
+                    byte_size = coalesce(byte_size, length(song))
+                WHERE user_id = ?1",
+            user_id,
+            request.url,
+            request.start_seconds,
+            request.end_seconds,
+            requested_at
+        )
+        .execute(&mut *conn)
+        .await?;
+        summary.updated += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+    use crate::themesong::get_themesong_metadata;
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(
+            parse_themesong_request(
+                "!themesong https://www.youtube.com/watch?v=SkypZuY6ZvA 01:05 01:10.5"
+            ),
+            Some(ThemesongRequest {
+                url: "https://www.youtube.com/watch?v=SkypZuY6ZvA".to_string(),
+                start_seconds: 65.,
+                end_seconds: 70.5,
+            })
+        );
+        assert!(
+            parse_themesong_request("!themesong https://www.youtube.com/watch?v=SkypZuY6ZvA")
+                .is_none()
+        );
+        assert!(parse_themesong_request("!themesong https://example.com 00:01 00:05").is_none());
+        assert!(
+            parse_themesong_request("!themesong https://youtu.be/SkypZuY6ZvA 00:05 00:01")
+                .is_none()
+        );
+
+        // Refused back then, so they were never downloaded
+        assert!(
+            parse_themesong_request("!themesong https://youtu.be/SkypZuY6ZvA 00:05 00:30")
+                .is_none()
+        );
+        assert!(
+            parse_themesong_request("!themesong https://youtu.be/SkypZuY6ZvA 5s 10s").is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn recovers_metadata_from_chat() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let prime = chatter(&mut conn, "2", "theprimeagen").await;
+
+        for msg in [
+            "!themesong https://youtu.be/old 00:01 00:05",
+            "!themesong https://youtu.be/SkypZuY6ZvA 00:10 00:15",
+            "!themesong https://youtu.be/oops 00:10",
+            "!themesong https://youtu.be/toolong 00:10 00:40",
+        ] {
+            subd_db::save_twitch_message(&mut conn, "1", msg).await?;
+        }
+
+        for user_id in [nyx, prime] {
+            let song = vec![0u8; 16];
+            sqlx::query!(
+                "INSERT INTO USER_THEME_SONGS (user_id, song) VALUES (?1, ?2)",
+                user_id,
+                song
+            )
+            .execute(&mut conn)
+            .await?;
+        }
+
+        let summary = backfill_themesong_metadata(&mut conn).await?;
+        assert_eq!(summary.updated, 1);
+        assert_eq!(summary.missing, vec![prime]);
+
+        let metadata = get_themesong_metadata(&mut conn, &nyx).await?.unwrap();
+        assert_eq!(
+            metadata.url.as_deref(),
+            Some("https://youtu.be/SkypZuY6ZvA")
+        );
+        assert_eq!(metadata.start_seconds, Some(10.));
+        assert_eq!(metadata.end_seconds, Some(15.));
+        assert_eq!(metadata.requested_by, Some(nyx));
+        assert_eq!(metadata.byte_size, Some(16));
+        assert!(metadata.requested_at.is_some());
+
+        // Stored like CURRENT_TIMESTAMP, so it sorts with songs saved since
+        let stored = sqlx::query!(
+            r#"SELECT requested_at as "requested_at!: String" FROM USER_THEME_SONGS WHERE user_id = ?1"#,
+            nyx
+        )
+        .fetch_one(&mut conn)
+        .await?;
+        assert!(!stored.requested_at.contains('T'));
+
+        // Nothing left to do the second time around
+        assert_eq!(backfill_themesong_metadata(&mut conn).await?.updated, 0);
+
+        Ok(())
+    }
+}
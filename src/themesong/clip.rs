@@ -0,0 +1,295 @@
+//! Which part of a video `!themesong` should cut out.
+//!
+//! All of these mean 1:05 to 1:12:
+//!
+//! ```text
+//! !themesong <url> 1:05 1:12
+//! !themesong <url> 00:01:05 00:01:12.0
+//! !themesong <url> 65 72
+//! !themesong <url> 1:05 +7s
+//! !themesong https://youtu.be/<id>?t=65 +7
+//! ```
+
+use std::fmt;
+
+use anyhow::Result;
+use reqwest::Url;
+
+use super::commands::format_timestamp;
+
+pub const CLIP_HELP: &str =
+    "!themesong <url> <start> <end>, like 1:05 1:12 or 1:05 +7s (or a youtube ?t= link and +7s)";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipRange {
+    /// Seconds into the video
+    pub start: f64,
+    pub end: f64,
+}
+
+impl ClipRange {
+    pub fn new(start: f64, end: f64) -> Result<Self> {
+        if end <= start {
+            return Err(anyhow::anyhow!("End must be after start"));
+        }
+
+        Ok(Self { start, end })
+    }
+
+    /// Parse the arguments after the url of a `!themesong`
+    pub fn parse(url: &str, args: &[&str]) -> Result<Self> {
+        let args = args
+            .iter()
+            .map(|arg| arg.trim())
+            .filter(|arg| !arg.is_empty())
+            .collect::<Vec<_>>();
+
+        let (start, end) = match args.as_slice() {
+            [start, end] => (parse_timestamp(start)?, *end),
+            [end] => match start_from_url(url) {
+                Some(start) => (start, *end),
+                None => return Err(anyhow::anyhow!("Missing a start time. {}", CLIP_HELP)),
+            },
+            _ => return Err(anyhow::anyhow!("Format: {}", CLIP_HELP)),
+        };
+
+        let end = match end.strip_prefix('+') {
+            Some(duration) => start + parse_timestamp(duration)?,
+            None => parse_timestamp(end)?,
+        };
+
+        Self::new(start, end)
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.end - self.start
+    }
+
+    pub fn check_length(&self, max_seconds: f64) -> Result<()> {
+        if self.seconds() > max_seconds {
+            return Err(anyhow::anyhow!(
+                "Too long. Choose a clip of at most {} seconds",
+                max_seconds
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ClipRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            format_timestamp(self.start),
+            format_timestamp(self.end)
+        )
+    }
+}
+
+/// A plain number like `65` or `5.25`, without the signs, `inf`s and `NaN`s `f64` also accepts
+fn number(text: &str) -> Result<f64> {
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(anyhow::anyhow!("{:?} isn't a number", text));
+    }
+
+    Ok(text.parse()?)
+}
+
+/// `1h2m5.5s`, the way youtube and twitch links write times
+fn parse_units(timestamp: &str) -> Result<f64> {
+    let mut seconds = 0.;
+    let mut rest = timestamp;
+    for (unit, scale) in [('h', 3600.), ('m', 60.), ('s', 1.)] {
+        if let Some((value, after)) = rest.split_once(unit) {
+            seconds += number(value)? * scale;
+            rest = after;
+        }
+    }
+
+    if !rest.is_empty() {
+        return Err(anyhow::anyhow!("Can't read {:?} as a time", timestamp));
+    }
+
+    Ok(seconds)
+}
+
+/// Seconds into the video for `1:05`, `00:01:05.5`, `65`, `65.5s` or `1m5s`
+pub fn parse_timestamp(timestamp: &str) -> Result<f64> {
+    let timestamp = timestamp.trim();
+    if timestamp.ends_with(|c| matches!(c, 'h' | 'm' | 's')) {
+        return parse_units(timestamp);
+    }
+
+    let parts = timestamp.split(':').collect::<Vec<_>>();
+    let (seconds, larger) = match parts.split_last() {
+        Some((seconds, larger)) if larger.len() <= 2 => (number(seconds)?, larger),
+        _ => return Err(anyhow::anyhow!("Can't read {:?} as a time", timestamp)),
+    };
+
+    if !larger.is_empty() && seconds >= 60. {
+        return Err(anyhow::anyhow!(
+            "Seconds must be under 60 in {:?}",
+            timestamp
+        ));
+    }
+
+    let (hours, minutes) = match larger {
+        [hours, minutes] => {
+            let minutes = number(minutes)?;
+            if minutes >= 60. {
+                return Err(anyhow::anyhow!(
+                    "Minutes must be under 60 in {:?}",
+                    timestamp
+                ));
+            }
+            (number(hours)?, minutes)
+        }
+        [minutes] => (0., number(minutes)?),
+        _ => (0., 0.),
+    };
+
+    Ok(hours * 3600. + minutes * 60. + seconds)
+}
+
+/// Where a `?t=1m5s` link starts playing
+pub fn start_from_url(url: &str) -> Option<f64> {
+    let url = Url::parse(url).ok()?;
+    let (_, start) = url
+        .query_pairs()
+        .find(|(key, _)| key == "t" || key == "start")?;
+
+    parse_timestamp(&start).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const URL: &str = "https://www.youtube.com/watch?v=SkypZuY6ZvA";
+
+    fn clip(args: &[&str]) -> Result<ClipRange> {
+        ClipRange::parse(URL, args)
+    }
+
+    #[test]
+    fn accepts_simple_timestamps() {
+        let max = |args: &[&str], seconds| clip(args).and_then(|clip| clip.check_length(seconds));
+
+        assert!(max(&["00:05", "00:10"], 10.0).is_ok());
+        assert!(max(&["01:58", "02:05"], 10.0).is_ok());
+        assert!(max(&["01:58.231", "02:05.09"], 10.).is_ok());
+        assert!(max(&["01:58", "02:05"], 3.).is_err());
+        assert!(max(&["00:05", "00:00"], 10.).is_err());
+        assert!(max(&["00:05", "00:50"], 10.).is_err());
+    }
+
+    #[test]
+    fn accepts_every_way_of_writing_a_clip() {
+        let expected = ClipRange::new(65., 72.).unwrap();
+
+        for args in [
+            &["1:05", "1:12"][..],
+            &["00:01:05", "00:01:12.0"],
+            &["65", "72"],
+            &["65s", "1m12s"],
+            &["1:05", "+7s"],
+            &["1:05", "+7"],
+            &["1:05", "", "+0:07"],
+        ] {
+            assert_eq!(clip(args).unwrap(), expected, "{:?}", args);
+        }
+
+        for url in [
+            "https://youtu.be/SkypZuY6ZvA?t=65",
+            "https://www.youtube.com/watch?v=SkypZuY6ZvA&t=1m5s",
+        ] {
+            assert_eq!(ClipRange::parse(url, &["+7s"]).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        for args in [
+            &[][..],
+            &["+7s"],
+            &["1:05"],
+            &["1:05", "1:00"],
+            &["1:05", "+0"],
+            &["1:75", "2:00"],
+            &["1:60:00", "2:00:00"],
+            &["1:2:3:4", "2:00"],
+            &["-5", "10"],
+            &["inf", "NaN"],
+            &["1m5", "2:00"],
+            &["1:05", "1:12", "1:20"],
+        ] {
+            assert!(clip(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn displays_like_it_was_written() {
+        assert_eq!(
+            ClipRange::new(65., 72.5).unwrap().to_string(),
+            "01:05-01:12.5"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn parses_what_it_displays(start in 0u32..360_000, length in 1u32..600) {
+            // Timestamps are shown to a tenth of a second
+            let range = ClipRange::new(start as f64 / 10., (start + length) as f64 / 10.).unwrap();
+
+            let shown = range.to_string();
+            let (start, end) = shown.split_once('-').unwrap();
+            let parsed = clip(&[start, end]).unwrap();
+
+            prop_assert!((parsed.start - range.start).abs() < 1e-6);
+            prop_assert!((parsed.end - range.end).abs() < 1e-6);
+        }
+
+        #[test]
+        fn every_syntax_agrees(
+            hours in 0u32..3,
+            minutes in 0u32..60,
+            seconds in 0u32..60,
+            length in 1u32..30,
+        ) {
+            let start = (hours * 3600 + minutes * 60 + seconds) as f64;
+            let expected = ClipRange::new(start, start + length as f64).unwrap();
+
+            let colons = format!("{}:{:02}:{:02}", hours, minutes, seconds);
+            let units = format!("{}h{}m{}s", hours, minutes, seconds);
+            let bare = start.to_string();
+            let plus = format!("+{}s", length);
+
+            for start in [&colons, &units, &bare] {
+                prop_assert_eq!(clip(&[start, &plus]).unwrap(), expected);
+            }
+
+            let url = format!("https://youtu.be/SkypZuY6ZvA?t={}", units);
+            prop_assert_eq!(ClipRange::parse(&url, &[&plus]).unwrap(), expected);
+        }
+
+        #[test]
+        fn never_panics(start in "\\PC*", end in "\\PC*") {
+            let _ = clip(&[&start, &end]);
+        }
+
+        #[test]
+        fn parsed_clips_end_after_they_start(
+            start in "[0-9:.+hms]{1,10}",
+            end in "[0-9:.+hms]{1,10}",
+        ) {
+            if let Ok(range) = clip(&[&start, &end]) {
+                prop_assert!(range.seconds() > 0.);
+                prop_assert!(range.start >= 0.);
+            }
+        }
+    }
+}
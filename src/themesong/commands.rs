@@ -0,0 +1,242 @@
+//! `!themesong info`, `!themesong preview` and `!themesong delete`.
+//! `!themesong set @user` goes through the regular download instead.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_types::UserID;
+
+use super::clip::{ClipRange, CLIP_HELP};
+use super::review::{get_themesong_status, ThemesongStatus};
+use super::{delete_user_themesong, get_themesong_metadata, last_played_themesong};
+
+/// How often a viewer can preview their own themesong on stream. Mods can always preview.
+pub const PREVIEW_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// `65.5` -> `01:05.5`, the way clips are written in `!themesong`
+pub fn format_timestamp(seconds: f64) -> String {
+    // Round first, so 119.96 carries over to 02:00 instead of showing 01:60.0
+    let tenths = (seconds * 10.).round() as i64;
+    let hours = tenths / 36000;
+    let minutes = tenths / 600 % 60;
+    let tenths = tenths % 600;
+
+    let seconds = if tenths % 10 == 0 {
+        format!("{:02}", tenths / 10)
+    } else {
+        format!("{:02}.{}", tenths / 10, tenths % 10)
+    };
+
+    if hours > 0 {
+        format!("{:02}:{:02}:{}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{}", minutes, seconds)
+    }
+}
+
+/// Handle `!themesong info`
+pub async fn info(conn: &mut SqliteConnection, user_id: &UserID, name: &str) -> Result<String> {
+    let metadata = match get_themesong_metadata(conn, user_id).await? {
+        Some(metadata) => metadata,
+        None => {
+            return Ok(format!(
+                "@{}: no themesong yet, set one with {}",
+                name, CLIP_HELP
+            ))
+        }
+    };
+
+    let source = metadata
+        .url
+        .unwrap_or_else(|| "(source unknown)".to_string());
+    let clip = match (metadata.start_seconds, metadata.end_seconds) {
+        (Some(start), Some(end)) => format!(" {}", ClipRange { start, end }),
+        _ => "".to_string(),
+    };
+
+    let status = match get_themesong_status(conn, user_id).await? {
+        Some(ThemesongStatus::Approved) | None => "".to_string(),
+        Some(status) => format!(" ({})", status.as_str()),
+    };
+
+    let played = match last_played_themesong(conn, user_id).await? {
+        Some(played_at) => format!("last played {}", played_at.format("%Y-%m-%d")),
+        None => "never played".to_string(),
+    };
+
+    Ok(format!(
+        "@{}: {}{}{}, {}",
+        name, source, clip, status, played
+    ))
+}
+
+/// Handle `!themesong delete`
+pub async fn delete(conn: &mut SqliteConnection, user_id: &UserID, name: &str) -> Result<String> {
+    Ok(match delete_user_themesong(conn, user_id).await? {
+        true => format!("@{}: deleted your themesong", name),
+        false => format!("@{}: you don't have a themesong", name),
+    })
+}
+
+/// Remembers when each viewer last previewed their themesong on stream
+#[derive(Debug, Default)]
+pub struct PreviewCooldown {
+    last_preview: HashMap<UserID, Instant>,
+}
+
+impl PreviewCooldown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a preview for `user_id`, or say how long until they can
+    pub fn try_preview(&mut self, user_id: &UserID, now: Instant) -> Result<(), Duration> {
+        if let Some(last) = self.last_preview.get(user_id) {
+            let elapsed = now.saturating_duration_since(*last);
+            if elapsed < PREVIEW_COOLDOWN {
+                return Err(PREVIEW_COOLDOWN - elapsed);
+            }
+        }
+
+        self.last_preview.insert(*user_id, now);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preview {
+    Play,
+    Refuse(String),
+}
+
+/// Handle `!themesong preview`, which plays an approved themesong on stream
+/// without counting it as played for today
+pub async fn preview(
+    conn: &mut SqliteConnection,
+    cooldown: &mut PreviewCooldown,
+    user_id: &UserID,
+    name: &str,
+    is_moderator: bool,
+    now: Instant,
+) -> Result<Preview> {
+    match get_themesong_status(conn, user_id).await? {
+        Some(ThemesongStatus::Approved) => {}
+        Some(_) => {
+            return Ok(Preview::Refuse(format!(
+                "@{}: only approved themesongs can be previewed on stream",
+                name
+            )))
+        }
+        None => {
+            return Ok(Preview::Refuse(format!(
+                "@{}: you don't have a themesong",
+                name
+            )))
+        }
+    }
+
+    if is_moderator {
+        return Ok(Preview::Play);
+    }
+
+    Ok(match cooldown.try_preview(user_id, now) {
+        Ok(()) => Preview::Play,
+        Err(remaining) => Preview::Refuse(format!(
+            "@{}: you can preview again in {} minute(s)",
+            name,
+            remaining.as_secs() / 60 + 1
+        )),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(65.), "01:05");
+        assert_eq!(format_timestamp(70.5), "01:10.5");
+        assert_eq!(format_timestamp(5.), "00:05");
+        assert_eq!(format_timestamp(3725.), "01:02:05");
+        assert_eq!(format_timestamp(119.96), "02:00");
+        assert_eq!(format_timestamp(3599.97), "01:00:00");
+        assert_eq!(format_timestamp(59.94), "00:59.9");
+    }
+
+    #[test]
+    fn previews_have_a_cooldown() {
+        let mut cooldown = PreviewCooldown::new();
+        let start = Instant::now();
+
+        assert!(cooldown.try_preview(&1, start).is_ok());
+        assert!(cooldown.try_preview(&2, start).is_ok());
+        assert_eq!(
+            cooldown.try_preview(&1, start + Duration::from_secs(60)),
+            Err(PREVIEW_COOLDOWN - Duration::from_secs(60))
+        );
+        assert!(cooldown.try_preview(&1, start + PREVIEW_COOLDOWN).is_ok());
+    }
+
+    #[tokio::test]
+    async fn shows_and_deletes_themesongs() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+
+        assert!(info(&mut conn, &nyx, "nyxkrage")
+            .await?
+            .contains("no themesong yet"));
+
+        let song = vec![0u8; 16];
+        sqlx::query!(
+            "INSERT INTO USER_THEME_SONGS (user_id, song, url, start_seconds, end_seconds)
+                VALUES (?1, ?2, 'https://youtu.be/SkypZuY6ZvA', 65, 70.5)",
+            nyx,
+            song
+        )
+        .execute(&mut conn)
+        .await?;
+
+        assert_eq!(
+            info(&mut conn, &nyx, "nyxkrage").await?,
+            "@nyxkrage: https://youtu.be/SkypZuY6ZvA 01:05-01:10.5 (pending), never played"
+        );
+
+        let mut cooldown = PreviewCooldown::new();
+        let now = Instant::now();
+        assert!(matches!(
+            preview(&mut conn, &mut cooldown, &nyx, "nyxkrage", false, now).await?,
+            Preview::Refuse(_)
+        ));
+
+        sqlx::query!("UPDATE USER_THEME_SONGS SET status = 'approved'")
+            .execute(&mut conn)
+            .await?;
+        assert_eq!(
+            preview(&mut conn, &mut cooldown, &nyx, "nyxkrage", false, now).await?,
+            Preview::Play
+        );
+        assert!(matches!(
+            preview(&mut conn, &mut cooldown, &nyx, "nyxkrage", false, now).await?,
+            Preview::Refuse(_)
+        ));
+        assert_eq!(
+            preview(&mut conn, &mut cooldown, &nyx, "nyxkrage", true, now).await?,
+            Preview::Play
+        );
+
+        assert_eq!(
+            delete(&mut conn, &nyx, "nyxkrage").await?,
+            "@nyxkrage: deleted your themesong"
+        );
+        assert_eq!(
+            delete(&mut conn, &nyx, "nyxkrage").await?,
+            "@nyxkrage: you don't have a themesong"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+//! `!themesong` downloads wait in a queue in the database instead of running
+//! one at a time in the chat handler, so a slow yt-dlp only holds up its own
+//! request.
+//!
+//! Each user has at most one job: asking again cancels whatever they had
+//! queued or running. Downloads that fail on something that might go away
+//! (the connection dropped, yt-dlp fell over) go back in the queue a little
+//! later, up to `max_attempts` times.
+
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use sqlx::SqliteConnection;
+use subd_db::{NewThemesongJob, ThemesongJob, ThemesongJobStatus};
+use subd_types::ThemesongDownloadStage;
+
+use super::clip::ClipRange;
+use super::probe::ThemesongRejection;
+use super::review;
+use super::source::{Sources, TransientError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobConfig {
+    /// Downloads running at the same time
+    pub concurrency: usize,
+    /// Tries before a job fails for good, counting the first
+    pub max_attempts: i64,
+    /// Wait before the first retry, doubled for every one after
+    pub retry_delay: Duration,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 2,
+            max_attempts: 3,
+            retry_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl JobConfig {
+    /// The defaults, overridden by `$SUBD_THEMESONG_DOWNLOADS`,
+    /// `$SUBD_THEMESONG_DOWNLOAD_ATTEMPTS` and `$SUBD_THEMESONG_RETRY_DELAY` (seconds)
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(concurrency) = env_var("SUBD_THEMESONG_DOWNLOADS") {
+            config.concurrency = concurrency;
+        }
+        if let Some(max_attempts) = env_var("SUBD_THEMESONG_DOWNLOAD_ATTEMPTS") {
+            config.max_attempts = max_attempts;
+        }
+        if let Some(retry_delay) = env_var("SUBD_THEMESONG_RETRY_DELAY") {
+            config.retry_delay = Duration::from_secs(retry_delay);
+        }
+
+        config.concurrency = config.concurrency.max(1);
+        config
+    }
+
+    /// How long to wait after failing attempt number `attempts`
+    pub fn backoff(&self, attempts: i64) -> Duration {
+        let doublings = (attempts - 1).clamp(0, 10) as u32;
+        self.retry_delay * 2u32.pow(doublings)
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Enqueued {
+    pub id: i64,
+    /// How many queued jobs start before this one
+    pub position: usize,
+    /// The user's earlier jobs this one replaced
+    pub cancelled: Vec<i64>,
+}
+
+/// Queue `job`, cancelling anything the same user already had going
+pub async fn enqueue(conn: &mut SqliteConnection, job: &NewThemesongJob) -> Result<Enqueued> {
+    let cancelled = subd_db::cancel_themesong_jobs_for(conn, &job.user_id).await?;
+    let id = subd_db::add_themesong_job(conn, job).await?;
+    let position = subd_db::queued_themesong_jobs_ahead(conn, id).await? as usize;
+
+    Ok(Enqueued {
+        id,
+        position,
+        cancelled,
+    })
+}
+
+/// Whether trying again later could work. Sources rejecting a video, bad
+/// audio and the like will fail the same way every time.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    if err.downcast_ref::<ThemesongRejection>().is_some() {
+        return false;
+    }
+
+    err.downcast_ref::<TransientError>().is_some()
+        || err.downcast_ref::<std::io::Error>().is_some()
+        || err.downcast_ref::<reqwest::Error>().is_some()
+        || err.downcast_ref::<youtube_dl::Error>().is_some()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobOutcome {
+    Done,
+    /// Back in the queue, it'll run again after a while
+    Retrying {
+        attempts: i64,
+        error: String,
+    },
+    /// The reason, in words chat can understand
+    Failed(String),
+}
+
+/// Download a job that `subd_db::claim_next_themesong_job` handed out, and
+/// record how it went
+pub async fn run_job(
+    conn: &mut SqliteConnection,
+    sources: &Sources,
+    config: &JobConfig,
+    job: &ThemesongJob,
+    progress: &(dyn Fn(ThemesongDownloadStage) + Send + Sync),
+) -> Result<JobOutcome> {
+    let result = match ClipRange::new(job.start_seconds, job.end_seconds) {
+        Ok(clip) => {
+            super::download_themesong_with_progress(
+                conn,
+                sources,
+                &job.user_id,
+                &job.requested_by,
+                &job.url,
+                &clip,
+                job.max_seconds,
+                progress,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+
+    let err = match result {
+        Ok(()) => {
+            if job.approve {
+                // A mod picked it, so there's nothing left to review
+                review::approve_themesong(conn, &job.user_id, &job.requested_by).await?;
+            }
+            subd_db::finish_themesong_job(conn, job.id, ThemesongJobStatus::Done, None).await?;
+            return Ok(JobOutcome::Done);
+        }
+        Err(err) => err,
+    };
+
+    if is_transient(&err) && job.attempts < config.max_attempts {
+        let error = err.to_string();
+        let backoff = chrono::Duration::from_std(config.backoff(job.attempts))?;
+        subd_db::retry_themesong_job(conn, job.id, &error, Utc::now() + backoff).await?;
+        return Ok(JobOutcome::Retrying {
+            attempts: job.attempts,
+            error,
+        });
+    }
+
+    let error = match err.downcast_ref::<ThemesongRejection>() {
+        Some(rejection) => rejection.to_string(),
+        None => {
+            println!("themesong: download {} failed: {:?}", job.id, err);
+            "Failed to download".to_string()
+        }
+    };
+    subd_db::finish_themesong_job(conn, job.id, ThemesongJobStatus::Failed, Some(&error)).await?;
+
+    Ok(JobOutcome::Failed(error))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+    use crate::themesong::approved_themesong;
+    use crate::themesong::source::FakeSource;
+
+    const TONE: &[u8] = include_bytes!("fixtures/tone.wav");
+    const URL: &str = "https://www.youtube.com/watch?v=SkypZuY6ZvA";
+
+    fn new_job(user_id: i64, url: &str) -> NewThemesongJob {
+        NewThemesongJob {
+            user_id,
+            display_name: format!("user{}", user_id),
+            requested_by: user_id,
+            requester_name: format!("user{}", user_id),
+            url: url.to_string(),
+            start_seconds: 0.,
+            end_seconds: 3.,
+            max_seconds: 10.,
+            approve: false,
+            priority: 0,
+        }
+    }
+
+    async fn claim(conn: &mut SqliteConnection) -> Result<ThemesongJob> {
+        // Far enough ahead that any retry is due
+        let later = Utc::now() + chrono::Duration::days(1);
+        Ok(subd_db::claim_next_themesong_job(conn, later)
+            .await?
+            .expect("a queued job"))
+    }
+
+    #[test]
+    fn backs_off() {
+        let config = JobConfig::default();
+        assert_eq!(config.backoff(1), Duration::from_secs(30));
+        assert_eq!(config.backoff(3), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn retries_sources_having_a_bad_day() {
+        let rate_limited = TransientError("yt-dlp failed: HTTP Error 429".to_string());
+        assert!(is_transient(&rate_limited.into()));
+
+        let gone = ThemesongRejection::Unavailable("Video unavailable".to_string());
+        assert!(!is_transient(&gone.into()));
+    }
+
+    #[tokio::test]
+    async fn asking_again_replaces_the_old_request() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let prime = chatter(&mut conn, "2", "theprimeagen").await;
+
+        let first = enqueue(&mut conn, &new_job(nyx, URL)).await?;
+        assert_eq!(first.position, 0);
+        assert_eq!(enqueue(&mut conn, &new_job(prime, URL)).await?.position, 1);
+
+        let again = enqueue(&mut conn, &new_job(nyx, URL)).await?;
+        assert_eq!(again.cancelled, vec![first.id]);
+        assert_eq!(again.position, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_progress_and_approves_mod_clips() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let sources = Sources::all(Arc::new(FakeSource::new().with_fixture(URL, TONE)));
+
+        let job = NewThemesongJob {
+            approve: true,
+            ..new_job(nyx, URL)
+        };
+        let id = enqueue(&mut conn, &job).await?.id;
+
+        let stages = Mutex::new(vec![]);
+        let progress = |stage: ThemesongDownloadStage| stages.lock().unwrap().push(stage);
+        let job = claim(&mut conn).await?;
+        let outcome = run_job(&mut conn, &sources, &JobConfig::default(), &job, &progress).await?;
+
+        assert_eq!(outcome, JobOutcome::Done);
+        assert_eq!(
+            stages.into_inner().unwrap(),
+            vec![
+                ThemesongDownloadStage::Checking,
+                ThemesongDownloadStage::Downloading,
+                ThemesongDownloadStage::Normalizing,
+                ThemesongDownloadStage::Saving,
+            ]
+        );
+        assert!(approved_themesong(&mut conn, &nyx).await?.is_some());
+
+        let job = subd_db::get_themesong_job(&mut conn, id).await?.unwrap();
+        assert_eq!(job.status()?, ThemesongJobStatus::Done);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_only() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let prime = chatter(&mut conn, "2", "theprimeagen").await;
+
+        let flaky = Arc::new(
+            FakeSource::new()
+                .with_fixture(URL, TONE)
+                .with_failures(URL, 1),
+        );
+        let sources = Sources::all(flaky.clone());
+        let config = JobConfig::default();
+
+        enqueue(&mut conn, &new_job(nyx, URL)).await?;
+        let job = claim(&mut conn).await?;
+        let outcome = run_job(&mut conn, &sources, &config, &job, &|_| {}).await?;
+        assert!(matches!(outcome, JobOutcome::Retrying { attempts: 1, .. }));
+
+        // Not due yet
+        assert_eq!(
+            subd_db::claim_next_themesong_job(&mut conn, Utc::now()).await?,
+            None
+        );
+
+        let job = claim(&mut conn).await?;
+        assert_eq!(job.attempts, 2);
+        let outcome = run_job(&mut conn, &sources, &config, &job, &|_| {}).await?;
+        assert_eq!(outcome, JobOutcome::Done);
+        assert_eq!(flaky.downloads(), vec![URL, URL]);
+
+        // The video is gone, so there's no point trying again
+        enqueue(&mut conn, &new_job(prime, "https://youtu.be/gone")).await?;
+        let job = claim(&mut conn).await?;
+        let outcome = run_job(&mut conn, &sources, &config, &job, &|_| {}).await?;
+        assert_eq!(
+            outcome,
+            JobOutcome::Failed("that video is unavailable: Video unavailable".to_string())
+        );
+
+        let job = subd_db::get_themesong_job(&mut conn, job.id)
+            .await?
+            .unwrap();
+        assert_eq!(job.status()?, ThemesongJobStatus::Failed);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! Make every themesong about as loud as every other one.
+//!
+//! Clips are decoded, trimmed of leading/trailing silence, measured (EBU R128
+//! integrated loudness and sample peak), gained to the target loudness without
+//! letting the peak clip, faded in and out, and stored as wav.
+
+use std::env;
+use std::io::Cursor;
+use std::time::Duration;
+
+use anyhow::Result;
+use ebur128::{EbuR128, Mode};
+use rodio::Source;
+
+pub const NORMALIZED_FORMAT: &str = "wav";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizeConfig {
+    pub target_lufs: f64,
+    /// Gain is limited so the loudest sample stays under this
+    pub max_peak_dbfs: f64,
+    /// Anything quieter than this at the start or end is cut
+    pub silence_dbfs: f64,
+    pub fade_in: Duration,
+    pub fade_out: Duration,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        Self {
+            target_lufs: -16.,
+            max_peak_dbfs: -1.,
+            silence_dbfs: -50.,
+            fade_in: Duration::from_millis(50),
+            fade_out: Duration::from_millis(500),
+        }
+    }
+}
+
+impl NormalizeConfig {
+    /// The defaults, with the target loudness from `$SUBD_THEMESONG_LUFS`
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(target) = env::var("SUBD_THEMESONG_LUFS")
+            .ok()
+            .and_then(|target| target.parse().ok())
+        {
+            config.target_lufs = target;
+        }
+
+        config
+    }
+}
+
+/// Interleaved samples between -1 and 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Audio {
+    fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    fn frames_in(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64) as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// `-inf` for silence
+    pub integrated_lufs: f64,
+    pub peak_dbfs: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Normalized {
+    pub song: Vec<u8>,
+    pub format: &'static str,
+    /// Measured before any gain was applied
+    pub loudness: Loudness,
+}
+
+fn to_db(amplitude: f64) -> f64 {
+    20. * amplitude.log10()
+}
+
+fn from_db(db: f64) -> f64 {
+    10f64.powf(db / 20.)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Audio> {
+    let decoder = rodio::Decoder::new(Cursor::new(bytes.to_vec()))?;
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+    let samples = decoder
+        .map(|sample| sample as f32 / i16::MAX as f32)
+        .collect();
+
+    Ok(Audio {
+        channels,
+        sample_rate,
+        samples,
+    })
+}
+
+pub fn encode_wav(audio: &Audio) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: audio.channels,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut cursor = Cursor::new(vec![]);
+    {
+        let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+        for sample in &audio.samples {
+            writer.write_sample((sample.clamp(-1., 1.) * i16::MAX as f32) as i16)?;
+        }
+        writer.finalize()?;
+    }
+
+    Ok(cursor.into_inner())
+}
+
+/// Cut the frames before the first and after the last one louder than `threshold_dbfs`
+pub fn trim_silence(audio: &mut Audio, threshold_dbfs: f64) {
+    let threshold = from_db(threshold_dbfs) as f32;
+    let channels = audio.channels as usize;
+    let is_loud = |frame: &[f32]| frame.iter().any(|sample| sample.abs() > threshold);
+
+    let frames = audio.samples.chunks(channels).collect::<Vec<_>>();
+    let start = frames.iter().position(|frame| is_loud(frame));
+    let end = frames.iter().rposition(|frame| is_loud(frame));
+
+    audio.samples = match (start, end) {
+        (Some(start), Some(end)) => audio.samples[start * channels..(end + 1) * channels].to_vec(),
+        _ => vec![],
+    };
+}
+
+pub fn measure(audio: &Audio) -> Result<Loudness> {
+    let mut meter = EbuR128::new(
+        audio.channels as u32,
+        audio.sample_rate,
+        Mode::I | Mode::SAMPLE_PEAK,
+    )?;
+    meter.add_frames_f32(&audio.samples)?;
+
+    let mut peak: f64 = 0.;
+    for channel in 0..audio.channels as u32 {
+        peak = peak.max(meter.sample_peak(channel)?);
+    }
+
+    Ok(Loudness {
+        integrated_lufs: meter.loudness_global()?,
+        peak_dbfs: to_db(peak),
+    })
+}
+
+/// How much to change the volume to hit the target, without clipping
+pub fn gain_for(loudness: &Loudness, config: &NormalizeConfig) -> f64 {
+    if !loudness.integrated_lufs.is_finite() {
+        return 0.;
+    }
+
+    let gain = config.target_lufs - loudness.integrated_lufs;
+    gain.min(config.max_peak_dbfs - loudness.peak_dbfs)
+}
+
+pub fn apply_gain(audio: &mut Audio, gain_db: f64) {
+    let gain = from_db(gain_db) as f32;
+    for sample in &mut audio.samples {
+        *sample *= gain;
+    }
+}
+
+/// Linear fade in and out, shortened to half the clip each if it's very short
+pub fn fade(audio: &mut Audio, fade_in: Duration, fade_out: Duration) {
+    let channels = audio.channels as usize;
+    let frames = audio.frames();
+    let fade_in = audio.frames_in(fade_in).min(frames / 2);
+    let fade_out = audio.frames_in(fade_out).min(frames / 2);
+
+    for (index, frame) in audio.samples.chunks_mut(channels).enumerate() {
+        let mut volume = 1.;
+        if index < fade_in {
+            volume = index as f32 / fade_in as f32;
+        }
+        let remaining = frames - index - 1;
+        if remaining < fade_out {
+            volume = volume.min(remaining as f32 / fade_out as f32);
+        }
+
+        for sample in frame {
+            *sample *= volume;
+        }
+    }
+}
+
+pub fn normalize(bytes: &[u8], config: &NormalizeConfig) -> Result<Normalized> {
+    let mut audio = decode(bytes)?;
+
+    trim_silence(&mut audio, config.silence_dbfs);
+    if audio.samples.is_empty() {
+        return Err(anyhow::anyhow!("That clip is completely silent"));
+    }
+
+    let loudness = measure(&audio)?;
+    apply_gain(&mut audio, gain_for(&loudness, config));
+    fade(&mut audio, config.fade_in, config.fade_out);
+
+    Ok(Normalized {
+        song: encode_wav(&audio)?,
+        format: NORMALIZED_FORMAT,
+        loudness,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A 1kHz stereo sine wave at `dbfs`, with `padding` of silence on both sides
+    fn sine(dbfs: f64, seconds: f64, padding: f64) -> Audio {
+        let sample_rate = 48_000;
+        let amplitude = from_db(dbfs) as f32;
+        let frames = (seconds * sample_rate as f64) as usize;
+        let padding = vec![0.; (padding * sample_rate as f64) as usize * 2];
+
+        let mut samples = padding.clone();
+        for frame in 0..frames {
+            let t = frame as f32 / sample_rate as f32;
+            let sample = amplitude * (2. * std::f32::consts::PI * 1000. * t).sin();
+            samples.extend([sample, sample]);
+        }
+        samples.extend(padding);
+
+        Audio {
+            channels: 2,
+            sample_rate,
+            samples,
+        }
+    }
+
+    #[test]
+    fn trims_silence() {
+        let mut audio = sine(-20., 1., 0.5);
+        trim_silence(&mut audio, -50.);
+        assert!((audio.frames() as i64 - 48_000).abs() < 48);
+
+        let mut silence = sine(-80., 1., 0.);
+        trim_silence(&mut silence, -50.);
+        assert!(silence.samples.is_empty());
+    }
+
+    #[test]
+    fn normalizes_quiet_and_loud_clips_to_target() -> Result<()> {
+        let config = NormalizeConfig::default();
+
+        for dbfs in [-30., -6.] {
+            let mut audio = sine(dbfs, 3., 0.);
+            let gain = gain_for(&measure(&audio)?, &config);
+            apply_gain(&mut audio, gain);
+
+            let after = measure(&audio)?;
+            assert!((after.integrated_lufs - config.target_lufs).abs() < 0.5);
+            assert!(after.peak_dbfs <= config.max_peak_dbfs + 0.01);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn never_clips() -> Result<()> {
+        let config = NormalizeConfig {
+            target_lufs: 0.,
+            ..Default::default()
+        };
+
+        let loudness = measure(&sine(-20., 3., 0.))?;
+        let gain = gain_for(&loudness, &config);
+        assert!((loudness.peak_dbfs + gain - config.max_peak_dbfs).abs() < 0.01);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fades_in_and_out() {
+        let mut audio = Audio {
+            channels: 1,
+            sample_rate: 1000,
+            samples: vec![1.; 1000],
+        };
+        fade(
+            &mut audio,
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+        );
+
+        assert_eq!(audio.samples[0], 0.);
+        assert_eq!(audio.samples[500], 1.);
+        assert_eq!(audio.samples[999], 0.);
+        assert!(audio.samples[50] < audio.samples[99]);
+    }
+}
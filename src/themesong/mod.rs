@@ -1,40 +1,103 @@
 use std::io::{BufReader, Cursor};
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use psl::Psl;
 use reqwest::Url;
-use sqlx::SqliteConnection;
-use subd_types::{UserID, UserRoles};
-use tokio::{fs::File, io::AsyncReadExt};
+use sqlx::{Connection, SqliteConnection};
+use subd_types::{ThemesongDownloadStage, UserID, UserRoles};
 use twitch_irc::message::PrivmsgMessage;
 
-const THEMESONG_LOCATION: &str = "/tmp/themesong";
+use crate::perks;
+use clip::ClipRange;
+use replay::ReplayConfig;
+use source::{SourceKind, Sources};
+
+pub mod backfill;
+pub mod clip;
+pub mod commands;
+pub mod jobs;
+pub mod loudness;
+pub mod player;
+pub mod probe;
+pub mod replay;
+pub mod review;
+pub mod source;
+
+/// Everything we know about a themesong besides the audio itself.
+/// Songs from before we kept track have `None`s until they are backfilled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThemesongMetadata {
+    pub url: Option<String>,
+    pub start_seconds: Option<f64>,
+    pub end_seconds: Option<f64>,
+    pub requested_at: Option<DateTime<Utc>>,
+    pub requested_by: Option<UserID>,
+    pub file_format: Option<String>,
+    pub byte_size: Option<i64>,
+    /// Measured before normalizing, see [`loudness`]
+    pub loudness_lufs: Option<f64>,
+    pub peak_dbfs: Option<f64>,
+}
 
-pub async fn play_themesong_for_today(
+pub async fn get_themesong_metadata(
     conn: &mut SqliteConnection,
     user_id: &UserID,
-    sink: &rodio::Sink,
-) -> Result<()> {
-    if has_played_themesong_today(conn, user_id).await? {
-        return Ok(());
-    }
-
-    if play_themesong(conn, user_id, sink).await? {
-        mark_themesong_played(conn, user_id).await?;
-    }
+) -> Result<Option<ThemesongMetadata>> {
+    let metadata = sqlx::query_as!(
+        ThemesongMetadata,
+        r#"
+        SELECT url, start_seconds, end_seconds,
+               requested_at as "requested_at: DateTime<Utc>",
+               requested_by, file_format, byte_size,
+               loudness_lufs, peak_dbfs
+            FROM USER_THEME_SONGS
+            WHERE user_id = ?1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
 
-    Ok(())
+    Ok(metadata)
 }
 
 pub async fn delete_themesong(conn: &mut SqliteConnection, display_name: &str) -> Result<()> {
     let display_name = display_name.replace("@", "").to_lowercase();
-    let user_id = subd_db::get_user_from_twitch_user_name(conn, display_name.as_str()).await?;
+    if let Some(user_id) =
+        subd_db::get_user_from_twitch_user_name(conn, display_name.as_str()).await?
+    {
+        delete_user_themesong(conn, &user_id).await?;
+    }
+
+    Ok(())
+}
 
-    sqlx::query!("DELETE FROM user_theme_songs WHERE user_id = ?1", user_id)
+/// Returns false if they didn't have a themesong
+pub async fn delete_user_themesong(conn: &mut SqliteConnection, user_id: &UserID) -> Result<bool> {
+    let result = sqlx::query!("DELETE FROM user_theme_songs WHERE user_id = ?1", user_id)
         .execute(&mut *conn)
         .await?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn last_played_themesong(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+) -> Result<Option<DateTime<Utc>>> {
+    let record = sqlx::query!(
+        r#"
+        SELECT max(played_at) as "played_at: DateTime<Utc>"
+            FROM USER_THEME_SONG_HISTORY
+            WHERE user_id = ?1
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(record.played_at)
 }
 
 async fn mark_themesong_played(conn: &mut SqliteConnection, user_id: &UserID) -> Result<()> {
@@ -49,11 +112,13 @@ async fn mark_themesong_played(conn: &mut SqliteConnection, user_id: &UserID) ->
     Ok(())
 }
 
+/// Forget their plays since the replay window started, so it plays again
 pub async fn mark_themesong_unplayed(conn: &mut SqliteConnection, user_id: &UserID) -> Result<()> {
-    // Insert that we've played their theme song
+    let since = replay::window_start(conn, &ReplayConfig::from_env(), Utc::now()).await?;
     sqlx::query!(
-        "DELETE FROM USER_THEME_SONG_HISTORY WHERE user_id = (?1) AND date(played_at) = date(CURRENT_TIMESTAMP)",
-        user_id
+        "DELETE FROM USER_THEME_SONG_HISTORY WHERE user_id = (?1) AND played_at >= datetime(?2)",
+        user_id,
+        since
     )
     .execute(&mut *conn)
     .await?;
@@ -61,17 +126,20 @@ pub async fn mark_themesong_unplayed(conn: &mut SqliteConnection, user_id: &User
     Ok(())
 }
 
-pub async fn has_played_themesong_today(
+/// Whether it already played this session (or however `$SUBD_THEMESONG_REPLAY` says)
+pub async fn has_played_themesong_recently(
     conn: &mut SqliteConnection,
     user_id: &UserID,
 ) -> Result<bool> {
+    let since = replay::window_start(conn, &ReplayConfig::from_env(), Utc::now()).await?;
     let played_count = sqlx::query!(
         r#"
             SELECT count(*) as result
             FROM USER_THEME_SONG_HISTORY
-            WHERE date(played_at) = date('now') AND user_id = ?1;
+            WHERE played_at >= datetime(?2) AND user_id = ?1;
         "#,
-        user_id
+        user_id,
+        since
     )
     .fetch_one(&mut *conn)
     .await?;
@@ -79,75 +147,104 @@ pub async fn has_played_themesong_today(
     Ok(played_count.result > 0)
 }
 
+/// Download a clip as `user_id`'s themesong. `requested_by` is who asked for
+/// it, usually the same user unless a mod is fixing someone's clip.
 pub async fn download_themesong(
     conn: &mut SqliteConnection,
+    sources: &Sources,
+    user_id: &UserID,
+    requested_by: &UserID,
+    url: &str,
+    clip: &ClipRange,
+    max_seconds: f64,
+) -> Result<()> {
+    download_themesong_with_progress(
+        conn,
+        sources,
+        user_id,
+        requested_by,
+        url,
+        clip,
+        max_seconds,
+        &|_| {},
+    )
+    .await
+}
+
+/// `download_themesong`, calling `progress` as each stage starts
+#[allow(clippy::too_many_arguments)]
+pub async fn download_themesong_with_progress(
+    conn: &mut SqliteConnection,
+    sources: &Sources,
     user_id: &UserID,
+    requested_by: &UserID,
     url: &str,
-    start: &str,
-    end: &str,
+    clip: &ClipRange,
+    max_seconds: f64,
+    progress: &(dyn Fn(ThemesongDownloadStage) + Send + Sync),
 ) -> Result<()> {
-    // TODO: Use temp_file and/or temp_dir for this
-    // TODO: Use ytextract to make sure that the video is < 1 hour or something like that
-    // TODO: Also could probably use --max-filesize as well or in place of ytextract
-
-    validate_themesong(url)?;
-    validate_duration(start, end, 10.)?;
-
-    let location = THEMESONG_LOCATION.to_string() + user_id.to_string().as_str();
-
-    println!("youtube_dl: Downloading == {:?}", url);
-    dbg!(youtube_dl::YoutubeDl::new(url)
-        .youtube_dl_path("yt-dlp")
-        .extract_audio(true)
-        .download(true)
-        // .extra_arg("-f bestaudio")
-        .extra_arg("--downloader")
-        .extra_arg("ffmpeg")
-        .extra_arg("--downloader-args")
-        .extra_arg(format!("-ss {} -to {}", start, end))
-        .extra_arg("--audio-format")
-        .extra_arg("mp3")
-        .extra_arg("-o")
-        .extra_arg(location.clone() + ".%(ext)s"))
-    .run()?;
-    println!("  Done!");
-
-    let mut contents = vec![];
-
-    let mut f = File::open(location.clone() + ".mp3").await?;
-    f.read_to_end(&mut contents).await?;
+    let kind = validate_themesong(url)?;
+    clip.check_length(max_seconds)?;
+
+    progress(ThemesongDownloadStage::Checking);
+    let source = sources.get(kind);
+    let info = source.probe(url).await?;
+    probe::check(&info, clip.end)?;
+
+    progress(ThemesongDownloadStage::Downloading);
+    let contents = source.download(url, clip.start, clip.end).await?;
+
+    progress(ThemesongDownloadStage::Normalizing);
+    let config = loudness::NormalizeConfig::from_env();
+    let normalized =
+        tokio::task::spawn_blocking(move || loudness::normalize(&contents, &config)).await??;
+    let byte_size = normalized.song.len() as i64;
+    // Clips shorter than a loudness block measure as -inf, which sqlite can't hold
+    let loudness_lufs = Some(normalized.loudness.integrated_lufs).filter(|lufs| lufs.is_finite());
+    let peak_dbfs = Some(normalized.loudness.peak_dbfs).filter(|peak| peak.is_finite());
+
+    progress(ThemesongDownloadStage::Saving);
+    // Together, so a download cancelled halfway through saving keeps the old one
+    let mut tx = conn.begin().await?;
 
     // Delete the previous theme song
     sqlx::query!("DELETE FROM USER_THEME_SONGS WHERE user_id = ?1", user_id)
-        .execute(&mut *conn)
+        .execute(&mut tx)
         .await?;
 
     // Insert the new theme song
     sqlx::query!(
-        "INSERT INTO USER_THEME_SONGS (user_id, song) VALUES (?1, ?2)",
+        "INSERT INTO USER_THEME_SONGS
+            (user_id, song, url, start_seconds, end_seconds,
+             requested_at, requested_by, file_format, byte_size,
+             loudness_lufs, peak_dbfs)
+            VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP, ?6, ?7, ?8, ?9, ?10)",
         user_id,
-        contents
+        normalized.song,
+        url,
+        clip.start,
+        clip.end,
+        requested_by,
+        normalized.format,
+        byte_size,
+        loudness_lufs,
+        peak_dbfs
     )
-    .execute(&mut *conn)
+    .execute(&mut tx)
     .await?;
 
-    // And now delete the file
-    std::fs::remove_file(location.clone() + ".mp3")?;
+    tx.commit().await?;
 
     Ok(())
 }
 
 pub fn can_user_access_themesong(user_roles: &UserRoles) -> bool {
-    user_roles.is_github_sponsor
-        || user_roles.is_twitch_mod
-        || user_roles.is_twitch_sub
-        || user_roles.is_twitch_vip
-        || user_roles.is_twitch_founder
+    perks::perks_for(user_roles).themesong_max_seconds > 0.
 }
 
 // TODO: We should probably not copy & paste this like this
 pub async fn should_play_themesong(conn: &mut SqliteConnection, user_id: &UserID) -> Result<bool> {
-    if has_played_themesong_today(conn, user_id).await? {
+    if has_played_themesong_recently(conn, user_id).await? {
         return Ok(false);
     }
 
@@ -157,7 +254,7 @@ pub async fn should_play_themesong(conn: &mut SqliteConnection, user_id: &UserID
     }
 
     let themesong = sqlx::query!(
-        "SELECT song FROM user_theme_songs WHERE user_id = ?1",
+        "SELECT song FROM user_theme_songs WHERE user_id = ?1 AND status = 'approved'",
         user_id
     )
     .fetch_optional(&mut *conn)
@@ -169,11 +266,46 @@ pub async fn should_play_themesong(conn: &mut SqliteConnection, user_id: &UserID
     }
 }
 
+/// The audio of `user_id`'s themesong, if a mod approved it
+pub async fn approved_themesong(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+) -> Result<Option<Vec<u8>>> {
+    let themesong = sqlx::query!(
+        "SELECT song FROM user_theme_songs WHERE user_id = ?1 AND status = 'approved'",
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(themesong.map(|themesong| themesong.song))
+}
+
 // Play a themesong. Does not wait for sink to complete playing
 pub async fn play_themesong(
     conn: &mut SqliteConnection,
     user_id: &UserID,
     sink: &rodio::Sink,
+) -> Result<bool> {
+    let song = match approved_themesong(conn, user_id).await? {
+        Some(song) => song,
+        None => {
+            println!("theme_song: No themesong available for: {:?}", user_id);
+            return Ok(false);
+        }
+    };
+
+    let rodioer = rodio::Decoder::new(BufReader::new(Cursor::new(song))).unwrap();
+    sink.append(rodioer);
+
+    Ok(true)
+}
+
+/// Play a themesong whatever its review status, without marking it played
+pub async fn preview_themesong(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+    sink: &rodio::Sink,
 ) -> Result<bool> {
     let themesong = sqlx::query!(
         "SELECT song FROM user_theme_songs WHERE user_id = ?1",
@@ -184,19 +316,33 @@ pub async fn play_themesong(
 
     let themesong = match themesong {
         Some(themesong) => themesong,
-        None => {
-            println!("theme_song: No themesong available for: {:?}", user_id);
-            return Ok(false);
-        }
+        None => return Ok(false),
     };
 
-    let rodioer = rodio::Decoder::new(BufReader::new(Cursor::new(themesong.song))).unwrap();
+    let rodioer = rodio::Decoder::new(BufReader::new(Cursor::new(themesong.song)))?;
     sink.append(rodioer);
 
     Ok(true)
 }
 
-pub fn validate_themesong(themesong_url: &str) -> Result<()> {
+/// Open the output device called `name`, for audio that shouldn't go out on stream
+pub fn open_output_device(name: &str) -> Option<(rodio::OutputStream, rodio::Sink)> {
+    use rodio::cpal::traits::HostTrait;
+    use rodio::DeviceTrait;
+
+    let device = rodio::cpal::default_host()
+        .output_devices()
+        .ok()?
+        .find(|device| device.name().map(|n| n == name).unwrap_or(false))?;
+
+    let (stream, handle) = rodio::OutputStream::try_from_device(&device).ok()?;
+    let sink = rodio::Sink::try_new(&handle).ok()?;
+
+    Some((stream, sink))
+}
+
+/// Check `themesong_url` is somewhere we download from, and how to download it
+pub fn validate_themesong(themesong_url: &str) -> Result<SourceKind> {
     let parsed = Url::parse(themesong_url)?;
 
     let domain = match parsed.domain() {
@@ -208,50 +354,29 @@ pub fn validate_themesong(themesong_url: &str) -> Result<()> {
         .domain(domain.as_bytes())
         .ok_or(anyhow::anyhow!("invalid domain"))?;
 
-    if !(domain == "youtube.com"
-        || domain == "youtu.be"
-        || domain == "twitch.tv"
-        || domain == "twitter.com"
-        || domain == "beginworld.website-us-east-1.linodeobjects.com")
-    {
-        return Err(anyhow::anyhow!(
-            "invalid host. must be youtube.com or clips.twitch.tv"
-        ));
-    }
-
-    // TODO: If ppl are being stinkers, we may have to check the length
-    // and information about the video before allowing the download
-
-    Ok(())
-}
-
-pub fn validate_duration(start: &str, end: &str, maxtime: f64) -> Result<()> {
-    // 01:10, 01:23
-
-    let (start_minutes, start_seconds) = start
-        .split_once(":")
-        .ok_or(anyhow::anyhow!("Must be single : split str"))?;
-
-    let (end_minutes, end_seconds) = end
-        .split_once(":")
-        .ok_or(anyhow::anyhow!("Must be single : split str"))?;
-
-    // TODO: Support ms for ppl
-    let start = start_minutes.parse::<f64>()? * 60.0 + start_seconds.parse::<f64>()?;
-    let end = end_minutes.parse::<f64>()? * 60.0 + end_seconds.parse::<f64>()?;
+    let kind = match domain {
+        "youtube.com" | "youtu.be" | "twitch.tv" | "twitter.com" => SourceKind::YtDlp,
+        "beginworld.website-us-east-1.linodeobjects.com" => SourceKind::Direct,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "invalid host. must be youtube.com or clips.twitch.tv"
+            ))
+        }
+    };
 
-    if end - start <= 0.0 {
-        Err(anyhow::anyhow!("End must be after start"))
-    } else if end - start > maxtime {
-        Err(anyhow::anyhow!("Too long. Choose shorter clip"))
-    } else {
-        Ok(())
-    }
+    Ok(kind)
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use super::probe::{SourceInfo, ThemesongRejection};
+    use super::source::FakeSource;
     use super::*;
+    use crate::test_utils::{chatter, test_conn};
+
+    const TONE: &[u8] = include_bytes!("fixtures/tone.wav");
 
     #[test]
     fn accepts_youtube_dot_com() {
@@ -275,12 +400,105 @@ mod test {
     }
 
     #[test]
-    fn accepts_simple_timestamps() {
-        assert!(validate_duration("00:05", "00:10", 10.0).is_ok());
-        assert!(validate_duration("01:58", "02:05", 10.0).is_ok());
-        assert!(validate_duration("01:58.231", "02:05.09", 10.).is_ok());
-        assert!(validate_duration("01:58", "02:05", 3.).is_err());
-        assert!(validate_duration("00:05", "00:00", 10.).is_err());
-        assert!(validate_duration("00:05", "00:50", 10.).is_err());
+    fn picks_source_by_domain() {
+        assert_eq!(
+            validate_themesong("https://youtu.be/SkypZuY6ZvA").unwrap(),
+            SourceKind::YtDlp
+        );
+        assert_eq!(
+            validate_themesong(
+                "https://beginworld.website-us-east-1.linodeobjects.com/themes/stupac62.mp3"
+            )
+            .unwrap(),
+            SourceKind::Direct
+        );
+        assert!(validate_themesong("https://example.com/theme.mp3").is_err());
+    }
+
+    #[tokio::test]
+    async fn downloads_from_the_picked_source() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let user_id = chatter(&mut conn, "1", "nyxkrage").await;
+
+        let url = "https://www.youtube.com/watch?v=SkypZuY6ZvA";
+        let fake = Arc::new(FakeSource::new().with_fixture(url, TONE));
+        let sources = Sources::all(fake.clone());
+
+        download_themesong(
+            &mut conn,
+            &sources,
+            &user_id,
+            &user_id,
+            url,
+            &ClipRange::new(0., 3.)?,
+            10.,
+        )
+        .await?;
+        assert_eq!(fake.downloads(), vec![url]);
+
+        let metadata = get_themesong_metadata(&mut conn, &user_id).await?.unwrap();
+        assert_eq!(metadata.file_format.as_deref(), Some("wav"));
+        assert!(metadata.loudness_lufs.is_some());
+
+        // The silence around the tone was trimmed: about 2s of 8kHz 16-bit mono
+        assert!((metadata.byte_size.unwrap() - 32_000).abs() < 100);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn probes_before_downloading() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let user_id = chatter(&mut conn, "1", "nyxkrage").await;
+
+        let live = "https://www.twitch.tv/teej_dv";
+        let fake = Arc::new(
+            FakeSource::new()
+                .with_fixture(live, TONE)
+                .with_info(
+                    live,
+                    SourceInfo {
+                        is_live: true,
+                        ..Default::default()
+                    },
+                )
+                .with_fixture("https://youtu.be/short", TONE),
+        );
+        let sources = Sources::all(fake.clone());
+
+        for (url, end, rejection) in [
+            (live, 3., ThemesongRejection::Livestream),
+            (
+                "https://youtu.be/short",
+                9.,
+                ThemesongRejection::PastEnd {
+                    end: 9.,
+                    duration: 3.,
+                },
+            ),
+            (
+                "https://youtu.be/gone",
+                3.,
+                ThemesongRejection::Unavailable("Video unavailable".to_string()),
+            ),
+        ] {
+            let err = download_themesong(
+                &mut conn,
+                &sources,
+                &user_id,
+                &user_id,
+                url,
+                &ClipRange::new(0., end)?,
+                10.,
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.downcast_ref::<ThemesongRejection>(), Some(&rejection));
+        }
+
+        assert!(fake.downloads().is_empty());
+        assert!(get_themesong_metadata(&mut conn, &user_id).await?.is_none());
+
+        Ok(())
     }
 }
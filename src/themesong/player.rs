@@ -0,0 +1,466 @@
+//! Plays themesongs one at a time.
+//!
+//! Everyone who shows up at the start of stream gets queued instead of all
+//! being appended to the sink at once, supporters ahead of everyone else.
+//! The queue only holds a few songs, and a song that waited too long is
+//! dropped, since the welcome makes no sense five minutes later. Dropped
+//! songs aren't marked played, so they'll play the next time that chatter
+//! says something.
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{BufReader, Cursor};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rodio::Source;
+use sqlx::SqliteConnection;
+use subd_types::{ThemesongControl, ThemesongPlay, UserID};
+
+use super::{approved_themesong, has_played_themesong_recently, mark_themesong_played};
+
+/// How often a skipped song checks whether it should stop
+const SKIP_CHECK: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerConfig {
+    /// Songs waiting to play, not counting the one playing
+    pub max_queue: usize,
+    /// How long a song can wait in the queue before it's dropped
+    pub max_wait: Duration,
+    /// Between 0 and 1
+    pub volume: f32,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            max_queue: 5,
+            max_wait: Duration::from_secs(60),
+            volume: 1.,
+        }
+    }
+}
+
+impl PlayerConfig {
+    /// The defaults, overridden by `$SUBD_THEMESONG_MAX_QUEUE`,
+    /// `$SUBD_THEMESONG_MAX_WAIT` (seconds) and `$SUBD_THEMESONG_VOLUME` (0-100)
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(max_queue) = env_var("SUBD_THEMESONG_MAX_QUEUE") {
+            config.max_queue = max_queue;
+        }
+        if let Some(max_wait) = env_var("SUBD_THEMESONG_MAX_WAIT") {
+            config.max_wait = Duration::from_secs(max_wait);
+        }
+        if let Some(volume) = env::var("SUBD_THEMESONG_VOLUME")
+            .ok()
+            .and_then(|volume| parse_volume(&volume))
+        {
+            config.volume = volume;
+        }
+
+        config
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+/// `!volume 50` -> `0.5`. `None` for anything that isn't a number, NaN included.
+pub fn parse_volume(percent: &str) -> Option<f32> {
+    let percent = percent.trim().trim_end_matches('%').parse::<f32>().ok()?;
+    if !percent.is_finite() {
+        return None;
+    }
+
+    Some(percent.clamp(0., 100.) / 100.)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedSong {
+    pub user_id: UserID,
+    pub display_name: String,
+    /// Played with `!themesong preview`, so it doesn't count as today's play
+    pub preview: bool,
+    /// From `perks::Perks::queue_priority`, higher goes first
+    pub priority: u8,
+    pub queued_at: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueued {
+    /// How many songs are ahead of it
+    Queued(usize),
+    AlreadyQueued,
+    Full,
+}
+
+#[derive(Debug)]
+pub struct ThemesongQueue {
+    songs: VecDeque<QueuedSong>,
+    max_len: usize,
+    max_wait: Duration,
+}
+
+impl ThemesongQueue {
+    pub fn new(max_len: usize, max_wait: Duration) -> Self {
+        Self {
+            songs: VecDeque::new(),
+            max_len,
+            max_wait,
+        }
+    }
+
+    pub fn push(&mut self, song: QueuedSong) -> Enqueued {
+        if self
+            .songs
+            .iter()
+            .any(|queued| queued.user_id == song.user_id)
+        {
+            return Enqueued::AlreadyQueued;
+        }
+
+        if self.songs.len() >= self.max_len {
+            return Enqueued::Full;
+        }
+
+        // Behind everyone with the same priority or higher
+        let position = self
+            .songs
+            .iter()
+            .position(|queued| queued.priority < song.priority)
+            .unwrap_or(self.songs.len());
+        self.songs.insert(position, song);
+        Enqueued::Queued(position)
+    }
+
+    /// The next song that hasn't waited too long, and the ones that did
+    pub fn pop(&mut self, now: Instant) -> (Option<QueuedSong>, Vec<QueuedSong>) {
+        let mut expired = vec![];
+        while let Some(song) = self.songs.pop_front() {
+            if now.duration_since(song.queued_at) > self.max_wait {
+                expired.push(song);
+            } else {
+                return (Some(song), expired);
+            }
+        }
+
+        (None, expired)
+    }
+
+    pub fn clear(&mut self) -> Vec<QueuedSong> {
+        self.songs.drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Playing {
+    song: QueuedSong,
+    skip: Arc<AtomicBool>,
+}
+
+/// Owns what goes into the themesong sink. Call `tick` often, it starts the
+/// next song once the sink is done with the last one.
+#[derive(Debug)]
+pub struct ThemesongPlayer {
+    queue: ThemesongQueue,
+    playing: Option<Playing>,
+}
+
+impl ThemesongPlayer {
+    pub fn new(config: &PlayerConfig) -> Self {
+        Self {
+            queue: ThemesongQueue::new(config.max_queue, config.max_wait),
+            playing: None,
+        }
+    }
+
+    pub fn request(
+        &mut self,
+        user_id: UserID,
+        display_name: String,
+        preview: bool,
+        priority: u8,
+    ) -> Enqueued {
+        if matches!(&self.playing, Some(playing) if playing.song.user_id == user_id) {
+            return Enqueued::AlreadyQueued;
+        }
+
+        self.queue.push(QueuedSong {
+            user_id,
+            display_name,
+            preview,
+            priority,
+            queued_at: Instant::now(),
+        })
+    }
+
+    pub fn control(&mut self, sink: &rodio::Sink, control: &ThemesongControl) {
+        match control {
+            ThemesongControl::Skip => self.skip(),
+            ThemesongControl::Stop => {
+                for song in self.queue.clear() {
+                    println!("themesong: {} won't play, stopped", song.display_name);
+                }
+                self.skip();
+            }
+            ThemesongControl::Volume(volume) => sink.set_volume(*volume),
+        }
+    }
+
+    fn skip(&mut self) {
+        if let Some(playing) = &self.playing {
+            playing.skip.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Who is playing right now
+    pub fn playing(&self) -> Option<&QueuedSong> {
+        self.playing.as_ref().map(|playing| &playing.song)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Finish the current song if the sink is done with it, then start the next one
+    pub async fn tick(
+        &mut self,
+        conn: &mut SqliteConnection,
+        sink: &rodio::Sink,
+        now: Instant,
+    ) -> Result<Vec<ThemesongPlay>> {
+        let mut events = vec![];
+
+        if !sink.empty() {
+            return Ok(events);
+        }
+
+        if let Some(Playing { song, skip }) = self.playing.take() {
+            events.push(ThemesongPlay::Finish {
+                user_id: song.user_id,
+                display_name: song.display_name,
+                skipped: skip.load(Ordering::SeqCst),
+            });
+        }
+
+        loop {
+            let (song, expired) = self.queue.pop(now);
+            for song in expired {
+                println!("themesong: {} waited too long, dropping", song.display_name);
+            }
+
+            let song = match song {
+                Some(song) => song,
+                None => break,
+            };
+
+            if let Some(skip) = self.start(conn, sink, &song).await? {
+                events.push(ThemesongPlay::Start {
+                    user_id: song.user_id,
+                    display_name: song.display_name.clone(),
+                });
+                self.playing = Some(Playing { song, skip });
+                break;
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Append `song` to the sink, or `None` if it shouldn't play after all
+    async fn start(
+        &self,
+        conn: &mut SqliteConnection,
+        sink: &rodio::Sink,
+        song: &QueuedSong,
+    ) -> Result<Option<Arc<AtomicBool>>> {
+        if !song.preview && has_played_themesong_recently(conn, &song.user_id).await? {
+            return Ok(None);
+        }
+
+        // A mod could have deleted or rejected it while it was queued
+        let audio = match approved_themesong(conn, &song.user_id).await? {
+            Some(audio) => audio,
+            None => return Ok(None),
+        };
+
+        let decoder = match rodio::Decoder::new(BufReader::new(Cursor::new(audio))) {
+            Ok(decoder) => decoder,
+            Err(err) => {
+                println!("themesong: can't play {}'s: {:?}", song.display_name, err);
+                return Ok(None);
+            }
+        };
+
+        let skip = Arc::new(AtomicBool::new(false));
+        let should_skip = skip.clone();
+        sink.append(
+            decoder
+                .stoppable()
+                .periodic_access(SKIP_CHECK, move |source| {
+                    if should_skip.load(Ordering::SeqCst) {
+                        source.stop();
+                    }
+                }),
+        );
+
+        if !song.preview {
+            mark_themesong_played(conn, &song.user_id).await?;
+        }
+
+        Ok(Some(skip))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+
+    const TONE: &[u8] = include_bytes!("fixtures/tone.wav");
+
+    fn song(user_id: UserID, queued_at: Instant) -> QueuedSong {
+        QueuedSong {
+            user_id,
+            display_name: format!("user{}", user_id),
+            preview: false,
+            priority: 0,
+            queued_at,
+        }
+    }
+
+    async fn approved_song(conn: &mut SqliteConnection, user_id: &UserID) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO USER_THEME_SONGS (user_id, song, status) VALUES (?1, ?2, 'approved')",
+            user_id,
+            TONE
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn queue_is_bounded_and_deduplicated() {
+        let now = Instant::now();
+        let mut queue = ThemesongQueue::new(2, Duration::from_secs(60));
+
+        assert_eq!(queue.push(song(1, now)), Enqueued::Queued(0));
+        assert_eq!(queue.push(song(1, now)), Enqueued::AlreadyQueued);
+        assert_eq!(queue.push(song(2, now)), Enqueued::Queued(1));
+        assert_eq!(queue.push(song(3, now)), Enqueued::Full);
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn supporters_go_first() {
+        let now = Instant::now();
+        let mut queue = ThemesongQueue::new(5, Duration::from_secs(60));
+        let supporter = |user_id, priority| QueuedSong {
+            priority,
+            ..song(user_id, now)
+        };
+
+        assert_eq!(queue.push(song(1, now)), Enqueued::Queued(0));
+        assert_eq!(queue.push(supporter(2, 1)), Enqueued::Queued(0));
+        assert_eq!(queue.push(supporter(3, 3)), Enqueued::Queued(0));
+        assert_eq!(queue.push(supporter(4, 1)), Enqueued::Queued(2));
+
+        let order = std::iter::from_fn(|| queue.pop(now).0)
+            .map(|song| song.user_id)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![3, 2, 4, 1]);
+    }
+
+    #[test]
+    fn drops_songs_that_waited_too_long() {
+        let start = Instant::now();
+        let mut queue = ThemesongQueue::new(5, Duration::from_secs(60));
+        queue.push(song(1, start));
+        queue.push(song(2, start + Duration::from_secs(30)));
+
+        let (next, expired) = queue.pop(start + Duration::from_secs(80));
+        assert_eq!(next.map(|song| song.user_id), Some(2));
+        assert_eq!(expired, vec![song(1, start)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn volume_is_a_percentage() {
+        assert_eq!(parse_volume("50"), Some(0.5));
+        assert_eq!(parse_volume("50%"), Some(0.5));
+        assert_eq!(parse_volume("250"), Some(1.));
+        assert_eq!(parse_volume("-3"), Some(0.));
+        assert_eq!(parse_volume("NaN"), None);
+        assert_eq!(parse_volume("inf"), None);
+        assert_eq!(parse_volume("loud"), None);
+    }
+
+    #[tokio::test]
+    async fn plays_one_song_at_a_time_and_skips() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let prime = chatter(&mut conn, "2", "theprimeagen").await;
+        approved_song(&mut conn, &nyx).await?;
+        approved_song(&mut conn, &prime).await?;
+
+        let (sink, mut output) = rodio::Sink::new_idle();
+        let mut player = ThemesongPlayer::new(&PlayerConfig::default());
+        player.request(nyx, "nyxkrage".to_string(), false, 0);
+        player.request(prime, "theprimeagen".to_string(), false, 0);
+
+        let events = player.tick(&mut conn, &sink, Instant::now()).await?;
+        assert_eq!(
+            events,
+            vec![ThemesongPlay::Start {
+                user_id: nyx,
+                display_name: "nyxkrage".to_string()
+            }]
+        );
+        assert!(has_played_themesong_recently(&mut conn, &nyx).await?);
+        assert_eq!(player.queued(), 1);
+
+        // Still playing, so nothing changes
+        assert!(player
+            .tick(&mut conn, &sink, Instant::now())
+            .await?
+            .is_empty());
+
+        player.control(&sink, &ThemesongControl::Skip);
+        // Pull audio through the sink until the skip is noticed
+        output.by_ref().take(8000).for_each(drop);
+
+        let events = player.tick(&mut conn, &sink, Instant::now()).await?;
+        assert_eq!(
+            events,
+            vec![
+                ThemesongPlay::Finish {
+                    user_id: nyx,
+                    display_name: "nyxkrage".to_string(),
+                    skipped: true
+                },
+                ThemesongPlay::Start {
+                    user_id: prime,
+                    display_name: "theprimeagen".to_string()
+                }
+            ]
+        );
+
+        Ok(())
+    }
+}
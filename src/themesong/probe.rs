@@ -0,0 +1,268 @@
+//! Look at a source before downloading it, so we don't spend ten minutes
+//! pulling down a livestream just to cut seven seconds out of it.
+
+use anyhow::Result;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::commands::format_timestamp;
+
+/// Longest video we'll cut a themesong out of
+pub const MAX_SOURCE_SECONDS: f64 = 60. * 60.;
+/// Biggest audio file we'll download
+pub const MAX_FILESIZE_BYTES: u64 = 100 * 1024 * 1024;
+
+/// What a source says about itself. Anything it doesn't say is `None` and isn't checked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceInfo {
+    pub title: Option<String>,
+    pub duration: Option<f64>,
+    pub filesize: Option<u64>,
+    pub is_live: bool,
+    pub age_restricted: bool,
+}
+
+/// Why a themesong can't come from a source. The messages are said in chat as is.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ThemesongRejection {
+    #[error("livestreams can't be themesongs, use a VOD or a clip instead")]
+    Livestream,
+
+    #[error(
+        "that video is {} long, themesongs have to come from something under {}",
+        format_timestamp(*seconds),
+        format_timestamp(MAX_SOURCE_SECONDS)
+    )]
+    TooLong { seconds: f64 },
+
+    /// `bytes` is as far as we got when the size wasn't known up front
+    #[error("that file is over the {}MB limit", MAX_FILESIZE_BYTES / 1024 / 1024)]
+    TooLarge { bytes: u64 },
+
+    #[error("that video is age-restricted")]
+    AgeRestricted,
+
+    #[error("that video is unavailable: {0}")]
+    Unavailable(String),
+
+    #[error(
+        "the clip ends at {} but the video is only {} long",
+        format_timestamp(*end),
+        format_timestamp(*duration)
+    )]
+    PastEnd { end: f64, duration: f64 },
+}
+
+/// Make sure a clip ending at `end` seconds can come from `info`
+pub fn check(info: &SourceInfo, end: f64) -> Result<(), ThemesongRejection> {
+    if info.is_live {
+        return Err(ThemesongRejection::Livestream);
+    }
+
+    if info.age_restricted {
+        return Err(ThemesongRejection::AgeRestricted);
+    }
+
+    if let Some(bytes) = info.filesize.filter(|bytes| *bytes > MAX_FILESIZE_BYTES) {
+        return Err(ThemesongRejection::TooLarge { bytes });
+    }
+
+    if let Some(duration) = info.duration {
+        if duration > MAX_SOURCE_SECONDS {
+            return Err(ThemesongRejection::TooLong { seconds: duration });
+        }
+
+        if end > duration {
+            return Err(ThemesongRejection::PastEnd { end, duration });
+        }
+    }
+
+    Ok(())
+}
+
+/// The parts of `yt-dlp --dump-single-json` we care about
+#[derive(Debug, Deserialize)]
+struct YtDlpInfo {
+    title: Option<String>,
+    duration: Option<f64>,
+    filesize: Option<u64>,
+    filesize_approx: Option<u64>,
+    is_live: Option<bool>,
+    live_status: Option<String>,
+    age_limit: Option<i64>,
+}
+
+pub fn parse_yt_dlp_info(json: &[u8]) -> Result<SourceInfo> {
+    let info: YtDlpInfo = serde_json::from_slice(json)?;
+
+    Ok(SourceInfo {
+        title: info.title,
+        duration: info.duration,
+        filesize: info.filesize.or(info.filesize_approx),
+        // Premieres that haven't started yet download like livestreams
+        is_live: info.is_live.unwrap_or(false)
+            || matches!(info.live_status.as_deref(), Some("is_live" | "is_upcoming")),
+        age_restricted: info.age_limit.unwrap_or(0) >= 18,
+    })
+}
+
+/// What yt-dlp says about videos that are gone for good, lowercase
+const UNAVAILABLE_ERRORS: &[&str] = &[
+    "video unavailable",
+    "private video",
+    "video is private",
+    "has been removed",
+    "no longer available",
+    "does not exist",
+    "account associated with this video has been terminated",
+    "unsupported url",
+    "http error 404",
+    "http error 410",
+];
+
+/// yt-dlp exits with an error instead of metadata for videos it can't see.
+/// `None` when it failed for some other reason, like the network or yt-dlp itself.
+pub fn rejection_from_stderr(stderr: &str) -> Option<ThemesongRejection> {
+    let lower = stderr.to_lowercase();
+    if lower.contains("confirm your age") || lower.contains("age-restricted") {
+        return Some(ThemesongRejection::AgeRestricted);
+    }
+
+    if !UNAVAILABLE_ERRORS.iter().any(|error| lower.contains(error)) {
+        return None;
+    }
+
+    let reason = stderr
+        .lines()
+        .rev()
+        .find_map(|line| line.strip_prefix("ERROR: "))
+        .unwrap_or("yt-dlp couldn't find it");
+
+    // "[youtube] SkypZuY6ZvA: Video unavailable" -> "Video unavailable"
+    let reason = reason.rsplit(": ").next().unwrap_or(reason);
+    Some(ThemesongRejection::Unavailable(reason.trim().to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn video(duration: f64) -> SourceInfo {
+        SourceInfo {
+            title: Some("a video".to_string()),
+            duration: Some(duration),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checks_limits() {
+        assert_eq!(check(&video(120.), 65.), Ok(()));
+        assert_eq!(check(&SourceInfo::default(), 65.), Ok(()));
+
+        assert_eq!(
+            check(&video(60.), 65.),
+            Err(ThemesongRejection::PastEnd {
+                end: 65.,
+                duration: 60.
+            })
+        );
+        assert_eq!(
+            check(&video(2. * 60. * 60.), 65.),
+            Err(ThemesongRejection::TooLong {
+                seconds: 2. * 60. * 60.
+            })
+        );
+
+        let live = SourceInfo {
+            is_live: true,
+            ..Default::default()
+        };
+        assert_eq!(check(&live, 65.), Err(ThemesongRejection::Livestream));
+
+        let huge = SourceInfo {
+            filesize: Some(MAX_FILESIZE_BYTES + 1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            check(&huge, 65.),
+            Err(ThemesongRejection::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn explains_rejections() {
+        assert_eq!(
+            ThemesongRejection::PastEnd {
+                end: 65.,
+                duration: 60.
+            }
+            .to_string(),
+            "the clip ends at 01:05 but the video is only 01:00 long"
+        );
+    }
+
+    #[test]
+    fn parses_yt_dlp_info() -> Result<()> {
+        let info = parse_yt_dlp_info(
+            br#"{
+                "id": "SkypZuY6ZvA",
+                "title": "a video",
+                "duration": 212,
+                "filesize": null,
+                "filesize_approx": 3418213,
+                "is_live": false,
+                "live_status": "not_live",
+                "age_limit": 0
+            }"#,
+        )?;
+        assert_eq!(
+            info,
+            SourceInfo {
+                title: Some("a video".to_string()),
+                duration: Some(212.),
+                filesize: Some(3418213),
+                is_live: false,
+                age_restricted: false,
+            }
+        );
+
+        let premiere = parse_yt_dlp_info(br#"{ "live_status": "is_upcoming", "age_limit": 18 }"#)?;
+        assert!(premiere.is_live);
+        assert!(premiere.age_restricted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_yt_dlp_errors() {
+        assert_eq!(
+            rejection_from_stderr(
+                "ERROR: [youtube] SkypZuY6ZvA: Sign in to confirm your age. This video may be inappropriate for some users."
+            ),
+            Some(ThemesongRejection::AgeRestricted)
+        );
+        assert_eq!(
+            rejection_from_stderr("ERROR: [youtube] SkypZuY6ZvA: Video unavailable\n"),
+            Some(ThemesongRejection::Unavailable(
+                "Video unavailable".to_string()
+            ))
+        );
+        assert_eq!(
+            rejection_from_stderr("ERROR: [youtube] SkypZuY6ZvA: Private video. Sign in if you've been granted access to this video"),
+            Some(ThemesongRejection::Unavailable(
+                "Private video. Sign in if you've been granted access to this video".to_string()
+            ))
+        );
+
+        // Trying again later could work for these, so they aren't the video's fault
+        assert_eq!(
+            rejection_from_stderr("ERROR: [youtube] SkypZuY6ZvA: Unable to download API page: HTTP Error 429: Too Many Requests"),
+            None
+        );
+        assert_eq!(
+            rejection_from_stderr("ERROR: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution>"),
+            None
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! When a themesong gets to play again.
+//!
+//! By default it's once per stream session. Without a session (say the bot was
+//! restarted mid-stream and never saw us go live) it falls back to once per day,
+//! where the day is the streamer's day rather than UTC's.
+
+use std::env;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::SqliteConnection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayRule {
+    /// Once per stream session
+    Session,
+    /// Once every N hours
+    Hours(i64),
+    /// Once per calendar day in the streamer's timezone
+    Day,
+}
+
+impl ReplayRule {
+    /// `session`, `day`, or a number of hours like `12` or `12h`
+    pub fn parse(rule: &str) -> Option<Self> {
+        match rule.trim().to_lowercase().as_str() {
+            "session" => Some(ReplayRule::Session),
+            "day" => Some(ReplayRule::Day),
+            hours => hours
+                .trim_end_matches('h')
+                .parse()
+                .ok()
+                .filter(|hours| *hours > 0)
+                .map(ReplayRule::Hours),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayConfig {
+    pub rule: ReplayRule,
+    pub timezone: Tz,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            rule: ReplayRule::Session,
+            timezone: Tz::UTC,
+        }
+    }
+}
+
+impl ReplayConfig {
+    /// `$SUBD_THEMESONG_REPLAY` (see `ReplayRule::parse`) and `$SUBD_TIMEZONE`,
+    /// an IANA name like `America/New_York`
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(rule) = env::var("SUBD_THEMESONG_REPLAY")
+            .ok()
+            .and_then(|rule| ReplayRule::parse(&rule))
+        {
+            config.rule = rule;
+        }
+        if let Some(timezone) = env::var("SUBD_TIMEZONE")
+            .ok()
+            .and_then(|timezone| timezone.parse().ok())
+        {
+            config.timezone = timezone;
+        }
+
+        config
+    }
+}
+
+/// Midnight of `now`'s day in `timezone`
+pub fn start_of_day(timezone: Tz, now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.with_timezone(&timezone).naive_local().date();
+    let midnight = today.and_hms_opt(0, 0, 0).expect("midnight exists");
+
+    // Days that start with a DST jump have no 00:00, so use the first moment that exists
+    timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or_else(|| timezone.from_utc_datetime(&midnight))
+        .with_timezone(&Utc)
+}
+
+/// Plays since this moment count against playing again
+pub async fn window_start(
+    conn: &mut SqliteConnection,
+    config: &ReplayConfig,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>> {
+    Ok(match config.rule {
+        ReplayRule::Hours(hours) => now - Duration::hours(hours),
+        ReplayRule::Day => start_of_day(config.timezone, now),
+        ReplayRule::Session => match subd_db::get_current_stream_session(conn).await? {
+            Some(session) => session.started_at,
+            None => start_of_day(config.timezone, now),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_conn;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(ReplayRule::parse("session"), Some(ReplayRule::Session));
+        assert_eq!(ReplayRule::parse("Day"), Some(ReplayRule::Day));
+        assert_eq!(ReplayRule::parse("12h"), Some(ReplayRule::Hours(12)));
+        assert_eq!(ReplayRule::parse("6"), Some(ReplayRule::Hours(6)));
+        assert_eq!(ReplayRule::parse("0h"), None);
+        assert_eq!(ReplayRule::parse("weekly"), None);
+    }
+
+    #[test]
+    fn days_start_in_the_streamers_timezone() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+
+        // 02:00 UTC is still the previous evening in New York
+        assert_eq!(
+            start_of_day(new_york, at("2022-07-14T02:00:00Z")),
+            at("2022-07-13T04:00:00Z")
+        );
+        assert_eq!(
+            start_of_day(Tz::UTC, at("2022-07-14T02:00:00Z")),
+            at("2022-07-14T00:00:00Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_cross_midnight() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let config = ReplayConfig::default();
+
+        let started_at = at("2022-07-13T22:00:00Z");
+        subd_db::start_stream_session(&mut conn, started_at).await?;
+        assert_eq!(
+            window_start(&mut conn, &config, at("2022-07-14T01:00:00Z")).await?,
+            started_at
+        );
+
+        subd_db::end_stream_session(&mut conn, at("2022-07-14T02:00:00Z")).await?;
+        assert_eq!(
+            window_start(&mut conn, &config, at("2022-07-14T03:00:00Z")).await?,
+            at("2022-07-14T00:00:00Z")
+        );
+
+        let hourly = ReplayConfig {
+            rule: ReplayRule::Hours(4),
+            ..config
+        };
+        assert_eq!(
+            window_start(&mut conn, &hourly, at("2022-07-14T03:00:00Z")).await?,
+            at("2022-07-13T23:00:00Z")
+        );
+
+        Ok(())
+    }
+}
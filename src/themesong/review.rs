@@ -0,0 +1,266 @@
+//! Mods approve or reject new themesongs before they can play on stream
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use sqlx::SqliteConnection;
+use subd_types::UserID;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemesongStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ThemesongStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThemesongStatus::Pending => "pending",
+            ThemesongStatus::Approved => "approved",
+            ThemesongStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromStr for ThemesongStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(ThemesongStatus::Pending),
+            "approved" => Ok(ThemesongStatus::Approved),
+            "rejected" => Ok(ThemesongStatus::Rejected),
+            _ => Err(anyhow!("unknown themesong status: {:?}", status)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingThemesong {
+    pub user_id: UserID,
+    pub login: String,
+    pub url: Option<String>,
+    pub start_seconds: Option<f64>,
+    pub end_seconds: Option<f64>,
+}
+
+impl PendingThemesong {
+    /// Link to the clip, so mods can check it without it playing on stream
+    pub fn source_link(&self) -> Option<String> {
+        let url = self.url.as_ref()?;
+        let start = self.start_seconds.unwrap_or(0.).floor() as i64;
+        let separator = if url.contains('?') { '&' } else { '?' };
+
+        Some(
+            match url.contains("youtube.com") || url.contains("youtu.be") {
+                true => format!("{}{}t={}", url, separator, start),
+                false => url.clone(),
+            },
+        )
+    }
+}
+
+/// What `!themesong pending` says
+pub fn pending_summary(pending: &[PendingThemesong]) -> String {
+    if pending.is_empty() {
+        return "No themesongs waiting for review".to_string();
+    }
+
+    let logins = pending
+        .iter()
+        .map(|song| song.login.as_str())
+        .collect::<Vec<_>>();
+    format!("Waiting for review: {}", logins.join(", "))
+}
+
+pub async fn get_themesong_status(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+) -> Result<Option<ThemesongStatus>> {
+    let record = sqlx::query!(
+        "SELECT status FROM USER_THEME_SONGS WHERE user_id = ?1",
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    record.map(|record| record.status.parse()).transpose()
+}
+
+/// Oldest request first
+pub async fn get_pending_themesongs(conn: &mut SqliteConnection) -> Result<Vec<PendingThemesong>> {
+    let pending = sqlx::query_as!(
+        PendingThemesong,
+        r#"
+        SELECT USER_THEME_SONGS.user_id as "user_id!: i64", twitch_users.login,
+               url, start_seconds, end_seconds
+            FROM USER_THEME_SONGS
+                JOIN users ON users.id = USER_THEME_SONGS.user_id
+                JOIN twitch_users ON twitch_users.id = users.twitch_id
+            WHERE status = 'pending'
+            ORDER BY requested_at, USER_THEME_SONGS.user_id
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(pending)
+}
+
+/// Returns false when there was no pending themesong to review
+async fn review_themesong(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+    reviewer: &UserID,
+    status: ThemesongStatus,
+    reason: Option<&str>,
+) -> Result<bool> {
+    let status = status.as_str();
+    let result = sqlx::query!(
+        "UPDATE USER_THEME_SONGS
+            SET status = ?3, reviewed_by = ?2, reviewed_at = CURRENT_TIMESTAMP, reject_reason = ?4
+            WHERE user_id = ?1 AND status = 'pending'",
+        user_id,
+        reviewer,
+        status,
+        reason
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn approve_themesong(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+    reviewer: &UserID,
+) -> Result<bool> {
+    review_themesong(conn, user_id, reviewer, ThemesongStatus::Approved, None).await
+}
+
+pub async fn reject_themesong(
+    conn: &mut SqliteConnection,
+    user_id: &UserID,
+    reviewer: &UserID,
+    reason: Option<&str>,
+) -> Result<bool> {
+    review_themesong(conn, user_id, reviewer, ThemesongStatus::Rejected, reason).await
+}
+
+/// Handle `!themesong approve @user` and `!themesong reject @user [reason]`
+pub async fn review_command(
+    conn: &mut SqliteConnection,
+    reviewer: &UserID,
+    args: &[String],
+) -> Result<String> {
+    let (action, target) = match args {
+        [action, target, ..] => (action.as_str(), target.replace("@", "")),
+        _ => return Ok("!themesong approve @user or !themesong reject @user [reason]".to_string()),
+    };
+
+    let user_id = match subd_db::get_user_from_twitch_user_name(conn, &target).await? {
+        Some(user_id) => user_id,
+        None => return Ok(format!("Don't know who {} is", target)),
+    };
+
+    let reason = args[2..].join(" ");
+    let reviewed = match action {
+        "approve" => approve_themesong(conn, &user_id, reviewer).await?,
+        _ if reason.is_empty() => reject_themesong(conn, &user_id, reviewer, None).await?,
+        _ => reject_themesong(conn, &user_id, reviewer, Some(&reason)).await?,
+    };
+
+    Ok(match (reviewed, action) {
+        (false, _) => format!("{} doesn't have a themesong waiting for review", target),
+        (true, "approve") => format!("@{}: your themesong was approved!", target),
+        (true, _) if reason.is_empty() => format!("@{}: your themesong was rejected", target),
+        (true, _) => format!("@{}: your themesong was rejected: {}", target, reason),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+    use crate::themesong::should_play_themesong;
+
+    async fn pending_song(conn: &mut SqliteConnection, user_id: &UserID) -> Result<()> {
+        let song = vec![0u8; 16];
+        sqlx::query!(
+            "INSERT INTO USER_THEME_SONGS (user_id, song, url, start_seconds, end_seconds, requested_at)
+                VALUES (?1, ?2, 'https://youtu.be/SkypZuY6ZvA', 65, 70, CURRENT_TIMESTAMP)",
+            user_id,
+            song
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    fn args(msg: &str) -> Vec<String> {
+        msg.split(' ').map(|s| s.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn only_approved_songs_play() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let teej = chatter(&mut conn, "2", "teej_dv").await;
+        subd_db::set_user_roles(
+            &mut conn,
+            &nyx,
+            subd_types::UserRoles {
+                is_twitch_sub: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+        pending_song(&mut conn, &nyx).await?;
+
+        assert!(!should_play_themesong(&mut conn, &nyx).await?);
+        let pending = get_pending_themesongs(&mut conn).await?;
+        assert_eq!(pending[0].login, "nyxkrage");
+        assert_eq!(
+            pending[0].source_link().as_deref(),
+            Some("https://youtu.be/SkypZuY6ZvA?t=65")
+        );
+
+        let reply = review_command(&mut conn, &teej, &args("approve @nyxkrage")).await?;
+        assert_eq!(reply, "@nyxkrage: your themesong was approved!");
+        assert_eq!(
+            get_themesong_status(&mut conn, &nyx).await?,
+            Some(ThemesongStatus::Approved)
+        );
+        assert!(should_play_themesong(&mut conn, &nyx).await?);
+        assert!(get_pending_themesongs(&mut conn).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_with_a_reason() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let nyx = chatter(&mut conn, "1", "nyxkrage").await;
+        let teej = chatter(&mut conn, "2", "teej_dv").await;
+        pending_song(&mut conn, &nyx).await?;
+
+        let reply = review_command(&mut conn, &teej, &args("reject nyxkrage too loud")).await?;
+        assert_eq!(reply, "@nyxkrage: your themesong was rejected: too loud");
+        assert_eq!(
+            get_themesong_status(&mut conn, &nyx).await?,
+            Some(ThemesongStatus::Rejected)
+        );
+
+        // Already reviewed
+        let reply = review_command(&mut conn, &teej, &args("approve nyxkrage")).await?;
+        assert_eq!(
+            reply,
+            "nyxkrage doesn't have a themesong waiting for review"
+        );
+
+        Ok(())
+    }
+}
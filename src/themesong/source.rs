@@ -0,0 +1,388 @@
+//! Where themesong audio comes from.
+//!
+//! Video sites go through yt-dlp, links straight to an audio file are fetched
+//! directly, and tests use `FakeSource` so nothing touches the network.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use thiserror::Error;
+
+use super::loudness;
+use super::probe::{self, SourceInfo, ThemesongRejection, MAX_FILESIZE_BYTES};
+
+/// What yt-dlp converts everything to
+const YT_DLP_FORMAT: &str = "mp3";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// youtube, twitch clips, twitter, ...
+    YtDlp,
+    /// A link to an audio file
+    Direct,
+}
+
+/// A source failed in a way that could go away on its own: the network,
+/// rate limits, a server having a bad day
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{0}")]
+pub struct TransientError(pub String);
+
+#[async_trait]
+pub trait ThemesongSource: Send + Sync {
+    /// What `url` is, without downloading it. Fails with a `ThemesongRejection`
+    /// when the source says it can't be used at all.
+    async fn probe(&self, url: &str) -> Result<SourceInfo>;
+
+    /// The audio between `start` and `end` (in seconds) of `url`, in any format rodio can decode
+    async fn download(&self, url: &str, start: f64, end: f64) -> Result<Vec<u8>>;
+}
+
+/// One source for each `SourceKind`
+#[derive(Clone)]
+pub struct Sources {
+    pub yt_dlp: Arc<dyn ThemesongSource>,
+    pub direct: Arc<dyn ThemesongSource>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self {
+            yt_dlp: Arc::new(YtDlpSource),
+            direct: Arc::new(DirectSource::new()),
+        }
+    }
+
+    /// Use `source` for everything, mostly for `FakeSource` in tests
+    pub fn all(source: Arc<dyn ThemesongSource>) -> Self {
+        Self {
+            yt_dlp: source.clone(),
+            direct: source,
+        }
+    }
+
+    pub fn get(&self, kind: SourceKind) -> &dyn ThemesongSource {
+        match kind {
+            SourceKind::YtDlp => self.yt_dlp.as_ref(),
+            SourceKind::Direct => self.direct.as_ref(),
+        }
+    }
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cut `start..end` out of a whole audio file
+fn clip(bytes: &[u8], start: f64, end: f64) -> Result<Vec<u8>> {
+    let mut audio = loudness::decode(bytes)?;
+
+    let channels = audio.channels as usize;
+    let frame_at = |seconds: f64| (seconds * audio.sample_rate as f64) as usize * channels;
+    let end = frame_at(end).min(audio.samples.len());
+    let start = frame_at(start).min(end);
+
+    audio.samples = audio.samples[start..end].to_vec();
+    loudness::encode_wav(&audio)
+}
+
+pub struct YtDlpSource;
+
+#[async_trait]
+impl ThemesongSource for YtDlpSource {
+    async fn probe(&self, url: &str) -> Result<SourceInfo> {
+        let url = url.to_string();
+
+        tokio::task::spawn_blocking(move || -> Result<SourceInfo> {
+            let output = std::process::Command::new("yt-dlp")
+                .args(["--dump-single-json", "--no-playlist", "--skip-download"])
+                // So the filesize is for what we'd actually download
+                .args(["--format", "bestaudio/best"])
+                .arg(&url)
+                .output()?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(match probe::rejection_from_stderr(&stderr) {
+                    Some(rejection) => rejection.into(),
+                    None => TransientError(format!("yt-dlp failed: {}", stderr.trim())).into(),
+                });
+            }
+
+            probe::parse_yt_dlp_info(&output.stdout)
+        })
+        .await?
+    }
+
+    async fn download(&self, url: &str, start: f64, end: f64) -> Result<Vec<u8>> {
+        let url = url.to_string();
+
+        // yt-dlp blocks until ffmpeg is done, so keep it off the runtime. Every
+        // download gets its own directory so two requests can't clobber each other.
+        tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let dir = tempfile::tempdir()?;
+            let output = dir.path().join("themesong");
+
+            println!("youtube_dl: Downloading == {:?}", url);
+            youtube_dl::YoutubeDl::new(&url)
+                .youtube_dl_path("yt-dlp")
+                .extract_audio(true)
+                .download(true)
+                .extra_arg("--downloader")
+                .extra_arg("ffmpeg")
+                .extra_arg("--downloader-args")
+                .extra_arg(format!("-ss {} -to {}", start, end))
+                .extra_arg("--audio-format")
+                .extra_arg(YT_DLP_FORMAT)
+                .extra_arg("-o")
+                .extra_arg(format!("{}.%(ext)s", output.display()))
+                .run()?;
+            println!("  Done!");
+
+            Ok(std::fs::read(output.with_extension(YT_DLP_FORMAT))?)
+        })
+        .await?
+    }
+}
+
+pub struct DirectSource {
+    client: reqwest::Client,
+    /// Downloads are cut off past this, even when the server didn't say how big they are
+    max_filesize: u64,
+}
+
+impl DirectSource {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            max_filesize: MAX_FILESIZE_BYTES,
+        }
+    }
+}
+
+impl Default for DirectSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ThemesongSource for DirectSource {
+    async fn probe(&self, url: &str) -> Result<SourceInfo> {
+        let response = self.client.head(url).send().await?;
+        let status = response.status();
+        if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(ThemesongRejection::Unavailable(status.to_string()).into());
+        }
+        if !status.is_success() {
+            return Err(TransientError(format!("{} answered {}", url, status)).into());
+        }
+
+        // We only find out how long it is once it's decoded
+        Ok(SourceInfo {
+            filesize: response.content_length(),
+            ..Default::default()
+        })
+    }
+
+    async fn download(&self, url: &str, start: f64, end: f64) -> Result<Vec<u8>> {
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        if let Some(bytes) = response.content_length() {
+            if bytes > self.max_filesize {
+                return Err(ThemesongRejection::TooLarge { bytes }.into());
+            }
+        }
+
+        // The probe can't always tell how big it is, so count as it comes in
+        let mut bytes = vec![];
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() as u64 > self.max_filesize {
+                let bytes = bytes.len() as u64;
+                return Err(ThemesongRejection::TooLarge { bytes }.into());
+            }
+        }
+
+        tokio::task::spawn_blocking(move || clip(&bytes, start, end)).await?
+    }
+}
+
+/// Serves audio files from memory instead of the internet
+#[derive(Debug, Default)]
+pub struct FakeSource {
+    fixtures: HashMap<String, Vec<u8>>,
+    infos: HashMap<String, SourceInfo>,
+    failures: Mutex<HashMap<String, usize>>,
+    downloads: Mutex<Vec<String>>,
+}
+
+impl FakeSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer downloads of `url` with `audio`, clipped to what was asked for
+    pub fn with_fixture(mut self, url: &str, audio: &[u8]) -> Self {
+        self.fixtures.insert(url.to_string(), audio.to_vec());
+        self
+    }
+
+    /// Describe `url` as `info` when probed, instead of going by its fixture
+    pub fn with_info(mut self, url: &str, info: SourceInfo) -> Self {
+        self.infos.insert(url.to_string(), info);
+        self
+    }
+
+    /// Fail the next `times` downloads of `url` like the connection dropped
+    pub fn with_failures(self, url: &str, times: usize) -> Self {
+        self.failures.lock().unwrap().insert(url.to_string(), times);
+        self
+    }
+
+    /// Every url that was downloaded, in order
+    pub fn downloads(&self) -> Vec<String> {
+        self.downloads.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ThemesongSource for FakeSource {
+    async fn probe(&self, url: &str) -> Result<SourceInfo> {
+        if let Some(info) = self.infos.get(url) {
+            return Ok(info.clone());
+        }
+
+        let audio = self
+            .fixtures
+            .get(url)
+            .ok_or_else(|| ThemesongRejection::Unavailable("Video unavailable".to_string()))?;
+        let decoded = loudness::decode(audio)?;
+
+        Ok(SourceInfo {
+            title: Some(url.to_string()),
+            duration: Some(
+                decoded.samples.len() as f64 / decoded.channels as f64 / decoded.sample_rate as f64,
+            ),
+            filesize: Some(audio.len() as u64),
+            ..Default::default()
+        })
+    }
+
+    async fn download(&self, url: &str, start: f64, end: f64) -> Result<Vec<u8>> {
+        self.downloads.lock().unwrap().push(url.to_string());
+
+        if let Some(failures) = self.failures.lock().unwrap().get_mut(url) {
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "connection reset",
+                )
+                .into());
+            }
+        }
+
+        let audio = self
+            .fixtures
+            .get(url)
+            .ok_or_else(|| anyhow::anyhow!("no fixture for {}", url))?;
+        clip(audio, start, end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TONE: &[u8] = include_bytes!("fixtures/tone.wav");
+
+    #[tokio::test]
+    async fn fake_clips_fixtures() -> Result<()> {
+        let url = "https://www.youtube.com/watch?v=SkypZuY6ZvA";
+        let source = FakeSource::new().with_fixture(url, TONE);
+
+        let audio = loudness::decode(&source.download(url, 1., 2.).await?)?;
+        assert_eq!(audio.samples.len(), 8000);
+
+        assert!(source
+            .download("https://youtu.be/nope", 0., 1.)
+            .await
+            .is_err());
+        assert_eq!(source.downloads(), vec![url, "https://youtu.be/nope"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fake_probes_fixtures() -> Result<()> {
+        let url = "https://www.youtube.com/watch?v=SkypZuY6ZvA";
+        let source = FakeSource::new().with_fixture(url, TONE);
+
+        let info = source.probe(url).await?;
+        assert_eq!(info.duration, Some(3.));
+        assert_eq!(info.filesize, Some(TONE.len() as u64));
+
+        let err = source.probe("https://youtu.be/nope").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ThemesongRejection>(),
+            Some(ThemesongRejection::Unavailable(_))
+        ));
+
+        Ok(())
+    }
+
+    /// Serve `body` in chunks, without a Content-Length
+    fn serve(body: &[u8]) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/song.wav", listener.local_addr().unwrap());
+
+        let chunks = body.chunks(1024).map(<[u8]>::to_vec).collect::<Vec<_>>();
+        let app = axum::Router::new().route(
+            "/song.wav",
+            axum::routing::get(move || {
+                let chunks = chunks.clone().into_iter().map(Ok::<_, std::io::Error>);
+                async move { axum::body::StreamBody::new(futures::stream::iter(chunks)) }
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        url
+    }
+
+    #[tokio::test]
+    async fn direct_downloads_stop_at_the_size_limit() -> Result<()> {
+        let url = serve(TONE);
+
+        let source = DirectSource::new();
+        let audio = loudness::decode(&source.download(&url, 1., 2.).await?)?;
+        assert_eq!(audio.samples.len(), 8000);
+
+        let source = DirectSource {
+            max_filesize: 4096,
+            ..DirectSource::new()
+        };
+        let err = source.download(&url, 1., 2.).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ThemesongRejection>(),
+            Some(ThemesongRejection::TooLarge { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn clips_past_the_end_to_the_end() -> Result<()> {
+        let audio = loudness::decode(&clip(TONE, 2., 10.)?)?;
+        assert_eq!(audio.samples.len(), 8000);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! `!set github <login>` and `!set github verify`
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_db::GithubLinkResult;
+use subd_gh::{GithubApi, GithubError};
+use subd_types::UserID;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetGithubReply {
+    pub message: String,
+    /// The link was just verified, so their roles should be refreshed
+    pub verified: bool,
+}
+
+impl SetGithubReply {
+    fn say(message: String) -> Self {
+        Self {
+            message,
+            verified: false,
+        }
+    }
+}
+
+fn github_error_reply(sender: &str, err: &GithubError) -> String {
+    match err {
+        GithubError::NotFound(login) => {
+            format!("@{}: couldn't find github user {}", sender, login)
+        }
+        GithubError::RateLimited { .. } => format!(
+            "@{}: GitHub is rate limiting us, try again in a bit",
+            sender
+        ),
+        GithubError::Unauthorized => format!(
+            "@{}: can't talk to GitHub right now, let the streamer know",
+            sender
+        ),
+        GithubError::Network(_) | GithubError::Response(_) => {
+            format!("@{}: couldn't reach GitHub, try again in a bit", sender)
+        }
+    }
+}
+
+/// Turn GitHub failures into something to say in chat, and pass everything else along
+fn reply_or_github_error<T>(
+    sender: &str,
+    result: Result<T>,
+    reply: impl FnOnce(T) -> SetGithubReply,
+) -> Result<SetGithubReply> {
+    match result {
+        Ok(value) => Ok(reply(value)),
+        Err(err) => match err.downcast_ref::<GithubError>() {
+            Some(github_err) => Ok(SetGithubReply::say(github_error_reply(sender, github_err))),
+            None => Err(err),
+        },
+    }
+}
+
+/// Handle `!set github <arg>`, where `arg` is a github login or `verify`
+pub async fn set_github(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+    user_id: &UserID,
+    sender: &str,
+    arg: &str,
+) -> Result<SetGithubReply> {
+    if arg == "verify" {
+        let result = subd_db::verify_github_link(conn, github, user_id).await;
+        return reply_or_github_error(sender, result, |result| match result {
+            GithubLinkResult::Verified(github_user) => SetGithubReply {
+                message: format!(
+                    "Succesfully verified: twitch {} -> github {}",
+                    sender, github_user.login
+                ),
+                verified: true,
+            },
+            GithubLinkResult::CodeNotFound { login, code } => SetGithubReply::say(format!(
+                "@{}: couldn't find {} in the bio or public gists of github {}",
+                sender, code, login
+            )),
+            GithubLinkResult::Expired => SetGithubReply::say(format!(
+                "@{}: that code expired, run !set github <login> again",
+                sender
+            )),
+            GithubLinkResult::NoRequest => {
+                SetGithubReply::say(format!("@{}: run !set github <login> first", sender))
+            }
+        });
+    }
+
+    let result = subd_db::request_github_link(conn, github, user_id, arg).await;
+    reply_or_github_error(sender, result, |code| {
+        SetGithubReply::say(format!(
+            "@{}: to prove github {} is yours, put {} in your GitHub bio or a public gist, then run: !set github verify",
+            sender, arg, code
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use subd_gh::FakeGithub;
+    use subd_types::{GithubSponsorship, UserRoles};
+
+    use super::*;
+    use crate::test_utils::{chatter, test_conn};
+    use crate::users::roles_with_github;
+
+    fn code_from(reply: &SetGithubReply) -> String {
+        reply
+            .message
+            .split_whitespace()
+            .find(|word| word.starts_with("subd-"))
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn links_and_grants_sponsorship() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let user_id = chatter(&mut conn, "1", "jesseleite").await;
+
+        let github = FakeGithub::new().with_sponsor(
+            "jesseleite",
+            Some(GithubSponsorship {
+                tier_name: "$10 a month".to_string(),
+                monthly_dollars: 10,
+            }),
+        );
+
+        let reply = set_github(&mut conn, &github, &user_id, "jesseleite", "jesseleite").await?;
+        let code = code_from(&reply);
+
+        // Claiming an account isn't enough for perks
+        let roles = roles_with_github(&mut conn, &github, &user_id, &UserRoles::default()).await?;
+        assert!(!roles.is_github_sponsor);
+
+        let github = github.with_verification_source("jesseleite", &code);
+        let reply = set_github(&mut conn, &github, &user_id, "jesseleite", "verify").await?;
+        assert!(reply.verified);
+
+        let roles = roles_with_github(&mut conn, &github, &user_id, &UserRoles::default()).await?;
+        assert!(roles.is_github_sponsor);
+        assert_eq!(roles.github_sponsorship.unwrap().monthly_dollars, 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn github_failures_are_replies() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let user_id = chatter(&mut conn, "1", "nyxkrage").await;
+        let github = FakeGithub::new();
+
+        let reply = set_github(&mut conn, &github, &user_id, "nyxkrage", "nope").await?;
+        assert_eq!(reply.message, "@nyxkrage: couldn't find github user nope");
+
+        github.fail_with(GithubError::Network("timed out".to_string()));
+        let reply = set_github(&mut conn, &github, &user_id, "nyxkrage", "nyxkrage").await?;
+        assert!(reply.message.contains("couldn't reach GitHub"));
+        assert!(!reply.verified);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keeps_known_sponsorship_while_github_is_down() -> Result<()> {
+        let mut conn = test_conn().await?;
+        let user_id = chatter(&mut conn, "1", "jesseleite").await;
+
+        let github = FakeGithub::new().with_sponsor("jesseleite", None);
+        let reply = set_github(&mut conn, &github, &user_id, "jesseleite", "jesseleite").await?;
+        let github = github.with_verification_source("jesseleite", &code_from(&reply));
+        set_github(&mut conn, &github, &user_id, "jesseleite", "verify").await?;
+
+        let roles = roles_with_github(&mut conn, &github, &user_id, &UserRoles::default()).await?;
+        subd_db::set_user_roles(&mut conn, &user_id, roles).await?;
+        sqlx::query!("DELETE FROM github_sponsor_cache")
+            .execute(&mut conn)
+            .await?;
+
+        github.fail_with(GithubError::RateLimited { reset_at: None });
+        let moderator = UserRoles {
+            is_twitch_mod: true,
+            ..Default::default()
+        };
+        let roles = roles_with_github(&mut conn, &github, &user_id, &moderator).await?;
+        assert!(roles.is_twitch_mod);
+        assert!(roles.is_github_sponsor);
+
+        Ok(())
+    }
+}
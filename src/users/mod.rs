@@ -1,10 +1,16 @@
+pub mod github;
+pub mod sponsors;
+pub mod sync;
+
 use anyhow::Result;
 use sqlx::SqliteConnection;
-use subd_types::{UserID, UserRoles};
+use subd_gh::{GithubApi, GithubError};
+use subd_types::{GithubSponsorStatus, SubTier, UserID, UserRoles};
 use twitch_irc::message::PrivmsgMessage;
 
 pub async fn update_user_roles_once_per_day(
     conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
     user_id: &UserID,
     msg: &PrivmsgMessage,
 ) -> Result<()> {
@@ -15,6 +21,7 @@ pub async fn update_user_roles_once_per_day(
         && user_roles.is_twitch_vip == twitch_roles.is_twitch_vip
         && user_roles.is_twitch_founder == twitch_roles.is_twitch_founder
         && user_roles.is_twitch_sub == twitch_roles.is_twitch_sub
+        && user_roles.twitch_sub_tier == twitch_roles.twitch_sub_tier
     {
         let record = sqlx::query!(
         "select user_id from USER_ROLES where user_id = ?1 AND date(verified_date) = date(CURRENT_TIMESTAMP)",
@@ -27,51 +34,103 @@ pub async fn update_user_roles_once_per_day(
         }
     }
 
-    update_user_roles(conn, user_id, msg).await?;
+    update_user_roles(conn, github, user_id, msg).await?;
 
     Ok(())
 }
 
+/// Subscriber badge versions are the months shown on the badge, offset by
+/// 2000 or 3000 for tier 2 and tier 3 badges. Prime looks the same as tier 1.
+fn sub_tier_from_badge_version(version: &str) -> Option<SubTier> {
+    let version = version.parse::<i64>().ok()?;
+    Some(match version {
+        v if v >= 3000 => SubTier::Tier3,
+        v if v >= 2000 => SubTier::Tier2,
+        _ => SubTier::Tier1,
+    })
+}
+
 fn get_twitch_roles_from_msg(msg: &PrivmsgMessage) -> UserRoles {
     let is_twitch_mod = msg.badges.iter().any(|b| b.name == "moderator");
     let is_twitch_vip = msg.badges.iter().any(|b| b.name == "vip");
     let is_twitch_founder = msg.badges.iter().any(|b| b.name == "founder");
     let is_twitch_sub = msg.badges.iter().any(|b| b.name == "subscriber");
 
+    let twitch_sub_tier = msg
+        .badges
+        .iter()
+        .find(|b| b.name == "subscriber")
+        .and_then(|b| sub_tier_from_badge_version(&b.version));
+
+    // badge-info has the exact number of months, founders included
+    let twitch_sub_months = msg
+        .badge_info
+        .iter()
+        .find(|b| b.name == "subscriber" || b.name == "founder")
+        .and_then(|b| b.version.parse().ok());
+
     UserRoles {
         is_twitch_mod,
         is_twitch_vip,
         is_twitch_founder,
         is_twitch_sub,
         is_github_sponsor: false,
+        twitch_sub_tier,
+        twitch_sub_months,
+        github_sponsorship: None,
     }
 }
 
-async fn get_user_role_from_user_id_and_msg(
+/// Twitch roles from chat, plus the sponsorship of their verified github link.
+/// When GitHub can't tell us right now, we keep the sponsorship we already knew about.
+pub async fn roles_with_github(
     conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
     user_id: &UserID,
-    msg: &PrivmsgMessage,
+    twitch_roles: &UserRoles,
 ) -> Result<UserRoles> {
-    let github_user = subd_db::get_github_info_for_user(&mut *conn, user_id).await?;
-    let is_github_sponsor = if let Some(github_user) = github_user {
-        subd_gh::is_user_sponsoring(&github_user.login).await?
-    } else {
-        false
+    // Anyone can claim a github login, so only verified links count
+    let link = match subd_db::get_github_link_for_user(&mut *conn, user_id).await? {
+        Some(link) if link.verified => link,
+        _ => {
+            return Ok(sponsors::apply_sponsor_status(
+                twitch_roles,
+                &GithubSponsorStatus::default(),
+            ))
+        }
     };
 
-    let twitch_roles = get_twitch_roles_from_msg(msg);
-    Ok(UserRoles {
-        is_github_sponsor,
-        ..twitch_roles
-    })
+    let status = match sponsors::sponsor_status(conn, github, &link.user.login).await {
+        Ok(status) => status,
+        Err(err) => match err.downcast_ref::<GithubError>() {
+            // Deleted or renamed accounts can't be sponsoring
+            Some(GithubError::NotFound(_)) => GithubSponsorStatus::default(),
+            Some(github_err) => {
+                println!(
+                    "  Couldn't check sponsorship of github {}: {}",
+                    link.user.login, github_err
+                );
+                let previous = subd_db::get_user_roles(conn, user_id).await?;
+                GithubSponsorStatus {
+                    is_sponsoring: previous.is_github_sponsor,
+                    sponsorship: previous.github_sponsorship,
+                }
+            }
+            None => return Err(err),
+        },
+    };
+
+    Ok(sponsors::apply_sponsor_status(twitch_roles, &status))
 }
 
-async fn update_user_roles(
+pub async fn update_user_roles(
     conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
     user_id: &UserID,
     msg: &PrivmsgMessage,
 ) -> Result<()> {
-    let user_roles = get_user_role_from_user_id_and_msg(conn, user_id, msg).await?;
+    let twitch_roles = get_twitch_roles_from_msg(msg);
+    let user_roles = roles_with_github(conn, github, user_id, &twitch_roles).await?;
 
     println!(
         "  Updating User Roles: {} -> {:?}",
@@ -81,3 +140,16 @@ async fn update_user_roles(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_tier_from_badge_version() {
+        assert_eq!(sub_tier_from_badge_version("12"), Some(SubTier::Tier1));
+        assert_eq!(sub_tier_from_badge_version("2003"), Some(SubTier::Tier2));
+        assert_eq!(sub_tier_from_badge_version("3024"), Some(SubTier::Tier3));
+        assert_eq!(sub_tier_from_badge_version("nope"), None);
+    }
+}
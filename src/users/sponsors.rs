@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_gh::GithubApi;
+use subd_types::{
+    GithubSponsorStatus, GithubSponsorshipAction, GithubSponsorshipEvent, UserRoles,
+    UserRolesChange,
+};
+
+/// Roles after a sponsorship webhook. Pending changes only count once they happen.
+pub fn apply_sponsorship(roles: &UserRoles, sponsorship: &GithubSponsorshipEvent) -> UserRoles {
+    match sponsorship.action {
+        GithubSponsorshipAction::Created
+        | GithubSponsorshipAction::TierChanged
+        | GithubSponsorshipAction::Edited => UserRoles {
+            is_github_sponsor: true,
+            github_sponsorship: Some(sponsorship.tier.clone()),
+            ..roles.clone()
+        },
+        GithubSponsorshipAction::Cancelled => UserRoles {
+            is_github_sponsor: false,
+            github_sponsorship: None,
+            ..roles.clone()
+        },
+        GithubSponsorshipAction::PendingCancellation
+        | GithubSponsorshipAction::PendingTierChange => roles.clone(),
+    }
+}
+
+/// Roles with the github sponsorship replaced by what GitHub told us
+pub fn apply_sponsor_status(roles: &UserRoles, status: &GithubSponsorStatus) -> UserRoles {
+    UserRoles {
+        is_github_sponsor: status.is_sponsoring,
+        github_sponsorship: status.sponsorship.clone(),
+        ..roles.clone()
+    }
+}
+
+/// Sponsor status for `github_login`, only asking GitHub when the cached one is stale
+pub async fn sponsor_status(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+    github_login: &str,
+) -> Result<GithubSponsorStatus> {
+    let ttl = subd_db::sponsor_cache_ttl();
+    if let Some(status) = subd_db::get_cached_sponsor_status(conn, github_login, ttl).await? {
+        return Ok(status);
+    }
+
+    let status = github.get_sponsor_status(github_login).await?;
+    subd_db::set_cached_sponsor_status(conn, github_login, &status).await?;
+    Ok(status)
+}
+
+/// Re-check every verified github link in batches and return whose roles changed
+pub async fn sync_github_sponsors(
+    conn: &mut SqliteConnection,
+    github: &dyn GithubApi,
+) -> Result<Vec<UserRolesChange>> {
+    let links = subd_db::get_verified_github_links(conn).await?;
+    let logins = links
+        .iter()
+        .map(|link| link.github_login.clone())
+        .collect::<Vec<_>>();
+    let statuses: HashMap<String, GithubSponsorStatus> =
+        github.get_sponsor_statuses(&logins).await?;
+
+    let mut changes = vec![];
+    for link in links {
+        let status = match statuses.get(&link.github_login) {
+            Some(status) => status,
+            None => continue,
+        };
+        subd_db::set_cached_sponsor_status(conn, &link.github_login, status).await?;
+
+        let before = subd_db::get_user_roles(conn, &link.user_id).await?;
+        let after = apply_sponsor_status(&before, status);
+        if before != after {
+            subd_db::set_user_roles(conn, &link.user_id, after.clone()).await?;
+            changes.push(UserRolesChange {
+                user_id: link.user_id,
+                twitch_login: link.twitch_login.unwrap_or(link.github_login),
+                before,
+                after,
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Update the roles of whoever has verified the sponsoring github account.
+/// Sponsors that haven't linked their twitch account are skipped.
+pub async fn update_sponsor_roles(
+    conn: &mut SqliteConnection,
+    sponsorship: &GithubSponsorshipEvent,
+) -> Result<Option<UserRolesChange>> {
+    let (user_id, twitch_login) =
+        match subd_db::get_user_from_github_login(conn, &sponsorship.sponsor_login).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+    let before = subd_db::get_user_roles(conn, &user_id).await?;
+    let after = apply_sponsorship(&before, sponsorship);
+
+    // The webhook is newer than anything we have cached
+    let status = GithubSponsorStatus {
+        is_sponsoring: after.is_github_sponsor,
+        sponsorship: after.github_sponsorship.clone(),
+    };
+    subd_db::set_cached_sponsor_status(conn, &sponsorship.sponsor_login, &status).await?;
+
+    if before == after {
+        return Ok(None);
+    }
+
+    subd_db::set_user_roles(conn, &user_id, after.clone()).await?;
+    Ok(Some(UserRolesChange {
+        user_id,
+        twitch_login,
+        before,
+        after,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use subd_types::GithubSponsorship;
+
+    use super::*;
+
+    fn sponsorship(
+        action: GithubSponsorshipAction,
+        monthly_dollars: i64,
+    ) -> GithubSponsorshipEvent {
+        GithubSponsorshipEvent {
+            action,
+            sponsor_login: "jesseleite".to_string(),
+            tier: GithubSponsorship {
+                tier_name: format!("${} a month", monthly_dollars),
+                monthly_dollars,
+            },
+            previous_tier: None,
+            is_one_time: false,
+            is_private: false,
+            effective_date: None,
+        }
+    }
+
+    #[test]
+    fn created_and_cancelled() {
+        let roles = apply_sponsorship(
+            &UserRoles::default(),
+            &sponsorship(GithubSponsorshipAction::Created, 10),
+        );
+        assert!(roles.is_github_sponsor);
+        assert_eq!(
+            roles.github_sponsorship.as_ref().unwrap().monthly_dollars,
+            10
+        );
+
+        // Still a sponsor until the cancellation goes through
+        let pending = apply_sponsorship(
+            &roles,
+            &sponsorship(GithubSponsorshipAction::PendingCancellation, 10),
+        );
+        assert_eq!(pending, roles);
+
+        let cancelled =
+            apply_sponsorship(&roles, &sponsorship(GithubSponsorshipAction::Cancelled, 10));
+        assert!(!cancelled.is_github_sponsor);
+        assert!(cancelled.github_sponsorship.is_none());
+    }
+
+    #[test]
+    fn tier_changes_keep_twitch_roles() {
+        let roles = UserRoles {
+            is_twitch_mod: true,
+            ..Default::default()
+        };
+
+        let changed = apply_sponsorship(
+            &roles,
+            &sponsorship(GithubSponsorshipAction::TierChanged, 25),
+        );
+        assert!(changed.is_twitch_mod);
+        assert_eq!(changed.github_sponsorship.unwrap().monthly_dollars, 25);
+    }
+
+    #[test]
+    fn custom_amounts_still_count_as_sponsoring() {
+        let status = GithubSponsorStatus {
+            is_sponsoring: true,
+            sponsorship: None,
+        };
+
+        let roles = apply_sponsor_status(&UserRoles::default(), &status);
+        assert!(roles.is_github_sponsor);
+        assert!(roles.github_sponsorship.is_none());
+
+        let stopped = apply_sponsor_status(&roles, &GithubSponsorStatus::default());
+        assert!(!stopped.is_github_sponsor);
+    }
+}
@@ -0,0 +1,216 @@
+//! Keeps twitch roles in the database in sync with Helix, instead of only
+//! learning about them from the badges of people who happen to chat.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use subd_twitch::helix::{ChannelMember, Helix, Subscriber};
+use subd_types::{SubTier, SubscriptionUser, UserRoles, UserRolesChange};
+
+/// Everyone that has a role in the channel right now, according to Helix
+#[derive(Debug, Clone, Default)]
+pub struct ChannelRoles {
+    pub subscribers: Vec<Subscriber>,
+    pub moderators: Vec<ChannelMember>,
+    pub vips: Vec<ChannelMember>,
+}
+
+impl ChannelRoles {
+    pub async fn fetch(helix: &Helix) -> Result<Self> {
+        Ok(Self {
+            subscribers: helix.get_subscribers().await?,
+            moderators: helix.get_moderators().await?,
+            vips: helix.get_vips().await?,
+        })
+    }
+
+    /// Twitch roles for each twitch user id. Founder isn't reported by Helix.
+    pub fn by_twitch_id(&self, broadcaster_id: &str) -> BTreeMap<String, (String, UserRoles)> {
+        let mut roles = BTreeMap::new();
+
+        for sub in &self.subscribers {
+            // The broadcaster is always subscribed to themselves
+            if sub.user_id != broadcaster_id {
+                let sub_roles = roles_for(&mut roles, &sub.user_id, &sub.user_login);
+                sub_roles.is_twitch_sub = true;
+                sub_roles.twitch_sub_tier = Some(SubTier::from_plan(&sub.tier));
+            }
+        }
+        for moderator in &self.moderators {
+            roles_for(&mut roles, &moderator.user_id, &moderator.user_login).is_twitch_mod = true;
+        }
+        for vip in &self.vips {
+            roles_for(&mut roles, &vip.user_id, &vip.user_login).is_twitch_vip = true;
+        }
+
+        roles
+    }
+}
+
+fn roles_for<'a>(
+    roles: &'a mut BTreeMap<String, (String, UserRoles)>,
+    id: &str,
+    login: &str,
+) -> &'a mut UserRoles {
+    &mut roles
+        .entry(id.to_string())
+        .or_insert_with(|| (login.to_string(), UserRoles::default()))
+        .1
+}
+
+fn member_user(id: &str, login: &str, display_name: &str) -> SubscriptionUser {
+    SubscriptionUser {
+        id: id.to_string(),
+        login: login.to_string(),
+        display_name: display_name.to_string(),
+    }
+}
+
+/// Roles after a sync: twitch roles come from Helix, everything else is kept.
+/// Helix doesn't know how many months someone has been subbed, so chat keeps that up to date.
+pub fn merge_roles(previous: &UserRoles, twitch: &UserRoles) -> UserRoles {
+    let twitch_sub_months = if twitch.is_twitch_sub {
+        previous.twitch_sub_months
+    } else {
+        None
+    };
+
+    UserRoles {
+        is_twitch_mod: twitch.is_twitch_mod,
+        is_twitch_vip: twitch.is_twitch_vip,
+        is_twitch_sub: twitch.is_twitch_sub,
+        twitch_sub_tier: twitch.twitch_sub_tier,
+        twitch_sub_months,
+        ..previous.clone()
+    }
+}
+
+/// Write the current channel roles to the database and return whose roles changed
+pub async fn sync_twitch_roles(
+    conn: &mut SqliteConnection,
+    broadcaster_id: &str,
+    channel: &ChannelRoles,
+) -> Result<Vec<UserRolesChange>> {
+    let moderators = channel
+        .moderators
+        .iter()
+        .map(|m| member_user(&m.user_id, &m.user_login, &m.user_name))
+        .collect::<Vec<_>>();
+    subd_db::set_twitch_moderators(conn, broadcaster_id, &moderators).await?;
+
+    for sub in &channel.subscribers {
+        let user = member_user(&sub.user_id, &sub.user_login, &sub.user_name);
+        let tier = SubTier::from_plan(&sub.tier).as_number();
+        subd_db::note_twitch_subscription(conn, &user, tier, sub.is_gift).await?;
+    }
+    for vip in &channel.vips {
+        let user = member_user(&vip.user_id, &vip.user_login, &vip.user_name);
+        subd_db::create_twitch_user_chat(conn, &user.id, &user.login).await?;
+    }
+
+    let mut current = channel.by_twitch_id(broadcaster_id);
+
+    // Anyone we think has a role but Helix no longer lists has lost it
+    for holder in subd_db::get_twitch_role_holders(conn).await? {
+        if holder.twitch_id == broadcaster_id {
+            continue;
+        }
+
+        current
+            .entry(holder.twitch_id)
+            .or_insert_with(|| (holder.login, UserRoles::default()));
+    }
+
+    let mut changes = vec![];
+    for (twitch_id, (login, twitch_roles)) in current {
+        let user_id = subd_db::get_user_from_twitch_user(conn, &twitch_id).await?;
+        let before = subd_db::get_user_roles(conn, &user_id).await?;
+        let after = merge_roles(&before, &twitch_roles);
+
+        if before != after {
+            subd_db::set_user_roles(conn, &user_id, after.clone()).await?;
+            changes.push(UserRolesChange {
+                user_id,
+                twitch_login: login,
+                before,
+                after,
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(id: &str, login: &str) -> ChannelMember {
+        ChannelMember {
+            user_id: id.to_string(),
+            user_login: login.to_string(),
+            user_name: login.to_string(),
+        }
+    }
+
+    fn subscriber(id: &str, login: &str) -> Subscriber {
+        Subscriber {
+            user_id: id.to_string(),
+            user_login: login.to_string(),
+            user_name: login.to_string(),
+            tier: "1000".to_string(),
+            is_gift: false,
+        }
+    }
+
+    #[test]
+    fn combines_roles_per_user() {
+        let channel = ChannelRoles {
+            subscribers: vec![
+                subscriber("114257969", "teej_dv"),
+                subscriber("1", "nyxkrage"),
+            ],
+            moderators: vec![member("1", "nyxkrage")],
+            vips: vec![member("2", "theprimeagen")],
+        };
+
+        let roles = channel.by_twitch_id("114257969");
+        assert!(!roles.contains_key("114257969"));
+
+        let (login, nyx) = &roles["1"];
+        assert_eq!(login, "nyxkrage");
+        assert!(nyx.is_twitch_sub && nyx.is_twitch_mod && !nyx.is_twitch_vip);
+        assert_eq!(nyx.twitch_sub_tier, Some(SubTier::Tier1));
+
+        let (_, prime) = &roles["2"];
+        assert!(prime.is_twitch_vip && !prime.is_twitch_sub);
+    }
+
+    #[test]
+    fn merge_keeps_non_helix_roles() {
+        let previous = UserRoles {
+            is_github_sponsor: true,
+            is_twitch_founder: true,
+            is_twitch_sub: true,
+            twitch_sub_tier: Some(SubTier::Tier2),
+            twitch_sub_months: Some(7),
+            ..Default::default()
+        };
+
+        let merged = merge_roles(&previous, &UserRoles::default());
+        assert!(merged.is_github_sponsor && merged.is_twitch_founder);
+        assert!(!merged.is_twitch_sub);
+        assert_eq!(merged.twitch_sub_tier, None);
+        assert_eq!(merged.twitch_sub_months, None);
+
+        let change = UserRolesChange {
+            user_id: 1,
+            twitch_login: "nyxkrage".to_string(),
+            before: previous,
+            after: merged,
+        };
+        assert_eq!(change.lost(), vec!["subscriber"]);
+        assert!(change.gained().is_empty());
+    }
+}
@@ -0,0 +1,374 @@
+//! Receives GitHub webhook deliveries: GitHub Sponsors and activity on our repos.
+
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Extension, Router};
+use subd_gh::webhook;
+use subd_types::{Event, GithubRepoActivity, GithubRepoEvent, GithubRepoEventType};
+use tokio::sync::broadcast;
+
+use crate::text::shorten_title;
+
+/// Which repository activity gets announced on stream
+#[derive(Debug, Clone, Default)]
+pub struct RepoAnnouncements {
+    /// Lowercase `owner/name`, nothing is announced for other repos
+    repos: HashSet<String>,
+    events: HashSet<GithubRepoEventType>,
+    /// Lowercase logins whose titles we read out. Anyone can open an issue,
+    /// so everyone else's titles stay off stream.
+    trusted: HashSet<String>,
+}
+
+impl RepoAnnouncements {
+    /// The owners of `repos` are always trusted
+    pub fn new(repos: &[&str], events: &[GithubRepoEventType], trusted: &[&str]) -> Self {
+        let owners = repos.iter().filter_map(|repo| repo.split_once('/'));
+        Self {
+            repos: repos.iter().map(|repo| repo.to_lowercase()).collect(),
+            events: events.iter().copied().collect(),
+            trusted: owners
+                .map(|(owner, _)| owner)
+                .chain(trusted.iter().copied())
+                .map(str::to_lowercase)
+                .collect(),
+        }
+    }
+
+    /// Read `$GITHUB_ANNOUNCE_REPOS` (comma separated `owner/name`),
+    /// `$GITHUB_ANNOUNCE_EVENTS` (any of `star,issues,pull_request,release`, defaults to all)
+    /// and `$GITHUB_ANNOUNCE_TRUSTED` (comma separated logins)
+    pub fn from_env() -> Self {
+        let repos = env::var("GITHUB_ANNOUNCE_REPOS").unwrap_or_default();
+        let trusted = env::var("GITHUB_ANNOUNCE_TRUSTED").unwrap_or_default();
+        let events = match env::var("GITHUB_ANNOUNCE_EVENTS") {
+            Ok(events) => events
+                .split(',')
+                .filter(|name| !name.trim().is_empty())
+                .filter_map(|name| {
+                    let event_type = GithubRepoEventType::from_name(name);
+                    if event_type.is_none() {
+                        println!("[webhooks/github] unknown announce event: {}", name);
+                    }
+                    event_type
+                })
+                .collect(),
+            Err(_) => GithubRepoEventType::ALL.to_vec(),
+        };
+
+        let repos = repos
+            .split(',')
+            .map(str::trim)
+            .filter(|repo| !repo.is_empty())
+            .collect::<Vec<_>>();
+        let trusted = trusted
+            .split(',')
+            .map(str::trim)
+            .filter(|login| !login.is_empty())
+            .collect::<Vec<_>>();
+        Self::new(&repos, &events, &trusted)
+    }
+
+    pub fn allows(&self, event: &GithubRepoEvent) -> bool {
+        self.repos.contains(&event.repo.to_lowercase()) && self.events.contains(&event.event_type())
+    }
+
+    /// Make `event` safe to put on stream: titles from untrusted senders are
+    /// dropped, and the rest are put on one line and shortened
+    pub fn screen(&self, mut event: GithubRepoEvent) -> GithubRepoEvent {
+        let is_trusted = self.trusted.contains(&event.sender.to_lowercase());
+        match &mut event.activity {
+            GithubRepoActivity::IssueOpened { title, .. }
+            | GithubRepoActivity::PullRequestMerged { title, .. } => {
+                *title = if is_trusted {
+                    shorten_title(title)
+                } else {
+                    String::new()
+                };
+            }
+            GithubRepoActivity::Released {
+                name: Some(name), ..
+            } => *name = shorten_title(name),
+            _ => {}
+        }
+
+        event
+    }
+}
+
+pub struct GithubWebhookState {
+    secret: String,
+    announcements: RepoAnnouncements,
+    tx: broadcast::Sender<Event>,
+}
+
+pub fn routes(
+    secret: String,
+    announcements: RepoAnnouncements,
+    tx: broadcast::Sender<Event>,
+) -> Router {
+    let state = GithubWebhookState {
+        secret,
+        announcements,
+        tx,
+    };
+
+    Router::new()
+        .route("/webhooks/github", post(handle_delivery))
+        .layer(Extension(Arc::new(state)))
+}
+
+async fn handle_delivery(
+    Extension(state): Extension<Arc<GithubWebhookState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let (signature, event_type) = match (
+        header(webhook::SIGNATURE_HEADER),
+        header(webhook::EVENT_HEADER),
+    ) {
+        (Some(signature), Some(event_type)) => (signature, event_type),
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    if !webhook::verify_signature(state.secret.as_bytes(), &body, signature) {
+        println!(
+            "[webhooks/github] bad signature for delivery: {:?}",
+            header(webhook::DELIVERY_HEADER)
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match event_type {
+        "sponsorship" => match webhook::parse_sponsorship(&body) {
+            Ok(Some(sponsorship)) => {
+                let _ = state.tx.send(Event::GithubSponsorshipEvent(sponsorship));
+                StatusCode::NO_CONTENT.into_response()
+            }
+            Ok(None) => StatusCode::NO_CONTENT.into_response(),
+            Err(err) => {
+                println!("[webhooks/github] could not decode sponsorship: {:?}", err);
+                StatusCode::BAD_REQUEST.into_response()
+            }
+        },
+        "star" | "issues" | "pull_request" | "release" => {
+            match webhook::parse_repo_event(event_type, &body) {
+                Ok(Some(event)) if state.announcements.allows(&event) => {
+                    let event = state.announcements.screen(event);
+                    let _ = state.tx.send(Event::GithubRepoEvent(event));
+                    StatusCode::NO_CONTENT.into_response()
+                }
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => {
+                    println!(
+                        "[webhooks/github] could not decode {}: {:?}",
+                        event_type, err
+                    );
+                    StatusCode::BAD_REQUEST.into_response()
+                }
+            }
+        }
+        // Sent once when the webhook is created
+        "ping" => StatusCode::NO_CONTENT.into_response(),
+        other => {
+            println!("[webhooks/github] ignoring event: {}", other);
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use subd_types::{GithubRepoActivity, GithubSponsorshipAction};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::text::MAX_TITLE_CHARS;
+
+    const SECRET: &str = "this is a very secret secret";
+    const CREATED: &str = include_str!("fixtures/github_sponsorship_created.json");
+    const STARRED: &str = include_str!("fixtures/github_star_created.json");
+
+    fn app(tx: broadcast::Sender<Event>) -> Router {
+        let announcements = RepoAnnouncements::new(
+            &["tjdevries/subd"],
+            &[GithubRepoEventType::Star, GithubRepoEventType::Release],
+            &["nyxkrage"],
+        );
+        routes(SECRET.to_string(), announcements, tx)
+    }
+
+    fn signed_request(event_type: &str, secret: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/webhooks/github")
+            .header(
+                webhook::SIGNATURE_HEADER,
+                webhook::sign(secret.as_bytes(), body.as_bytes()),
+            )
+            .header(webhook::EVENT_HEADER, event_type)
+            .header(
+                webhook::DELIVERY_HEADER,
+                "72d3162e-cc78-11e3-81ab-4c9367dc0958",
+            )
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_publishes_sponsorship() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = app(tx);
+
+        let response = app
+            .oneshot(signed_request("sponsorship", SECRET, CREATED))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        match rx.recv().await.unwrap() {
+            Event::GithubSponsorshipEvent(sponsorship) => {
+                assert_eq!(sponsorship.action, GithubSponsorshipAction::Created);
+                assert_eq!(sponsorship.sponsor_login, "jesseleite");
+                assert_eq!(sponsorship.tier.monthly_dollars, 10);
+            }
+            other => panic!("expected sponsorship, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_signature() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = app(tx);
+
+        let response = app
+            .oneshot(signed_request("sponsorship", "not the secret", CREATED))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_accepts_ping() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = app(tx);
+
+        let response = app
+            .oneshot(signed_request(
+                "ping",
+                SECRET,
+                r#"{"zen": "Keep it logically awesome."}"#,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_announces_configured_repo_activity() {
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let response = app(tx)
+            .oneshot(signed_request("star", SECRET, STARRED))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        match rx.recv().await.unwrap() {
+            Event::GithubRepoEvent(event) => {
+                assert_eq!(event.sender, "nyxkrage");
+                assert_eq!(
+                    event.activity,
+                    GithubRepoActivity::Starred { stargazers: 420 }
+                );
+            }
+            other => panic!("expected repo event, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skips_unconfigured_repo_activity() {
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let other_repo = STARRED.replace("tjdevries/subd", "tjdevries/vlog.nvim");
+        let response = app(tx.clone())
+            .oneshot(signed_request("star", SECRET, &other_repo))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Issues are toggled off
+        let issue = r#"{
+            "action": "opened",
+            "issue": { "number": 1, "title": "hi", "html_url": "https://github.com/tjdevries/subd/issues/1" },
+            "repository": { "full_name": "tjdevries/subd" },
+            "sender": { "login": "nyxkrage" }
+        }"#;
+        let response = app(tx)
+            .oneshot(signed_request("issues", SECRET, issue))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    fn issue(sender: &str, title: &str) -> GithubRepoEvent {
+        GithubRepoEvent {
+            repo: "tjdevries/subd".to_string(),
+            sender: sender.to_string(),
+            activity: GithubRepoActivity::IssueOpened {
+                number: 1,
+                title: title.to_string(),
+                url: "https://github.com/tjdevries/subd/issues/1".to_string(),
+            },
+        }
+    }
+
+    fn title(event: GithubRepoEvent) -> String {
+        match event.activity {
+            GithubRepoActivity::IssueOpened { title, .. } => title,
+            other => panic!("expected issue, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_screens_titles() {
+        let announcements = RepoAnnouncements::new(
+            &["tjdevries/subd"],
+            &GithubRepoEventType::ALL,
+            &["NyxKrage"],
+        );
+
+        // Only trusted logins, and the repo owner, get their titles read out
+        let event = announcements.screen(issue("randomviewer", "something rude"));
+        assert_eq!(title(event.clone()), "");
+        assert_eq!(event.announcement(), "randomviewer opened tjdevries/subd#1");
+        assert_eq!(
+            title(announcements.screen(issue("TJDevries", "Fix themesongs"))),
+            "Fix themesongs"
+        );
+
+        let long = announcements.screen(issue("nyxkrage", &"a".repeat(200)));
+        let long = title(long);
+        assert_eq!(long.chars().count(), MAX_TITLE_CHARS);
+        assert!(long.ends_with('…'));
+
+        let multiline = announcements.screen(issue("nyxkrage", "first line\n\nsecond   line"));
+        assert_eq!(title(multiline), "first line second line");
+    }
+}
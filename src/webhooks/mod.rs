@@ -0,0 +1,28 @@
+use std::env;
+
+use axum::Router;
+use subd_types::Event;
+use tokio::sync::broadcast;
+
+pub mod github;
+pub mod twitch;
+
+/// All of the webhook routes that are configured for this deployment
+pub fn router(tx: broadcast::Sender<Event>) -> Router {
+    let mut router = Router::new();
+
+    match env::var("TWITCH_EVENTSUB_SECRET") {
+        Ok(secret) => router = router.merge(twitch::routes(secret, tx.clone())),
+        Err(_) => println!("[webhooks] $TWITCH_EVENTSUB_SECRET not set, ignoring twitch callbacks"),
+    }
+
+    match env::var("GITHUB_WEBHOOK_SECRET") {
+        Ok(secret) => {
+            let announcements = github::RepoAnnouncements::from_env();
+            router = router.merge(github::routes(secret, announcements, tx));
+        }
+        Err(_) => println!("[webhooks] $GITHUB_WEBHOOK_SECRET not set, ignoring github deliveries"),
+    }
+
+    router
+}
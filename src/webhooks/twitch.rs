@@ -0,0 +1,290 @@
+//! Receives Twitch EventSub callbacks, for deployments that have a public endpoint.
+
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Extension, Router};
+use chrono::Utc;
+use subd_twitch::eventsub::webhook::{self, WebhookMessage};
+use subd_twitch::eventsub::SeenMessages;
+use subd_types::Event;
+use tokio::sync::broadcast;
+
+pub struct TwitchWebhookState {
+    secret: String,
+    tx: broadcast::Sender<Event>,
+    seen: Mutex<SeenMessages>,
+}
+
+pub fn routes(secret: String, tx: broadcast::Sender<Event>) -> Router {
+    let state = TwitchWebhookState {
+        secret,
+        tx,
+        seen: Mutex::new(SeenMessages::new()),
+    };
+
+    Router::new()
+        .route("/webhooks/twitch", post(handle_callback))
+        .layer(Extension(Arc::new(state)))
+}
+
+async fn handle_callback(
+    Extension(state): Extension<Arc<TwitchWebhookState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let (message_id, timestamp, signature, message_type) = match (
+        header(webhook::MESSAGE_ID_HEADER),
+        header(webhook::MESSAGE_TIMESTAMP_HEADER),
+        header(webhook::MESSAGE_SIGNATURE_HEADER),
+        header(webhook::MESSAGE_TYPE_HEADER),
+    ) {
+        (Some(id), Some(timestamp), Some(signature), Some(message_type)) => {
+            (id, timestamp, signature, message_type)
+        }
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    if !webhook::verify_signature(
+        state.secret.as_bytes(),
+        message_id,
+        timestamp,
+        &body,
+        signature,
+    ) {
+        println!(
+            "[webhooks/twitch] bad signature for message: {}",
+            message_id
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if !webhook::is_fresh(timestamp, Utc::now()) {
+        println!(
+            "[webhooks/twitch] stale message: {} @ {}",
+            message_id, timestamp
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let message = webhook::parse_message(message_type, &body);
+
+    // Twitch retries until it gets a 2xx, so we may see the same message more than once.
+    // Only remember the ones we understood, so a retry of a bad one gets another go.
+    if message.is_ok() && !state.seen.lock().unwrap().insert(message_id) {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    match message {
+        Ok(WebhookMessage::Challenge(challenge)) => {
+            (StatusCode::OK, [(CONTENT_TYPE, "text/plain")], challenge).into_response()
+        }
+        Ok(WebhookMessage::Notification(Some(event))) => {
+            let is_subscription = matches!(event, Event::TwitchSubscription(_));
+            let _ = state.tx.send(event);
+            if is_subscription {
+                let _ = state.tx.send(Event::RequestTwitchSubCount);
+            }
+
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(WebhookMessage::Notification(None)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(WebhookMessage::Revocation(subscription)) => {
+            println!(
+                "[webhooks/twitch] subscription revoked: {} ({})",
+                subscription.kind, subscription.status
+            );
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(WebhookMessage::Unknown(message_type)) => {
+            println!("[webhooks/twitch] unknown message type: {}", message_type);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(err) => {
+            println!("[webhooks/twitch] could not decode message: {:?}", err);
+            StatusCode::BAD_REQUEST.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use chrono::Duration;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const SECRET: &str = "this is a very secret secret";
+    const FOLLOW: &str = include_str!("fixtures/twitch_follow.json");
+    const SUBSCRIBE: &str = include_str!("fixtures/twitch_subscribe.json");
+    const CHALLENGE: &str = include_str!("fixtures/twitch_challenge.json");
+
+    fn signed_request(
+        message_type: &str,
+        message_id: &str,
+        timestamp: &str,
+        body: &str,
+    ) -> Request<Body> {
+        let signature = webhook::sign(SECRET.as_bytes(), message_id, timestamp, body.as_bytes());
+
+        Request::builder()
+            .method("POST")
+            .uri("/webhooks/twitch")
+            .header(webhook::MESSAGE_ID_HEADER, message_id)
+            .header(webhook::MESSAGE_TIMESTAMP_HEADER, timestamp)
+            .header(webhook::MESSAGE_SIGNATURE_HEADER, signature)
+            .header(webhook::MESSAGE_TYPE_HEADER, message_type)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn now() -> String {
+        Utc::now().to_rfc3339()
+    }
+
+    #[tokio::test]
+    async fn test_publishes_signed_notification() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        let response = app
+            .oneshot(signed_request("notification", "msg-1", &now(), FOLLOW))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        match rx.recv().await.unwrap() {
+            Event::TwitchFollow(follow) => assert_eq!(follow.user_name, "NyxKrage"),
+            other => panic!("expected follow, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscription_requests_new_sub_count() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        app.oneshot(signed_request("notification", "msg-1", &now(), SUBSCRIBE))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::TwitchSubscription(_)
+        ));
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::RequestTwitchSubCount
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_answers_challenge() {
+        let (tx, _rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        let response = app
+            .oneshot(signed_request(
+                "webhook_callback_verification",
+                "msg-1",
+                &now(),
+                CHALLENGE,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"pogchamp-kappa-360noscope-vohiyo");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_signature() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = routes("not the secret".to_string(), tx);
+
+        let response = app
+            .oneshot(signed_request("notification", "msg-1", &now(), FOLLOW))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_stale_timestamp() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        let stale = (Utc::now() - Duration::minutes(15)).to_rfc3339();
+        let response = app
+            .oneshot(signed_request("notification", "msg-1", &stale, FOLLOW))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dedupes_by_message_id() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(signed_request("notification", "msg-1", &now(), FOLLOW))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+
+        assert!(matches!(rx.recv().await.unwrap(), Event::TwitchFollow(_)));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retries_messages_that_failed_to_decode() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        let response = app
+            .clone()
+            .oneshot(signed_request("notification", "msg-1", &now(), "{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .oneshot(signed_request("notification", "msg-1", &now(), FOLLOW))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(matches!(rx.recv().await.unwrap(), Event::TwitchFollow(_)));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_missing_headers() {
+        let (tx, _rx) = broadcast::channel(16);
+        let app = routes(SECRET.to_string(), tx);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/webhooks/twitch")
+            .body(Body::from(FOLLOW))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}